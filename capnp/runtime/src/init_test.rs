@@ -4,7 +4,8 @@ mod test {
   use std::error;
 
   use crate::samples::rast_capnp::{
-    AppendEntriesReqShared, EntryShared, Index, MessageShared, NodeID, PayloadShared, ReadID, Term,
+    AppendEntriesReqShared, ClusterID, EntryKind, EntryShared, Index, MessageShared, NodeID,
+    PayloadShared, ReadID, Term,
   };
   use crate::samples::test_capnp::{TestAllTypesShared, TestEnum};
 
@@ -61,9 +62,12 @@ mod test {
 
   #[test]
   fn init_rast() -> Result<(), Box<dyn error::Error>> {
-    let entry = EntryShared::new(Term(9), Index(10), &[11, 12]);
-    assert_eq!(format!("{:?}", entry.capnp_as_ref()), "(term = 9, index = 10, payload = [0b, 0c])");
-    let entries = vec![entry, EntryShared::new(Term(13), Index(14), &[15])];
+    let entry = EntryShared::new(Term(9), Index(10), &[11, 12], EntryKind::User);
+    assert_eq!(
+      format!("{:?}", entry.capnp_as_ref()),
+      "(term = 9, index = 10, payload = [0b, 0c], kind = user)"
+    );
+    let entries = vec![entry, EntryShared::new(Term(13), Index(14), &[], EntryKind::Noop)];
     let req = AppendEntriesReqShared::new(
       Term(3),
       NodeID(4),
@@ -73,8 +77,14 @@ mod test {
       ReadID(8),
       entries.as_slice(),
     );
-    let message = MessageShared::new(NodeID(1), NodeID(2), PayloadShared::AppendEntriesReq(req));
-    let expected = "(src = 1, dest = 2, payload = (appendEntriesReq = (term = 3, leaderId = 4, prevLogIndex = 5, prevLogTerm = 6, leaderCommit = 7, readId = 8, entries = [(term = 9, index = 10, payload = [0b, 0c]), (term = 13, index = 14, payload = [0f])])))";
+    let message = MessageShared::new(
+      NodeID(1),
+      NodeID(2),
+      PayloadShared::AppendEntriesReq(req),
+      ClusterID(15),
+      16,
+    );
+    let expected = "(src = 1, dest = 2, payload = (appendEntriesReq = (term = 3, leaderId = 4, prevLogIndex = 5, prevLogTerm = 6, leaderCommit = 7, readId = 8, entries = [(term = 9, index = 10, payload = [0b, 0c], kind = user), (term = 13, index = 14, kind = noop)])), clusterId = 15, version = 16)";
     assert_eq!(format!("{:?}", message.capnp_as_ref()), expected);
     Ok(())
   }
@@ -15,6 +15,9 @@ pub mod rast_capnp {
   #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
   pub struct ReadID(pub u64);
 
+  #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+  pub struct ClusterID(pub u64);
+
   include!("rast_capnp.rs");
 }
 pub mod carsales_capnp;
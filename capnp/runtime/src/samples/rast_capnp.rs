@@ -15,15 +15,21 @@ impl EntryMeta {
     name: "payload",
     offset: NumElements(0),
   };
+  const KIND_META: &'static EnumFieldMeta = &EnumFieldMeta {
+    name: "kind",
+    offset: NumElements(8),
+    meta: &EntryKind::META,
+  };
 
   const META: &'static StructMeta = &StructMeta {
     name: "Entry",
-    data_size: NumWords(2),
+    data_size: NumWords(3),
     pointer_size: NumWords(1),
     fields: || &[
       FieldMeta::U64(EntryMeta::TERM_META),
       FieldMeta::U64(EntryMeta::INDEX_META),
       FieldMeta::Data(EntryMeta::PAYLOAD_META),
+      FieldMeta::Enum(EntryMeta::KIND_META),
     ],
   };
 }
@@ -46,6 +52,9 @@ pub trait Entry {
 
   /// The opaque user payload of the entry.
   fn payload<'a>(&'a self) -> Result<&'a [u8], Error>;
+
+  /// Whether the entry holds a user payload or was written by Raft itself.
+  fn kind<'a>(&'a self) -> Result<EntryKind, UnknownDiscriminant>;
 }
 
 /// An entry in the Raft log.
@@ -65,6 +74,9 @@ impl<'a> EntryRef<'a> {
   /// The opaque user payload of the entry.
   pub fn payload(&self) -> Result<&'a [u8], Error> {EntryMeta::PAYLOAD_META.get(&self.data) }
 
+  /// Whether the entry holds a user payload or was written by Raft itself.
+  pub fn kind(&self) -> Result<EntryKind, UnknownDiscriminant> {EntryMeta::KIND_META.get(&self.data) }
+
   pub fn capnp_to_owned(&self) -> EntryShared {
     EntryShared { data: self.data.capnp_to_owned() }
   }
@@ -80,6 +92,9 @@ impl Entry for EntryRef<'_> {
   fn payload<'a>(&'a self) -> Result<&'a [u8], Error> {
     self.payload()
  }
+  fn kind<'a>(&'a self) -> Result<EntryKind, UnknownDiscriminant> {
+    self.kind()
+ }
 }
 
 impl<'a> TypedStructRef<'a> for EntryRef<'a> {
@@ -129,11 +144,13 @@ impl EntryShared {
     term: Term,
     index: Index,
     payload: &[u8],
+    kind: EntryKind,
   ) -> EntryShared {
     let mut data = UntypedStructOwned::new_with_root_struct(EntryMeta::META.data_size, EntryMeta::META.pointer_size);
     EntryMeta::TERM_META.set(&mut data, term.0);
     EntryMeta::INDEX_META.set(&mut data, index.0);
     EntryMeta::PAYLOAD_META.set(&mut data, payload);
+    EntryMeta::KIND_META.set(&mut data, kind);
     EntryShared { data: data.into_shared() }
   }
 
@@ -160,6 +177,45 @@ impl<'a> CapnpAsRef<'a, EntryRef<'a>> for EntryShared {
   }
 }
 
+/// The kind of an entry in the Raft log.
+#[derive(Clone, Copy)]
+pub enum EntryKind {
+  User = 0,
+  Noop = 1,
+}
+
+impl EntryKind {
+  const META: &'static EnumMeta = &EnumMeta {
+    name: "EntryKind",
+    enumerants: &[
+      EnumerantMeta{
+        name: "user",
+        discriminant: Discriminant(0),
+      },
+      EnumerantMeta{
+        name: "noop",
+        discriminant: Discriminant(1),
+      },
+    ],
+  };
+}
+
+impl TypedEnum for EntryKind {
+  fn meta() -> &'static EnumMeta {
+    &EntryKind::META
+  }
+  fn from_discriminant(discriminant: Discriminant) -> Result<Self, UnknownDiscriminant> {
+   match discriminant {
+      Discriminant(0) => Ok(EntryKind::User),
+      Discriminant(1) => Ok(EntryKind::Noop),
+      d => Err(UnknownDiscriminant(d, EntryKind::META.name)),
+    }
+  }
+  fn to_discriminant(&self) -> Discriminant {
+    Discriminant(*self as u16)
+  }
+}
+
 pub struct MessageMeta;
 
 impl MessageMeta {
@@ -176,15 +232,25 @@ impl MessageMeta {
     offset: NumElements(8),
     meta: &Payload::META,
   };
+  const CLUSTER_ID_META: &'static U64FieldMeta = &U64FieldMeta {
+    name: "clusterId",
+    offset: NumElements(3),
+  };
+  const VERSION_META: &'static U32FieldMeta = &U32FieldMeta {
+    name: "version",
+    offset: NumElements(5),
+  };
 
   const META: &'static StructMeta = &StructMeta {
     name: "Message",
-    data_size: NumWords(3),
+    data_size: NumWords(4),
     pointer_size: NumWords(1),
     fields: || &[
       FieldMeta::U64(MessageMeta::SRC_META),
       FieldMeta::U64(MessageMeta::DEST_META),
       FieldMeta::Union(MessageMeta::PAYLOAD_META),
+      FieldMeta::U64(MessageMeta::CLUSTER_ID_META),
+      FieldMeta::U32(MessageMeta::VERSION_META),
     ],
   };
 }
@@ -206,6 +272,12 @@ pub trait Message {
   fn dest<'a>(&'a self) -> NodeID;
 
   fn payload<'a>(&'a self) -> Result<Result<Payload<'a>, UnknownDiscriminant>,Error>;
+
+  /// The cluster of the node sending this rpc.
+  fn cluster_id<'a>(&'a self) -> ClusterID;
+
+  /// The protocol version of the node sending this rpc.
+  fn version<'a>(&'a self) -> u32;
 }
 
 /// An rpc message.
@@ -224,6 +296,12 @@ impl<'a> MessageRef<'a> {
 
   pub fn payload(&self) -> Result<Result<Payload<'a>, UnknownDiscriminant>,Error> {MessageMeta::PAYLOAD_META.get(&self.data) }
 
+  /// The cluster of the node sending this rpc.
+  pub fn cluster_id(&self) -> ClusterID {ClusterID(MessageMeta::CLUSTER_ID_META.get(&self.data)) }
+
+  /// The protocol version of the node sending this rpc.
+  pub fn version(&self) -> u32 {MessageMeta::VERSION_META.get(&self.data) }
+
   pub fn capnp_to_owned(&self) -> MessageShared {
     MessageShared { data: self.data.capnp_to_owned() }
   }
@@ -239,6 +317,12 @@ impl Message for MessageRef<'_> {
   fn payload<'a>(&'a self) -> Result<Result<Payload<'a>, UnknownDiscriminant>,Error> {
     self.payload()
  }
+  fn cluster_id<'a>(&'a self) -> ClusterID {
+    self.cluster_id()
+ }
+  fn version<'a>(&'a self) -> u32 {
+    self.version()
+ }
 }
 
 impl<'a> TypedStructRef<'a> for MessageRef<'a> {
@@ -288,11 +372,15 @@ impl MessageShared {
     src: NodeID,
     dest: NodeID,
     payload: PayloadShared,
+    cluster_id: ClusterID,
+    version: u32,
   ) -> MessageShared {
     let mut data = UntypedStructOwned::new_with_root_struct(MessageMeta::META.data_size, MessageMeta::META.pointer_size);
     MessageMeta::SRC_META.set(&mut data, src.0);
     MessageMeta::DEST_META.set(&mut data, dest.0);
     MessageMeta::PAYLOAD_META.set(&mut data, payload);
+    MessageMeta::CLUSTER_ID_META.set(&mut data, cluster_id.0);
+    MessageMeta::VERSION_META.set(&mut data, version);
     MessageShared { data: data.into_shared() }
   }
 
@@ -0,0 +1,6 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| rast::fuzz::step_group(data));
@@ -41,7 +41,8 @@ impl CompressedLog {
     self.extend_trimmed(entries);
   }
 
-  fn trim(&mut self, index: Index) {
+  // Removes every entry after the given index.
+  pub fn trim(&mut self, index: Index) {
     while let Some((_, tc_index)) = self.term_changes.last().copied() {
       if index >= tc_index {
         break;
@@ -151,7 +152,7 @@ mod tests {
   #![allow(clippy::wildcard_imports)]
   use super::*;
 
-  use crate::serde::EntryShared;
+  use crate::serde::{EntryKind, EntryShared};
 
   #[test]
   fn empty() {
@@ -176,7 +177,7 @@ mod tests {
     let entries = history
       .iter()
       .copied()
-      .map(|(term, index)| EntryShared::new(term, index, &[]))
+      .map(|(term, index)| EntryShared::new(term, index, &[], EntryKind::User))
       .collect::<Vec<_>>();
 
     log.extend(&entries.iter().map(|x| x.capnp_as_ref()).collect::<Vec<_>>());
@@ -205,7 +206,7 @@ mod tests {
     let entries = history
       .iter()
       .copied()
-      .map(|(term, index)| EntryShared::new(term, index, &[]))
+      .map(|(term, index)| EntryShared::new(term, index, &[], EntryKind::User))
       .collect::<Vec<_>>();
 
     log.extend(&entries.iter().map(|x| x.capnp_as_ref()).collect::<Vec<_>>());
@@ -221,7 +222,7 @@ mod tests {
     let alt_entries = alt_history
       .iter()
       .copied()
-      .map(|(term, index)| EntryShared::new(term, index, &[]))
+      .map(|(term, index)| EntryShared::new(term, index, &[], EntryKind::User))
       .collect::<Vec<_>>();
 
     log.extend(&alt_entries[1..].iter().map(|x| x.capnp_as_ref()).collect::<Vec<_>>());
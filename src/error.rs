@@ -3,14 +3,24 @@
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::io;
+use std::time::Duration;
 
-use super::serde::NodeID;
+use super::raft::PROTOCOL_VERSION;
+use super::serde::{ClusterID, Index, NodeID, Term};
 
 /// An error to be handed by the user of this Raft library.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ClientError {
   /// See [`NotLeaderError`].
   NotLeaderError(NotLeaderError),
+  /// See [`PersistError`].
+  PersistError(PersistError),
+  /// A [`ReadConsistency::AtLeastIndex`](crate::ReadConsistency::AtLeastIndex)
+  /// read was sent to a node that hasn't heard of the index, or whose entry at
+  /// the index was truncated. It may be behind, so the read can be retried, or
+  /// sent to the leader.
+  UnknownIndex(Index),
 }
 
 /// An error returned when a read or write was sent to a node that was not the
@@ -41,3 +51,159 @@ impl Error for NotLeaderError {
     None
   }
 }
+
+/// An error returned when the log couldn't durably persist a write, see
+/// [`PersistRes`](crate::PersistRes).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistError {
+  /// A description of what went wrong.
+  pub message: String,
+}
+
+impl PersistError {
+  /// Returns a `PersistError` with the given description.
+  pub fn new<S: Into<String>>(message: S) -> PersistError {
+    PersistError { message: message.into() }
+  }
+}
+
+impl From<io::Error> for PersistError {
+  fn from(err: io::Error) -> PersistError {
+    PersistError::new(err.to_string())
+  }
+}
+
+impl Display for PersistError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "persist failed: {}", self.message)
+  }
+}
+
+impl Error for PersistError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+/// An error returned when a [`Config`](crate::Config) contains an unsafe
+/// combination of tunables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+  /// The election timeout was zero, which would make every tick call an
+  /// election.
+  ZeroElectionTimeout,
+  /// The heartbeat interval was zero, which would make every tick send a
+  /// heartbeat.
+  ZeroHeartbeatInterval,
+  /// The heartbeat interval was not less than the election timeout, which
+  /// would make followers call elections while the leader is healthy.
+  HeartbeatNotLessThanElectionTimeout {
+    /// The configured heartbeat interval.
+    heartbeat_interval: Duration,
+    /// The configured election timeout.
+    election_timeout: Duration,
+  },
+  /// The protocol version was zero or newer than this release understands.
+  UnsupportedProtocolVersion(u32),
+  /// The cluster id of a live node was changed.
+  ClusterIDChanged,
+}
+
+impl Display for ConfigError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      ConfigError::ZeroElectionTimeout => write!(f, "election_timeout must be non-zero"),
+      ConfigError::ZeroHeartbeatInterval => write!(f, "heartbeat_interval must be non-zero"),
+      ConfigError::UnsupportedProtocolVersion(version) => {
+        write!(f, "protocol_version ({}) must be between 1 and {}", version, PROTOCOL_VERSION)
+      }
+      ConfigError::ClusterIDChanged => write!(f, "cluster_id can't be changed on a live node"),
+      ConfigError::HeartbeatNotLessThanElectionTimeout { heartbeat_interval, election_timeout } => {
+        write!(
+          f,
+          "heartbeat_interval ({:?}) must be less than election_timeout ({:?})",
+          heartbeat_interval, election_timeout
+        )
+      }
+    }
+  }
+}
+
+impl Error for ConfigError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+/// An error returned when [`Raft::step`](crate::Raft::step) is handed a message
+/// that no correct peer would have sent.
+///
+/// The message is dropped without affecting the node, so a buggy or malicious
+/// peer can't take it down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+  /// The message was sent by a node in a different group.
+  ClusterMismatch(ClusterID),
+  /// The message was at a newer protocol version than this node understands,
+  /// see [`Config::protocol_version`](crate::Config::protocol_version).
+  UnsupportedVersion(u32),
+  /// The message couldn't be decoded.
+  Malformed(String),
+  /// The message was addressed to a different node.
+  Misaddressed(NodeID),
+  /// The message was sent by a node that isn't in the group.
+  UnknownSender,
+  /// The message named a different node as the leader or candidate than the
+  /// one that sent it.
+  SenderMismatch(NodeID),
+  /// The entries in an AppendEntries request didn't immediately follow its
+  /// previous log entry, had terms out of order, or ended at the largest
+  /// possible index.
+  InvalidEntries,
+  /// An AppendEntries request would have removed committed entries from the
+  /// log, starting at the given index.
+  TruncatesCommitted(Index),
+  /// An AppendEntries response acknowledged the given index, which is past the
+  /// end of this leader's log.
+  AckedUnknownIndex(Index),
+  /// An AppendEntries request arrived from another leader of the term this
+  /// node is leader of.
+  DuplicateLeader(Term),
+  /// The message was from the largest possible term. No node campaigns for
+  /// it, because no election could follow it.
+  TermExhausted,
+}
+
+impl Display for ProtocolError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      ProtocolError::ClusterMismatch(cluster_id) => {
+        write!(f, "message from cluster {:?}", cluster_id)
+      }
+      ProtocolError::UnsupportedVersion(version) => {
+        write!(f, "message at unsupported protocol version {}", version)
+      }
+      ProtocolError::Malformed(message) => write!(f, "malformed message: {}", message),
+      ProtocolError::Misaddressed(dest) => write!(f, "message addressed to {:?}", dest),
+      ProtocolError::UnknownSender => write!(f, "message from a node not in the group"),
+      ProtocolError::SenderMismatch(claimed) => {
+        write!(f, "message on behalf of {:?} from a different node", claimed)
+      }
+      ProtocolError::InvalidEntries => write!(f, "append entries out of order"),
+      ProtocolError::TruncatesCommitted(index) => {
+        write!(f, "append entries would truncate committed index {:?}", index)
+      }
+      ProtocolError::AckedUnknownIndex(index) => {
+        write!(f, "append entries acknowledged {:?} past the end of the log", index)
+      }
+      ProtocolError::DuplicateLeader(term) => write!(f, "second leader of term {:?}", term),
+      ProtocolError::TermExhausted => write!(f, "message from the last possible term"),
+    }
+  }
+}
+
+impl Error for ProtocolError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
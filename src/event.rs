@@ -0,0 +1,136 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use super::error::ProtocolError;
+use super::raft::Role;
+use super::serde::{Index, NodeID, Term};
+
+/// A notable state transition inside a Raft node.
+///
+/// These are emitted by [`step`](crate::Raft::step) into the sink installed
+/// with [`Raft::set_events`](crate::Raft::set_events). They exist so tests can
+/// assert on protocol behavior and so operators can record an audit trail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+  /// The node started campaigning for leadership in a new term.
+  BecameCandidate {
+    /// The term of the new election.
+    term: Term,
+  },
+  /// The node won an election.
+  BecameLeader {
+    /// The term the node is leader of.
+    term: Term,
+  },
+  /// The node stopped being a candidate or leader and became a follower.
+  SteppedDown {
+    /// The term the node is a follower in.
+    term: Term,
+    /// The role the node had before stepping down.
+    from: Role,
+    /// Why the node stepped down.
+    reason: StepDownReason,
+  },
+  /// The commit index advanced.
+  CommitAdvanced {
+    /// The previous commit index.
+    from: Index,
+    /// The new commit index.
+    to: Index,
+  },
+  /// The node granted its vote to a candidate.
+  VoteGranted {
+    /// The term of the vote.
+    term: Term,
+    /// The candidate that received the vote.
+    candidate: NodeID,
+  },
+  /// The node denied its vote to a candidate.
+  VoteDenied {
+    /// The term of the requested vote.
+    term: Term,
+    /// The candidate that requested the vote.
+    candidate: NodeID,
+    /// Why the vote was denied.
+    reason: VoteDeniedReason,
+  },
+  /// Log entries conflicting with a leader's log were removed.
+  LogTruncated {
+    /// The index of the first removed entry. It and every entry after it were
+    /// removed.
+    index: Index,
+  },
+  /// A message was dropped because no correct peer would have sent it.
+  MessageRejected {
+    /// The node the message was from.
+    src: NodeID,
+    /// What was wrong with the message.
+    error: ProtocolError,
+  },
+}
+
+/// See [`Event::SteppedDown`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepDownReason {
+  /// An rpc arrived from the given node with a higher term.
+  HigherTerm(NodeID),
+  /// The given node won the election this node was campaigning in.
+  NewLeader(NodeID),
+  /// This node's log failed to persist a write, see
+  /// [`PersistRes::error`](crate::PersistRes::error).
+  StorageFailed,
+}
+
+/// See [`Event::VoteDenied`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoteDeniedReason {
+  /// The candidate's term is older than this node's current term.
+  StaleTerm,
+  /// This node already voted for the given node in this term.
+  AlreadyVoted(NodeID),
+  /// The candidate's log is missing entries that are in this node's log.
+  LogNotUpToDate,
+  /// This node's log is failing to persist writes, so it can't safely vote.
+  StorageFailed,
+}
+
+/// A destination for [`Event`]s.
+///
+/// This is called inline from [`step`](crate::Raft::step), so it should be
+/// cheap and must not block.
+pub trait EventSink: fmt::Debug + Send + Sync {
+  /// Records that the node with the given id underwent the given transition.
+  fn event(&self, id: NodeID, event: Event);
+}
+
+/// An in-memory [`EventSink`] suitable for unit tests.
+///
+/// It can be shared between every node in a group to get a single, ordered
+/// record of the group's behavior.
+#[derive(Debug, Default)]
+pub struct MemEvents {
+  events: Mutex<Vec<(NodeID, Event)>>,
+}
+
+impl MemEvents {
+  /// Returns a new `MemEvents` with nothing recorded.
+  pub fn new() -> MemEvents {
+    Default::default()
+  }
+
+  /// Removes and returns everything recorded so far.
+  pub fn take(&self) -> Vec<(NodeID, Event)> {
+    self.events.lock().map(|mut events| events.split_off(0)).unwrap_or_default()
+  }
+}
+
+impl EventSink for MemEvents {
+  fn event(&self, id: NodeID, event: Event) {
+    // TODO: what should we do if the lock is poisoned?
+    if let Ok(mut events) = self.events.lock() {
+      events.push((id, event));
+    }
+  }
+}
@@ -0,0 +1,339 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::invariants::{dump, Invariants, NodeState};
+use crate::prelude::*;
+use crate::runtime::{LogStore, MemLog};
+use crate::serde::{
+  AppendEntriesReqShared, AppendEntriesResShared, PayloadShared, RequestVoteReqShared,
+  RequestVoteResShared, StartElectionReqShared,
+};
+
+const NODES: u64 = 3;
+
+/// Drives a small group of nodes with a schedule decoded from `data`,
+/// panicking if any of the Raft safety properties are violated.
+///
+/// The bytes pick which node ticks and by how much, which in-flight messages
+/// are delivered, dropped, or duplicated, when each node's disk work finishes,
+/// and when clients send reads and writes. Every message is one that a correct
+/// node sent, so any violation is a bug.
+pub fn step_group(data: &[u8]) {
+  let mut bytes = Bytes { data };
+  let now = Instant::now();
+  let ids: Vec<NodeID> = (0..NODES).map(NodeID).collect();
+  let mut nodes: Vec<Node> = ids.iter().map(|id| Node::new(*id, ids.clone(), now)).collect();
+  let mut network: Vec<MessageShared> = vec![];
+  let mut invariants = Invariants::new();
+  let mut writes: u64 = 0;
+
+  while !bytes.is_empty() {
+    let op = bytes.u8() % 8;
+    let node = &mut nodes[(bytes.u8() as u64 % NODES) as usize];
+    match op {
+      0 => node.tick(&mut network, bytes.duration()),
+      1..=3 if !network.is_empty() => {
+        let idx = bytes.u8() as usize % network.len();
+        let msg = match op {
+          1 => network.remove(idx),
+          2 => {
+            network.remove(idx);
+            continue;
+          }
+          _ => network[idx].clone(),
+        };
+        let dest = msg.capnp_as_ref().dest();
+        if let Some(dest) = nodes.get_mut(dest.0 as usize) {
+          dest.step(&mut network, Input::Message(msg.capnp_as_ref())).expect("protocol error");
+        }
+      }
+      // Disk work is done twice as often as anything else, otherwise most
+      // schedules spend all their time with every node waiting on it. Now and
+      // then, a write fails.
+      4 => node.finish_disk(&mut network, false),
+      5 => node.finish_disk(&mut network, bytes.u8() % 8 == 0),
+      6 => {
+        writes += 1;
+        let req = WriteReq { payload: writes.to_string().into_bytes() };
+        node.step(&mut network, Input::Write(req, WriteFuture::new())).expect("unreachable");
+      }
+      7 => {
+        let req = ReadReq { consistency: bytes.consistency(), payload: vec![] };
+        node.step(&mut network, Input::Read(req, ReadFuture::new())).expect("unreachable");
+      }
+      _ => {}
+    }
+
+    let states: Vec<_> = nodes.iter().map(Node::state).collect();
+    if let Err(err) = invariants.check(&states) {
+      panic!("{}\n{}", err, dump(&states));
+    }
+  }
+}
+
+/// Feeds the first node of a small group a sequence of inputs decoded from
+/// `data`, panicking if it does.
+///
+/// Messages come from fake peers and have arbitrary terms, indexes, and
+/// entries, so they can violate the protocol in ways that no correct peer would.
+/// Those are rejected by [`Raft::step`] and the safety properties aren't
+/// checked. Disk work is still done faithfully and in order.
+pub fn step_node(data: &[u8]) {
+  let mut bytes = Bytes { data };
+  let ids: Vec<NodeID> = (0..NODES).map(NodeID).collect();
+  let mut node = Node::new(ids[0], ids, Instant::now());
+  // Anything the node sends is ignored.
+  let mut network: Vec<MessageShared> = vec![];
+
+  while !bytes.is_empty() {
+    // One more than the number of nodes, so that messages sometimes come from
+    // a node that isn't in the group.
+    let src = NodeID(1 + bytes.u8() as u64 % NODES);
+    // Usually the sender, but not always, which no correct peer would do.
+    let sender = if bytes.u8() % 4 == 0 { bytes.node() } else { src };
+    let payload = match bytes.u8() % 9 {
+      0 => {
+        node.tick(&mut network, bytes.duration());
+        continue;
+      }
+      1 => {
+        let (term, prev_log_index) = (bytes.term(), bytes.index());
+        let (prev_log_term, leader_commit) = (bytes.term(), bytes.index());
+        let read_id = bytes.read_id();
+        let entries: Vec<_> = (0..bytes.u8() % 4)
+          .map(|offset| {
+            let kind = if bytes.u8() % 2 == 0 { EntryKind::User } else { EntryKind::Noop };
+            let index = Index(prev_log_index.0.wrapping_add(offset as u64 + 1));
+            EntryShared::new(bytes.term(), index, &[bytes.u8()], kind)
+          })
+          .collect();
+        PayloadShared::AppendEntriesReq(AppendEntriesReqShared::new(
+          term,
+          sender,
+          prev_log_index,
+          prev_log_term,
+          leader_commit,
+          read_id,
+          &entries,
+        ))
+      }
+      2 => PayloadShared::AppendEntriesRes(AppendEntriesResShared::new(
+        bytes.term(),
+        bytes.u64() % 2,
+        bytes.index(),
+        bytes.read_id(),
+      )),
+      3 => PayloadShared::RequestVoteReq(RequestVoteReqShared::new(
+        bytes.term(),
+        sender,
+        bytes.index(),
+        bytes.term(),
+      )),
+      4 => PayloadShared::RequestVoteRes(RequestVoteResShared::new(bytes.term(), bytes.u64() % 2)),
+      5 => PayloadShared::StartElectionReq(StartElectionReqShared::new(bytes.term())),
+      6 => {
+        node.finish_disk(&mut network, bytes.u8() % 8 == 0);
+        continue;
+      }
+      7 => {
+        let req = WriteReq { payload: vec![bytes.u8()] };
+        node.step(&mut network, Input::Write(req, WriteFuture::new())).expect("unreachable");
+        continue;
+      }
+      _ => {
+        let req = ReadReq { consistency: bytes.consistency(), payload: vec![] };
+        node.step(&mut network, Input::Read(req, ReadFuture::new())).expect("unreachable");
+        continue;
+      }
+    };
+    let (cluster_id, version) = (node.raft.config().cluster_id, PROTOCOL_VERSION);
+    let msg = MessageShared::new(src, node.raft.id(), payload, cluster_id, version);
+    // Messages that violate the protocol are expected to be rejected.
+    let _ = node.step(&mut network, Input::Message(msg.capnp_as_ref()));
+    network.clear();
+  }
+}
+
+// A cursor over fuzzer provided bytes. Once they run out, everything reads as
+// zero.
+struct Bytes<'a> {
+  data: &'a [u8],
+}
+
+impl<'a> Bytes<'a> {
+  fn is_empty(&self) -> bool {
+    self.data.is_empty()
+  }
+
+  fn u8(&mut self) -> u8 {
+    match self.data.split_first() {
+      Some((b, rest)) => {
+        self.data = rest;
+        *b
+      }
+      None => 0,
+    }
+  }
+
+  // Small numbers are much more interesting than large ones for terms and
+  // indexes, so these only use one byte.
+  fn u64(&mut self) -> u64 {
+    self.u8() as u64
+  }
+
+  // Like u64, but now and then the largest values, which is where arithmetic
+  // overflows.
+  fn boundary(&mut self, modulus: u64) -> u64 {
+    match self.u8() {
+      255 => u64::MAX,
+      254 => u64::MAX - 1,
+      b => b as u64 % modulus,
+    }
+  }
+
+  fn node(&mut self) -> NodeID {
+    NodeID(self.u64() % (NODES + 1))
+  }
+
+  fn term(&mut self) -> Term {
+    Term(self.boundary(8))
+  }
+
+  fn index(&mut self) -> Index {
+    Index(self.boundary(16))
+  }
+
+  fn read_id(&mut self) -> ReadID {
+    ReadID(self.boundary(u64::MAX))
+  }
+
+  fn duration(&mut self) -> Duration {
+    Duration::from_millis(self.u64())
+  }
+
+  fn consistency(&mut self) -> ReadConsistency {
+    match self.u8() % 3 {
+      0 => ReadConsistency::Linearizable,
+      1 => ReadConsistency::AtLeastIndex(self.index()),
+      _ => ReadConsistency::MaxStaleness(self.duration()),
+    }
+  }
+}
+
+// A Raft node whose disk outputs are handled one at a time, whenever the
+// caller chooses.
+pub(super) struct Node {
+  pub(super) raft: Raft,
+  now: Instant,
+  pub(super) log: MemLog,
+  // Disk outputs that haven't been handled yet, in the order they were output.
+  pub(super) disk: VecDeque<Output>,
+  // The generation of the last failed write, see PersistReq::generation.
+  failed: Option<u64>,
+}
+
+impl Node {
+  pub(super) fn new(id: NodeID, nodes: Vec<NodeID>, now: Instant) -> Node {
+    Node {
+      raft: Raft::new(id, nodes, Config::default()),
+      now,
+      log: MemLog::new(),
+      disk: VecDeque::new(),
+      failed: None,
+    }
+  }
+
+  pub(super) fn state(&self) -> NodeState<'_> {
+    let synced = self.disk.iter().all(|output| !matches!(output, Output::PersistReq(_)));
+    NodeState { status: self.raft.status(), log: if synced { Some(&self.log) } else { None } }
+  }
+
+  pub(super) fn tick(&mut self, network: &mut Vec<MessageShared>, inc: Duration) {
+    self.now += inc;
+    self.step(network, Input::Tick(self.now)).expect("unreachable");
+  }
+
+  // Steps the node, handling its outputs. Only a message that no correct peer
+  // would have sent is rejected, see Raft::step.
+  pub(super) fn step(
+    &mut self,
+    network: &mut Vec<MessageShared>,
+    input: Input<'_>,
+  ) -> Result<(), ProtocolError> {
+    let mut output = vec![];
+    let res = self.raft.step(&mut output, input);
+    for output in output {
+      match output {
+        Output::Message(msg) => network.push(msg),
+        output => self.disk.push_back(output),
+      }
+    }
+    res
+  }
+
+  // Finishes the oldest disk work, failing it instead if it's a write and
+  // `fail` is set.
+  pub(super) fn finish_disk(&mut self, network: &mut Vec<MessageShared>, fail: bool) {
+    let res = match self.disk.pop_front() {
+      Some(Output::PersistReq(req)) => {
+        let error = if self.failed.map_or(false, |failed| req.generation <= failed) {
+          Some(PersistError::new("queued behind a failed write"))
+        } else if fail {
+          self.failed = Some(req.generation);
+          Some(PersistError::new("injected"))
+        } else {
+          self.log.append(&req.entries).expect("WIP");
+          None
+        };
+        Input::PersistRes(PersistRes {
+          leader_id: req.leader_id,
+          read_id: req.read_id,
+          log_index: req.entries.last().map_or(Index(0), |entry| entry.capnp_as_ref().index()),
+          generation: req.generation,
+          error,
+        })
+      }
+      Some(Output::ApplyReq(index)) => {
+        self.log.mark_stable(index).expect("WIP");
+        return;
+      }
+      Some(Output::ReadStateMachineReq(req)) => {
+        let payload = match self.log.stable() {
+          Some(stable) => self.log.user_payloads(Index(0), stable).expect("WIP").concat(),
+          None => vec![],
+        };
+        let reads = req.reads.into_iter().map(|(read_id, _)| (read_id, payload.clone()));
+        let res = ReadStateMachineRes { index: req.index, reads: reads.collect() };
+        Input::ReadStateMachineRes(res)
+      }
+      Some(Output::ReadLogReq(req)) => {
+        let entries = self.log.read(req.start, req.end).expect("WIP");
+        Input::ReadLogRes(ReadLogRes { peer: req.peer, term: req.term, entries })
+      }
+      Some(Output::Message(_)) | None => return,
+    };
+    self.step(network, res).expect("unreachable");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::rngs::SmallRng;
+  use rand::{Rng, SeedableRng};
+
+  use super::*;
+
+  // A smoke test for the fuzz targets, which are otherwise only run by
+  // cargo-fuzz.
+  #[test]
+  fn fuzz_random() {
+    for seed in 0..100 {
+      let mut rng = SmallRng::seed_from_u64(seed);
+      let data: Vec<u8> = (0..rng.gen_range(0, 2000)).map(|_| rng.gen()).collect();
+      step_group(&data);
+      step_node(&data);
+    }
+  }
+}
@@ -0,0 +1,259 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::prelude::*;
+use crate::runtime::LogStore;
+
+/// A snapshot of one node in a group, as checked by [`Invariants`].
+pub struct NodeState<'a> {
+  /// The status of the node.
+  pub status: Status,
+  /// The node's log or None if it has PersistReqs that haven't been handled,
+  /// in which case the checks that involve its log are skipped for now.
+  pub log: Option<&'a dyn LogStore>,
+}
+
+/// Checks the Raft safety properties (§5.2-5.4 of the paper) across a group of
+/// nodes.
+///
+/// It remembers what it has seen, so it's meant to be run repeatedly against
+/// the same group as it makes progress.
+#[derive(Debug, Default)]
+pub struct Invariants {
+  // The node that was leader in each term.
+  leaders: HashMap<Term, NodeID>,
+  // The last commit index seen for each node.
+  commits: HashMap<NodeID, Index>,
+  // Every entry known to be committed, along with the lowest term a node knew
+  // it to be committed in.
+  committed: BTreeMap<Index, (Term, Vec<u8>, Term)>,
+}
+
+impl Invariants {
+  /// Returns a new `Invariants` that hasn't seen anything.
+  pub fn new() -> Invariants {
+    Default::default()
+  }
+
+  /// Checks the given nodes against each other and against everything seen in
+  /// previous calls, returning a description of the first violation found.
+  pub fn check(&mut self, nodes: &[NodeState<'_>]) -> Result<(), String> {
+    let logs: Vec<_> = nodes.iter().map(|node| node.log.map(entries)).collect();
+    for (node, entries) in nodes.iter().zip(logs.iter()) {
+      let status = &node.status;
+
+      // Election safety: at most one leader can be elected in a given term.
+      if status.role == Role::Leader {
+        let leader = self.leaders.entry(status.current_term).or_insert(status.id);
+        if *leader != status.id {
+          return Err(format!(
+            "election safety: {:?} and {:?} were both leader in {:?}",
+            leader, status.id, status.current_term
+          ));
+        }
+      }
+
+      // The commit index never goes backward.
+      let commit = self.commits.entry(status.id).or_insert(Index(0));
+      if status.commit_index < *commit {
+        return Err(format!(
+          "commit index of {:?} went from {:?} to {:?}",
+          status.id, commit, status.commit_index
+        ));
+      }
+      *commit = status.commit_index;
+
+      let (log, entries) = match (node.log, entries) {
+        (Some(log), Some(entries)) => (log, entries),
+        _ => continue,
+      };
+
+      // Only committed entries are applied.
+      if let Some(stable) = log.stable() {
+        if stable > status.commit_index {
+          return Err(format!(
+            "{:?} applied {:?} but only committed {:?}",
+            status.id, stable, status.commit_index
+          ));
+        }
+      }
+
+      // State machine safety: no two nodes commit (and thus apply) different
+      // entries at the same index.
+      for (index, (term, payload)) in entries.range(..=status.commit_index) {
+        let (committed_term, committed_payload, commit_term) =
+          self.committed.entry(*index).or_insert((*term, payload.clone(), status.current_term));
+        if (&*committed_term, &*committed_payload) != (term, payload) {
+          return Err(format!(
+            "state machine safety: {:?} committed {:?} at {:?} which was already committed as {:?}",
+            status.id,
+            (term, payload),
+            index,
+            (committed_term, committed_payload)
+          ));
+        }
+        *commit_term = std::cmp::min(*commit_term, status.current_term);
+      }
+    }
+
+    // Leader completeness: an entry committed in some term is present in the
+    // log of every leader of a later term.
+    for (node, entries) in nodes.iter().zip(logs.iter()) {
+      let (status, entries) = match entries {
+        Some(entries) if node.status.role == Role::Leader => (&node.status, entries),
+        _ => continue,
+      };
+      for (index, (term, payload, commit_term)) in self.committed.iter() {
+        if *commit_term >= status.current_term {
+          continue;
+        }
+        match entries.get(index) {
+          Some((t, p)) if (t, p) == (term, payload) => {}
+          entry => {
+            return Err(format!(
+              "leader completeness: {:?} is leader in {:?} but has {:?} at {:?} instead of {:?} \
+               which was committed in {:?}",
+              status.id,
+              status.current_term,
+              entry,
+              index,
+              (term, payload),
+              commit_term
+            ))
+          }
+        }
+      }
+    }
+
+    // Log matching: if two logs contain an entry with the same index and term,
+    // then the logs are identical in all entries up through that index.
+    let logs: Vec<_> = nodes
+      .iter()
+      .zip(logs.iter())
+      .filter_map(|(node, entries)| entries.as_ref().map(|entries| (node.status.id, entries)))
+      .collect();
+    for (i, (a_id, a)) in logs.iter().enumerate() {
+      for (b_id, b) in logs.iter().skip(i + 1) {
+        let matching =
+          a.iter().rev().find(|(index, (term, _))| b.get(index).map_or(false, |(t, _)| t == term));
+        let matching = match matching {
+          Some((index, _)) => *index,
+          None => continue,
+        };
+        for (index, entry) in a.range(..=matching) {
+          if b.get(index) != Some(entry) {
+            return Err(format!(
+              "log matching: {:?} and {:?} agree on the term at {:?} but differ at {:?}",
+              a_id, b_id, matching, index
+            ));
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+// Every entry in the given log.
+fn read_all(log: &dyn LogStore) -> Vec<EntryShared> {
+  match (log.first_index(), log.last_index()) {
+    (Some(first), Some(last)) => log.read(first, last).expect("WIP"),
+    _ => vec![],
+  }
+}
+
+// The term and payload of every entry in the given log, by index.
+fn entries(log: &dyn LogStore) -> BTreeMap<Index, (Term, Vec<u8>)> {
+  read_all(log)
+    .iter()
+    .map(|entry| {
+      let entry = entry.capnp_as_ref();
+      (entry.index(), (entry.term(), entry.payload().expect("WIP").to_vec()))
+    })
+    .collect()
+}
+
+/// Renders the state of every node, for use in failure messages.
+pub fn dump(nodes: &[NodeState<'_>]) -> String {
+  let mut out = String::new();
+  for node in nodes.iter() {
+    let _ = writeln!(out, "{:?}", node.status);
+    match node.log {
+      Some(log) => {
+        let _ = writeln!(out, "  stable={:?}", log.stable());
+        for entry in read_all(log).iter() {
+          let _ = writeln!(out, "  {:?}", entry);
+        }
+      }
+      None => {
+        let _ = writeln!(out, "  log not synced");
+      }
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::wildcard_imports)]
+  use super::*;
+
+  use crate::runtime::MemLog;
+  use crate::testutil::DeterministicGroup;
+
+  fn committed_write() -> DeterministicGroup {
+    let mut g = DeterministicGroup::new(3, Config::default());
+    g.nodes[0].start_election();
+    g.drain();
+    g.nodes[0].write(WriteReq { payload: b"1".to_vec() });
+    g.drain();
+    g.tick(g.cfg().heartbeat_interval);
+    g.drain();
+    g
+  }
+
+  #[test]
+  fn invariants_hold() {
+    let g = committed_write();
+    let mut invariants = Invariants::new();
+    assert_eq!(invariants.check(&g.states()), Ok(()));
+    assert!(g.nodes.iter().all(|node| node.raft.status().commit_index == Index(2)));
+  }
+
+  #[test]
+  fn invariants_state_machine_safety() {
+    let mut g = committed_write();
+    let mut invariants = Invariants::new();
+    assert_eq!(invariants.check(&g.states()), Ok(()));
+    let mut entries = g.nodes[1].log.read(Index(1), Index(1)).unwrap();
+    entries.push(EntryShared::new(Term(1), Index(2), b"2", EntryKind::User));
+    g.nodes[1].log = MemLog::new();
+    g.nodes[1].log.append(&entries).unwrap();
+    let err = invariants.check(&g.states()).unwrap_err();
+    assert!(err.starts_with("state machine safety: NodeID(1) committed"), "{}", err);
+  }
+
+  #[test]
+  fn invariants_log_matching() {
+    // Nothing is committed, so only log matching applies.
+    let mut g = DeterministicGroup::new(3, Config::default());
+    let entry = |term, index, payload: &str| {
+      EntryShared::new(Term(term), Index(index), payload.as_bytes(), EntryKind::User)
+    };
+    g.nodes[1].log.append(&[entry(1, 1, "a"), entry(2, 2, "b")]).unwrap();
+    g.nodes[2].log.append(&[entry(1, 1, "a")]).unwrap();
+    assert_eq!(Invariants::new().check(&g.states()), Ok(()));
+    g.nodes[2].log = MemLog::new();
+    g.nodes[2].log.append(&[entry(1, 1, "c"), entry(2, 2, "b")]).unwrap();
+    assert_eq!(
+      Invariants::new().check(&g.states()),
+      Err(
+        "log matching: NodeID(1) and NodeID(2) agree on the term at Index(2) but differ at \
+         Index(1)"
+          .to_string()
+      )
+    );
+  }
+}
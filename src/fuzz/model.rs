@@ -0,0 +1,200 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::time::Instant;
+
+use super::fuzz::Node;
+use super::invariants::{dump, Invariants};
+use crate::prelude::*;
+use crate::runtime::LogStore;
+
+// The bounds on the state space visited by `explore`.
+struct Bounds {
+  // The number of nodes in the group.
+  nodes: u64,
+  // The maximum number of choices made along any path.
+  depth: usize,
+  // Any state where a node has a higher term than this isn't explored further.
+  max_term: Term,
+  // The maximum number of client writes along any path.
+  writes: u64,
+}
+
+// One step that can be taken from a state. Nodes and in-flight messages are
+// identified by their position.
+#[derive(Debug, Clone, Copy)]
+enum Choice {
+  Tick(usize),
+  Deliver(usize),
+  Drop(usize),
+  FinishDisk(usize),
+  Write(usize),
+}
+
+#[derive(Debug)]
+struct Stats {
+  // The number of distinct states visited.
+  states: usize,
+  // The highest commit index reached by any node in any state.
+  max_commit: Index,
+}
+
+struct World {
+  nodes: Vec<Node>,
+  network: Vec<MessageShared>,
+  invariants: Invariants,
+  writes: u64,
+}
+
+impl World {
+  fn new(bounds: &Bounds, now: Instant) -> World {
+    let ids: Vec<NodeID> = (0..bounds.nodes).map(NodeID).collect();
+    World {
+      nodes: ids.iter().map(|id| Node::new(*id, ids.clone(), now)).collect(),
+      network: vec![],
+      invariants: Invariants::new(),
+      writes: 0,
+    }
+  }
+
+  // Rebuilds the state reached by taking the given choices from the initial
+  // one. Raft nodes can't be cloned, so this is how a state is revisited.
+  fn replay(bounds: &Bounds, now: Instant, path: &[Choice]) -> World {
+    let mut world = World::new(bounds, now);
+    for (idx, choice) in path.iter().enumerate() {
+      world.apply(*choice);
+      let states: Vec<_> = world.nodes.iter().map(Node::state).collect();
+      if let Err(err) = world.invariants.check(&states) {
+        panic!("{}\nafter {:?}\n{}", err, &path[..=idx], dump(&states));
+      }
+    }
+    world
+  }
+
+  fn choices(&self, bounds: &Bounds) -> Vec<Choice> {
+    let mut choices = vec![];
+    for (idx, node) in self.nodes.iter().enumerate() {
+      choices.push(Choice::Tick(idx));
+      if !node.disk.is_empty() {
+        choices.push(Choice::FinishDisk(idx));
+      }
+      if self.writes < bounds.writes {
+        choices.push(Choice::Write(idx));
+      }
+    }
+    for idx in 0..self.network.len() {
+      choices.push(Choice::Deliver(idx));
+      choices.push(Choice::Drop(idx));
+    }
+    choices
+  }
+
+  fn apply(&mut self, choice: Choice) {
+    match choice {
+      Choice::Tick(idx) => {
+        // Long enough for any node to time out and for a leader to send
+        // heartbeats.
+        self.nodes[idx].tick(&mut self.network, Config::default().election_timeout);
+      }
+      Choice::Deliver(idx) => {
+        let msg = self.network.remove(idx);
+        let dest = msg.capnp_as_ref().dest();
+        if let Some(dest) = self.nodes.get_mut(dest.0 as usize) {
+          dest.step(&mut self.network, Input::Message(msg.capnp_as_ref())).expect("protocol error");
+        }
+      }
+      Choice::Drop(idx) => {
+        self.network.remove(idx);
+      }
+      Choice::FinishDisk(idx) => self.nodes[idx].finish_disk(&mut self.network, false),
+      Choice::Write(idx) => {
+        self.writes += 1;
+        let req = WriteReq { payload: self.writes.to_string().into_bytes() };
+        let input = Input::Write(req, WriteFuture::new());
+        self.nodes[idx].step(&mut self.network, input).expect("unreachable");
+      }
+    }
+  }
+
+  // A hash of everything about this state that's visible from outside the
+  // nodes. Timers aren't included, so two states that differ only in how long
+  // until some node's election timeout are considered the same.
+  fn fingerprint(&self) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for node in self.nodes.iter() {
+      let status = node.raft.status();
+      let peers: Vec<_> =
+        status.peers.iter().map(|peer| (peer.id, peer.match_index, peer.next_index)).collect();
+      format!(
+        "{:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+        status.role,
+        status.current_term,
+        status.voted_for,
+        status.leader_hint,
+        status.commit_index,
+        status.last_applied,
+        peers
+      )
+      .hash(&mut hasher);
+      let entries = node.log.last_index().map(|last| node.log.read(Index(1), last).expect("WIP"));
+      format!("{:?} {:?} {:?}", entries, node.log.stable(), node.disk).hash(&mut hasher);
+    }
+    // The order of in-flight messages doesn't matter.
+    let mut network: Vec<_> = self.network.iter().map(|msg| format!("{:?}", msg)).collect();
+    network.sort();
+    network.hash(&mut hasher);
+    self.writes.hash(&mut hasher);
+    hasher.finish()
+  }
+}
+
+// Visits, breadth first, every state reachable within the given bounds and
+// checks the Raft safety properties along the way, panicking with the path
+// taken if one is violated.
+fn explore(bounds: &Bounds) -> Stats {
+  let now = Instant::now();
+  let mut stats = Stats { states: 0, max_commit: Index(0) };
+  let mut seen = HashSet::new();
+  seen.insert(World::new(bounds, now).fingerprint());
+  let mut frontier: Vec<Vec<Choice>> = vec![vec![]];
+  for _ in 0..bounds.depth {
+    let mut next = vec![];
+    for path in frontier.iter() {
+      let choices = World::replay(bounds, now, path).choices(bounds);
+      for choice in choices {
+        let mut path = path.clone();
+        path.push(choice);
+        let world = World::replay(bounds, now, &path);
+        let statuses: Vec<_> = world.nodes.iter().map(|node| node.raft.status()).collect();
+        if statuses.iter().any(|status| status.current_term > bounds.max_term) {
+          continue;
+        }
+        if seen.insert(world.fingerprint()) {
+          stats.max_commit =
+            statuses.iter().map(|status| status.commit_index).fold(stats.max_commit, Ord::max);
+          next.push(path);
+        }
+      }
+    }
+    frontier = next;
+  }
+  stats.states = seen.len();
+  stats
+}
+
+#[test]
+fn model_check_two_nodes() {
+  let bounds = Bounds { nodes: 2, depth: 9, max_term: Term(2), writes: 1 };
+  let stats = explore(&bounds);
+  // Deep enough for a leader to be elected and commit its first entry.
+  assert!(stats.max_commit >= Index(1), "{:?}", stats);
+}
+
+#[test]
+fn model_check_three_nodes() {
+  let bounds = Bounds { nodes: 3, depth: 7, max_term: Term(2), writes: 0 };
+  let stats = explore(&bounds);
+  assert!(stats.max_commit >= Index(1), "{:?}", stats);
+}
@@ -15,7 +15,7 @@
 //! # fn main() {
 //! let mut raft = Raft::new(NodeID(0), vec![NodeID(0)], Config::default());
 //! let mut output = vec![];
-//! raft.step(&mut output, Input::Tick(Instant::now()));
+//! raft.step(&mut output, Input::Tick(Instant::now())).unwrap();
 //! # }
 //! ```
 //!
@@ -35,16 +35,14 @@
 //!   # // write gets eaten
 //!   # let _ = client.write(WriteReq{payload: vec![]});
 //!   let _ = client.write(WriteReq{payload: "1".as_bytes().to_vec()});
-//!   let read = client.read(ReadReq{payload: vec![]});
+//!   let read = client.read(ReadReq{consistency: ReadConsistency::Linearizable, payload: vec![]});
 //!   let result_bytes = read.await.unwrap();
 //!   String::from_utf8(result_bytes.payload).unwrap()
 //! }
 //!
 //! # fn main() {
 //! let raft = Raft::new(NodeID(0), vec![NodeID(0)], Config::default());
-//! let mut rpc = MemRPC::new();
-//! let runtime = Runtime::new("rast".to_string(), raft, rpc.clone(), MemLog::new());
-//! rpc.register(NodeID(0), runtime.sender());
+//! let runtime = Runtime::new("rast".to_string(), raft, MemRPC::new(), MemLog::new());
 //!
 //! // This client is Clone+Send.
 //! let client = runtime.client();
@@ -91,16 +89,23 @@ macro_rules! error { ($($x:tt)*) => (
 
 mod compressed_log;
 mod error;
+mod event;
 mod future;
+mod metrics;
 mod raft;
 mod serde;
 
-pub use crate::error::{ClientError, NotLeaderError};
+pub use crate::error::{ClientError, ConfigError, NotLeaderError, PersistError, ProtocolError};
+pub use crate::event::{Event, EventSink, MemEvents, StepDownReason, VoteDeniedReason};
 pub use crate::future::{ReadFuture, WriteFuture};
-pub use crate::raft::{Config, Input, Output, OwnedInput, PersistRes, Raft, ReadStateMachineRes};
+pub use crate::metrics::{MemMetrics, Metrics, MetricsSnapshot, NoopMetrics};
+pub use crate::raft::{
+  Config, HardState, Input, Output, OwnedInput, PeerStatus, PersistRes, Raft, ReadLogRes,
+  ReadStateMachineRes, Role, Status, PROTOCOL_VERSION,
+};
 pub use crate::serde::{
-  EntryRef, EntryShared, Index, MessageRef, MessageShared, NodeID, ReadID, ReadReq, ReadRes, Term,
-  WriteReq, WriteRes,
+  ClusterID, EntryKind, EntryRef, EntryShared, Index, MessageRef, MessageShared, NodeID,
+  ReadConsistency, ReadID, ReadReq, ReadRes, Term, WriteReq, WriteRes,
 };
 
 /// The Raft prelude.
@@ -119,18 +124,75 @@ pub mod prelude {
 /// feature.
 #[cfg(any(feature = "runtime", test))]
 pub mod runtime {
+  mod logstore;
+  pub use logstore::*;
+
   mod memlog;
   pub use memlog::*;
 
+  mod driver;
+  pub use driver::*;
+
   mod memrpc;
   pub use memrpc::*;
 
   mod runtime;
   pub use runtime::*;
+
+  mod transport;
+  pub use transport::*;
+
+  mod watch;
+  pub use watch::*;
+}
+
+/// A seeded, discrete-event simulator for testing whole Raft groups.
+///
+/// This drives a group of deterministic Raft nodes on a virtual clock, with
+/// randomized (but reproducible) message and disk latencies. This is enabled by
+/// opting in to the "sim" crate feature.
+#[cfg(any(feature = "sim", test))]
+pub mod sim {
+  #[allow(clippy::module_inception)]
+  mod sim;
+  pub use sim::*;
+}
+
+/// Entry points for fuzzing.
+///
+/// These decode arbitrary bytes into a sequence of inputs for one or more Raft
+/// nodes. They're used by the cargo-fuzz targets in the `fuzz` directory and
+/// are enabled by opting in to the "fuzz" crate feature.
+#[cfg(any(feature = "fuzz", test))]
+pub mod fuzz {
+  #[allow(clippy::module_inception)]
+  mod fuzz;
+  pub use fuzz::*;
+
+  mod invariants;
+  pub use invariants::*;
+
+  #[cfg(test)]
+  mod model;
+}
+
+/// Recording and replay of the inputs to a Raft node.
+///
+/// Because Raft is deterministic, a recording of every input a node received
+/// can be replayed later to reproduce its exact behavior, which is useful for
+/// debugging after the fact. This is enabled by opting in to the "record" crate
+/// feature.
+#[cfg(any(feature = "record", test))]
+pub mod record {
+  #[allow(clippy::module_inception)]
+  mod record;
+  pub use record::*;
 }
 
 #[cfg(test)]
 mod nemesis {
+  mod linearizability;
+
   mod nemesis;
   pub use nemesis::*;
 }
@@ -158,8 +220,6 @@ mod testutil {
 
 // TODO: figure out how to call output.extend without creating a vec
 // TODO: more consistent method naming
-// TODO: restart node with non-empty log + hard state
-// TODO: retry append rpc, find where follower diverges
 // TODO: nemesis test shouldn't hang when something panics
 // TODO: tests
 // - election timeout, node isn't elected in a short enough time
@@ -0,0 +1,127 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::serde::Term;
+
+/// Hooks for recording metrics about a Raft node.
+///
+/// Every method has a no-op default, so an implementation only needs to
+/// override the ones it's interested in. These are called inline from
+/// [`step`](crate::Raft::step) and the [`runtime`](crate::runtime), so they
+/// should be cheap and must not block.
+///
+/// Install an implementation with [`Raft::set_metrics`](crate::Raft::set_metrics).
+pub trait Metrics: fmt::Debug + Send + Sync {
+  /// Called when this node starts an election.
+  fn election_started(&self) {}
+  /// Called when this node wins an election.
+  fn election_won(&self) {}
+  /// Called when this node advances to a new term.
+  fn term_changed(&self, _term: Term) {}
+  /// Called when a user write is proposed to this node while it is leader.
+  fn proposal(&self) {}
+  /// Called when a user write commits with the time elapsed since it was
+  /// proposed, as measured by [`Input::Tick`](crate::Input::Tick).
+  fn commit_latency(&self, _latency: Duration) {}
+  /// Called when AppendEntries rpcs are sent to `rpcs` peers.
+  fn append_entries_sent(&self, _rpcs: usize) {}
+  /// Called when a peer rejects an AppendEntries rpc.
+  fn append_entries_rejected(&self) {}
+  /// Called when a batch of reads becomes servable by the state machine.
+  fn read_batch_size(&self, _reads: usize) {}
+  /// Called when the runtime has finished processing a
+  /// [`PersistReq`](crate::Output::PersistReq), or a group of them that were
+  /// persisted together.
+  fn persist_latency(&self, _latency: Duration) {}
+}
+
+/// A [`Metrics`] implementation that discards everything.
+///
+/// This is the default for a newly constructed [`Raft`](crate::Raft).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+/// A copy of everything recorded by a [`MemMetrics`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+  /// See [`Metrics::election_started`].
+  pub elections_started: u64,
+  /// See [`Metrics::election_won`].
+  pub elections_won: u64,
+  /// See [`Metrics::term_changed`].
+  pub term_changes: u64,
+  /// See [`Metrics::proposal`].
+  pub proposals: u64,
+  /// See [`Metrics::commit_latency`].
+  pub commit_latencies: Vec<Duration>,
+  /// See [`Metrics::append_entries_sent`].
+  pub append_entries_sent: u64,
+  /// See [`Metrics::append_entries_rejected`].
+  pub append_entries_rejected: u64,
+  /// See [`Metrics::read_batch_size`].
+  pub read_batch_sizes: Vec<usize>,
+  /// See [`Metrics::persist_latency`].
+  pub persist_latencies: Vec<Duration>,
+}
+
+/// An in-memory [`Metrics`] implementation suitable for unit tests.
+///
+/// Counters are summed and every histogram observation is kept.
+#[derive(Debug, Default)]
+pub struct MemMetrics {
+  snapshot: Mutex<MetricsSnapshot>,
+}
+
+impl MemMetrics {
+  /// Returns a new `MemMetrics` with nothing recorded.
+  pub fn new() -> MemMetrics {
+    Default::default()
+  }
+
+  /// Returns a copy of everything recorded so far.
+  pub fn snapshot(&self) -> MetricsSnapshot {
+    self.snapshot.lock().map(|snapshot| snapshot.clone()).unwrap_or_default()
+  }
+
+  fn record(&self, f: impl FnOnce(&mut MetricsSnapshot)) {
+    // TODO: what should we do if the lock is poisoned?
+    if let Ok(mut snapshot) = self.snapshot.lock() {
+      f(&mut snapshot)
+    }
+  }
+}
+
+impl Metrics for MemMetrics {
+  fn election_started(&self) {
+    self.record(|m| m.elections_started += 1)
+  }
+  fn election_won(&self) {
+    self.record(|m| m.elections_won += 1)
+  }
+  fn term_changed(&self, _term: Term) {
+    self.record(|m| m.term_changes += 1)
+  }
+  fn proposal(&self) {
+    self.record(|m| m.proposals += 1)
+  }
+  fn commit_latency(&self, latency: Duration) {
+    self.record(|m| m.commit_latencies.push(latency))
+  }
+  fn append_entries_sent(&self, rpcs: usize) {
+    self.record(|m| m.append_entries_sent += rpcs as u64)
+  }
+  fn append_entries_rejected(&self) {
+    self.record(|m| m.append_entries_rejected += 1)
+  }
+  fn read_batch_size(&self, reads: usize) {
+    self.record(|m| m.read_batch_sizes.push(reads))
+  }
+  fn persist_latency(&self, latency: Duration) {
+    self.record(|m| m.persist_latencies.push(latency))
+  }
+}
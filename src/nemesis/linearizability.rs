@@ -0,0 +1,486 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::time::Instant;
+
+/// A sequential specification that concurrent histories are checked against.
+pub trait Model {
+  type State: Clone + Eq + Hash + fmt::Debug;
+  type Input: Clone + fmt::Debug;
+  type Output: Clone + fmt::Debug;
+
+  /// The state before any op has been applied.
+  fn init(&self) -> Self::State;
+
+  /// Returns the state after applying `input` to `state` or None if `output`
+  /// couldn't have been the result of doing so. An output of None means the
+  /// result of the op is unknown.
+  fn step(
+    &self,
+    state: &Self::State,
+    input: &Self::Input,
+    output: Option<&Self::Output>,
+  ) -> Option<Self::State>;
+
+  /// Splits a history into sub-histories that can be checked independently.
+  /// Each returned Vec is a list of indexes into `history`.
+  fn partition(&self, history: &[Operation<Self::Input, Self::Output>]) -> Vec<Vec<usize>> {
+    vec![(0..history.len()).collect()]
+  }
+}
+
+/// A single op in a concurrent history.
+#[derive(Debug, Clone)]
+pub struct Operation<I, O> {
+  pub client: u64,
+  pub input: I,
+  pub call: Instant,
+  /// The result of the op and when it was received, or None if the op failed
+  /// or never returned. Such an op is indeterminate: it may have taken effect
+  /// at any point after it was called or not at all.
+  pub output: Option<(O, Instant)>,
+}
+
+/// A history with no valid linearization.
+#[derive(Debug, Clone)]
+pub struct NotLinearizable<I, O> {
+  /// A minimal non-linearizable sub-history of the checked history, sorted by
+  /// call time. Removing any one op from it makes it linearizable.
+  pub history: Vec<Operation<I, O>>,
+}
+
+impl<I: fmt::Debug, O: fmt::Debug> fmt::Display for NotLinearizable<I, O> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "not linearizable: [")?;
+    let base = self.history.iter().map(|op| op.call).min();
+    for (idx, op) in self.history.iter().enumerate() {
+      if idx > 0 {
+        write!(f, ", ")?;
+      }
+      // Times are relative to the first call in the sub-history.
+      let call = base.map_or_else(Default::default, |base| op.call.duration_since(base));
+      write!(f, "client {} {:?} at {:?} -> ", op.client, op.input, call)?;
+      match (&op.output, base) {
+        (Some((output, ret)), Some(base)) => {
+          write!(f, "{:?} at {:?}", output, ret.duration_since(base))?
+        }
+        _ => write!(f, "?")?,
+      }
+    }
+    write!(f, "]")
+  }
+}
+
+impl<I: fmt::Debug, O: fmt::Debug> Error for NotLinearizable<I, O> {}
+
+/// Checks whether a concurrent history is linearizable with respect to the
+/// given model.
+///
+/// This is the search of Wing & Gong, with the memoization of Lowe: ops are
+/// tentatively linearized in an order consistent with real time and the search
+/// backtracks when the model rejects one, skipping any (linearized ops, state)
+/// pair that has already been explored. It's exponential in the worst case, so
+/// it's only suitable for test-sized histories.
+pub fn check<M: Model>(
+  model: &M,
+  history: &[Operation<M::Input, M::Output>],
+) -> Result<(), NotLinearizable<M::Input, M::Output>> {
+  for partition in model.partition(history) {
+    let ops: Vec<_> = partition.iter().map(|idx| &history[*idx]).collect();
+    if !linearizable(model, &ops) {
+      let mut history: Vec<_> = minimize(model, ops).into_iter().cloned().collect();
+      history.sort_by_key(|op| op.call);
+      return Err(NotLinearizable { history });
+    }
+  }
+  Ok(())
+}
+
+// Greedily removes ops that aren't needed for the history to be
+// non-linearizable until there are none left to remove.
+fn minimize<'a, M: Model>(
+  model: &M,
+  mut ops: Vec<&'a Operation<M::Input, M::Output>>,
+) -> Vec<&'a Operation<M::Input, M::Output>> {
+  loop {
+    let before = ops.len();
+    // Go backward so removals don't shift the ops still to be tried.
+    for idx in (0..ops.len()).rev() {
+      let mut without = ops.clone();
+      without.remove(idx);
+      if !linearizable(model, &without) {
+        ops = without;
+      }
+    }
+    if ops.len() == before {
+      return ops;
+    }
+  }
+}
+
+fn linearizable<M: Model>(model: &M, ops: &[&Operation<M::Input, M::Output>]) -> bool {
+  let mut ops = ops.to_vec();
+  ops.sort_by_key(|op| op.call);
+  // Trying ops with a known result first finds a linearization (or rules one
+  // out) without guessing at which indeterminate ops took effect.
+  let mut candidates: Vec<usize> = (0..ops.len()).collect();
+  candidates.sort_by_key(|idx| (ops[*idx].output.is_none(), ops[*idx].call));
+  let remaining = ops.iter().filter(|op| op.output.is_some()).count();
+  let linearized = vec![false; ops.len()];
+  let mut search = Search { model, ops, candidates, linearized, seen: HashSet::new() };
+  search.search(&model.init(), remaining)
+}
+
+struct Search<'a, M: Model> {
+  model: &'a M,
+  ops: Vec<&'a Operation<M::Input, M::Output>>,
+  candidates: Vec<usize>,
+  linearized: Vec<bool>,
+  seen: HashSet<(Vec<bool>, M::State)>,
+}
+
+impl<'a, M: Model> Search<'a, M> {
+  // Returns whether the ops not yet linearized can be linearized starting from
+  // the given state. Indeterminate ops don't need to be linearized at all, so
+  // only ones with a result count toward `remaining`.
+  fn search(&mut self, state: &M::State, remaining: usize) -> bool {
+    if remaining == 0 {
+      return true;
+    }
+    if !self.seen.insert((self.linearized.clone(), state.clone())) {
+      return false;
+    }
+    // An op can only be linearized next if it was called before every op that
+    // hasn't been linearized yet returned.
+    let deadline = (0..self.ops.len())
+      .filter(|idx| !self.linearized[*idx])
+      .filter_map(|idx| self.ops[idx].output.as_ref().map(|(_, ret)| *ret))
+      .min();
+    for candidate_idx in 0..self.candidates.len() {
+      let idx = self.candidates[candidate_idx];
+      let op = self.ops[idx];
+      if self.linearized[idx] || deadline.map_or(false, |deadline| op.call > deadline) {
+        continue;
+      }
+      let output = op.output.as_ref().map(|(output, _)| output);
+      if let Some(next) = self.model.step(state, &op.input, output) {
+        self.linearized[idx] = true;
+        if self.search(&next, remaining - if output.is_some() { 1 } else { 0 }) {
+          return true;
+        }
+        self.linearized[idx] = false;
+      }
+    }
+    false
+  }
+}
+
+/// A single value that is read and overwritten.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Register;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterInput {
+  Read,
+  Write(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterOutput {
+  Read(Vec<u8>),
+  Write,
+}
+
+impl Model for Register {
+  type State = Vec<u8>;
+  type Input = RegisterInput;
+  type Output = RegisterOutput;
+
+  fn init(&self) -> Vec<u8> {
+    vec![]
+  }
+
+  fn step(
+    &self,
+    state: &Vec<u8>,
+    input: &RegisterInput,
+    output: Option<&RegisterOutput>,
+  ) -> Option<Vec<u8>> {
+    match (input, output) {
+      (RegisterInput::Read, None) => Some(state.clone()),
+      (RegisterInput::Read, Some(RegisterOutput::Read(value))) if value == state => {
+        Some(state.clone())
+      }
+      (RegisterInput::Write(value), None)
+      | (RegisterInput::Write(value), Some(RegisterOutput::Write)) => Some(value.clone()),
+      _ => None,
+    }
+  }
+}
+
+/// A byte string that is read in its entirety and appended to.
+///
+/// This is the state machine that the nemesis tests run on top of Raft.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AppendLog;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppendLogInput {
+  Read,
+  Append(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppendLogOutput {
+  Read(Vec<u8>),
+  Append,
+}
+
+impl Model for AppendLog {
+  type State = Vec<u8>;
+  type Input = AppendLogInput;
+  type Output = AppendLogOutput;
+
+  fn init(&self) -> Vec<u8> {
+    vec![]
+  }
+
+  fn step(
+    &self,
+    state: &Vec<u8>,
+    input: &AppendLogInput,
+    output: Option<&AppendLogOutput>,
+  ) -> Option<Vec<u8>> {
+    match (input, output) {
+      (AppendLogInput::Read, None) => Some(state.clone()),
+      (AppendLogInput::Read, Some(AppendLogOutput::Read(value))) if value == state => {
+        Some(state.clone())
+      }
+      (AppendLogInput::Append(value), None)
+      | (AppendLogInput::Append(value), Some(AppendLogOutput::Append)) => {
+        let mut state = state.clone();
+        state.extend(value);
+        Some(state)
+      }
+      _ => None,
+    }
+  }
+}
+
+/// A map of keys to values, each of which is read and overwritten
+/// independently.
+///
+/// Histories are partitioned by key, which keeps the search small.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Kv;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvInput {
+  Get(Vec<u8>),
+  Put(Vec<u8>, Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvOutput {
+  Get(Option<Vec<u8>>),
+  Put,
+}
+
+impl Model for Kv {
+  type State = BTreeMap<Vec<u8>, Vec<u8>>;
+  type Input = KvInput;
+  type Output = KvOutput;
+
+  fn init(&self) -> Self::State {
+    BTreeMap::new()
+  }
+
+  fn step(
+    &self,
+    state: &Self::State,
+    input: &KvInput,
+    output: Option<&KvOutput>,
+  ) -> Option<Self::State> {
+    match (input, output) {
+      (KvInput::Get(_), None) => Some(state.clone()),
+      (KvInput::Get(key), Some(KvOutput::Get(value))) if state.get(key) == value.as_ref() => {
+        Some(state.clone())
+      }
+      (KvInput::Put(key, value), None) | (KvInput::Put(key, value), Some(KvOutput::Put)) => {
+        let mut state = state.clone();
+        state.insert(key.clone(), value.clone());
+        Some(state)
+      }
+      _ => None,
+    }
+  }
+
+  fn partition(&self, history: &[Operation<KvInput, KvOutput>]) -> Vec<Vec<usize>> {
+    let mut partitions: BTreeMap<&Vec<u8>, Vec<usize>> = BTreeMap::new();
+    for (idx, op) in history.iter().enumerate() {
+      let key = match &op.input {
+        KvInput::Get(key) | KvInput::Put(key, _) => key,
+      };
+      partitions.entry(key).or_default().push(idx);
+    }
+    partitions.into_values().collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::wildcard_imports)]
+  use super::*;
+
+  use std::time::Duration;
+
+  struct History<I, O> {
+    base: Instant,
+    ops: Vec<Operation<I, O>>,
+  }
+
+  impl<I, O> History<I, O> {
+    fn new() -> Self {
+      History { base: Instant::now(), ops: vec![] }
+    }
+
+    // Adds an op that was called at time `call` and returned `output` at `ret`
+    // or, if `ret` is None, failed.
+    fn op(&mut self, client: u64, call: u64, ret: Option<u64>, input: I, output: O) -> &mut Self {
+      let at = |t| self.base + Duration::from_millis(t);
+      let call = at(call);
+      let output = ret.map(|ret| (output, at(ret)));
+      self.ops.push(Operation { client, input, call, output });
+      self
+    }
+  }
+
+  fn write(value: &str) -> RegisterInput {
+    RegisterInput::Write(value.as_bytes().to_vec())
+  }
+
+  fn read(value: &str) -> RegisterOutput {
+    RegisterOutput::Read(value.as_bytes().to_vec())
+  }
+
+  #[test]
+  fn register() {
+    // Sequential
+    let mut h = History::new();
+    h.op(0, 0, Some(1), write("a"), RegisterOutput::Write);
+    h.op(1, 2, Some(3), RegisterInput::Read, read("a"));
+    assert!(check(&Register, &h.ops).is_ok());
+
+    // A read concurrent with a write may see either value.
+    let mut h = History::new();
+    h.op(0, 0, Some(10), write("a"), RegisterOutput::Write);
+    h.op(1, 1, Some(2), RegisterInput::Read, read(""));
+    h.op(2, 3, Some(4), RegisterInput::Read, read("a"));
+    assert!(check(&Register, &h.ops).is_ok());
+
+    // But once one read has seen it, later reads must too.
+    let mut h = History::new();
+    h.op(0, 0, Some(10), write("a"), RegisterOutput::Write);
+    h.op(1, 1, Some(2), RegisterInput::Read, read("a"));
+    h.op(2, 3, Some(4), RegisterInput::Read, read(""));
+    assert!(check(&Register, &h.ops).is_err());
+
+    // A read that starts after a write finished must see it.
+    let mut h = History::new();
+    h.op(0, 0, Some(1), write("a"), RegisterOutput::Write);
+    h.op(1, 2, Some(3), RegisterInput::Read, read(""));
+    assert!(check(&Register, &h.ops).is_err());
+  }
+
+  #[test]
+  fn indeterminate() {
+    // A failed write may have taken effect...
+    let mut h = History::new();
+    h.op(0, 0, None, write("a"), RegisterOutput::Write);
+    h.op(1, 2, Some(3), RegisterInput::Read, read("a"));
+    assert!(check(&Register, &h.ops).is_ok());
+
+    // ...or not...
+    let mut h = History::new();
+    h.op(0, 0, None, write("a"), RegisterOutput::Write);
+    h.op(1, 2, Some(3), RegisterInput::Read, read(""));
+    assert!(check(&Register, &h.ops).is_ok());
+
+    // ...and it may take effect arbitrarily late.
+    let mut h = History::new();
+    h.op(0, 0, None, write("a"), RegisterOutput::Write);
+    h.op(1, 2, Some(3), write("b"), RegisterOutput::Write);
+    h.op(1, 4, Some(5), RegisterInput::Read, read("a"));
+    assert!(check(&Register, &h.ops).is_ok());
+
+    // But it can't be undone.
+    let mut h = History::new();
+    h.op(0, 0, None, write("a"), RegisterOutput::Write);
+    h.op(1, 2, Some(3), RegisterInput::Read, read("a"));
+    h.op(1, 4, Some(5), RegisterInput::Read, read(""));
+    assert!(check(&Register, &h.ops).is_err());
+  }
+
+  #[test]
+  fn append_log() {
+    let append = |value: &str| AppendLogInput::Append(value.as_bytes().to_vec());
+    let read = |value: &str| AppendLogOutput::Read(value.as_bytes().to_vec());
+
+    // Concurrent appends may be applied in either order.
+    let mut h = History::new();
+    h.op(0, 0, Some(5), append("1"), AppendLogOutput::Append);
+    h.op(1, 1, Some(4), append("2"), AppendLogOutput::Append);
+    h.op(2, 6, Some(7), AppendLogInput::Read, read("21"));
+    assert!(check(&AppendLog, &h.ops).is_ok());
+
+    // Sequential appends may not.
+    let mut h = History::new();
+    h.op(0, 0, Some(1), append("1"), AppendLogOutput::Append);
+    h.op(1, 2, Some(3), append("2"), AppendLogOutput::Append);
+    h.op(2, 4, Some(5), AppendLogInput::Read, read("21"));
+    assert!(check(&AppendLog, &h.ops).is_err());
+  }
+
+  #[test]
+  fn kv() {
+    let get = |key: &str| KvInput::Get(key.as_bytes().to_vec());
+    let got = |value: Option<&str>| KvOutput::Get(value.map(|v| v.as_bytes().to_vec()));
+    let put =
+      |key: &str, value: &str| KvInput::Put(key.as_bytes().to_vec(), value.as_bytes().to_vec());
+
+    let mut h = History::new();
+    h.op(0, 0, Some(1), put("a", "1"), KvOutput::Put);
+    h.op(1, 2, Some(3), put("b", "2"), KvOutput::Put);
+    h.op(0, 4, Some(5), get("a"), got(Some("1")));
+    h.op(1, 6, Some(7), get("b"), got(Some("2")));
+    h.op(2, 8, Some(9), get("c"), got(None));
+    assert!(check(&Kv, &h.ops).is_ok());
+
+    // Only the ops on the key with the violation are reported.
+    h.op(2, 10, Some(11), get("b"), got(None));
+    let err = check(&Kv, &h.ops).unwrap_err();
+    let inputs: Vec<_> = err.history.iter().map(|op| op.input.clone()).collect();
+    assert_eq!(inputs, vec![put("b", "2"), get("b")]);
+  }
+
+  #[test]
+  fn minimal() {
+    let mut h = History::new();
+    h.op(0, 0, Some(1), write("a"), RegisterOutput::Write);
+    h.op(1, 2, Some(3), RegisterInput::Read, read("a"));
+    h.op(2, 4, Some(5), write("b"), RegisterOutput::Write);
+    h.op(0, 6, Some(7), RegisterInput::Read, read("b"));
+    h.op(1, 8, Some(9), RegisterInput::Read, read(""));
+    h.op(2, 10, Some(11), RegisterInput::Read, read("b"));
+    let err = check(&Register, &h.ops).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "not linearizable: [\
+        client 0 Write([97]) at 0ns -> Write at 1ms, \
+        client 1 Read at 8ms -> Read([]) at 9ms]"
+    );
+  }
+}
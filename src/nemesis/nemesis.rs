@@ -1,18 +1,24 @@
 // Copyright 2020 Daniel Harrison. All Rights Reserved.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
+use ::serde::{Deserialize, Serialize};
 use extreme;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 
+use super::linearizability::{check, AppendLog, AppendLogInput, AppendLogOutput, Operation};
 use crate::prelude::*;
 use crate::runtime::RastClient;
 use crate::testutil;
@@ -62,6 +68,15 @@ pub struct Config {
   pub ops: u64,
   pub read: u64,
   pub write: u64,
+  // Each (op, node) crashes the node once op ops have been started. Anything it
+  // hadn't acknowledged as durable is lost and it's restarted after downtime.
+  pub crashes: Vec<(u64, NodeID)>,
+  pub downtime: Duration,
+  // Worker i generates ops with an rng seeded by seed + i.
+  pub seed: u64,
+  // Where to write the history of the run, see validate_saved. If None, it's
+  // only written if validation fails and then to a file in the temp dir.
+  pub history: Option<PathBuf>,
 }
 
 pub struct Generator {
@@ -89,7 +104,7 @@ impl Generator {
   }
 
   fn read(&self) -> OpReq {
-    OpReq::Read(ReadReq { payload: vec![] })
+    OpReq::Read(ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] })
   }
 
   fn write(&self) -> OpReq {
@@ -103,72 +118,307 @@ pub struct Applier<'a> {
   cfg: Config,
   ops: Arc<AtomicU64>,
   gen: &'a Generator,
-  c: RastClient,
+  clients: BTreeMap<NodeID, RastClient>,
 }
 
 impl<'a> Applier<'a> {
-  pub fn new(cfg: Config, ops: Arc<AtomicU64>, gen: &'a Generator, c: RastClient) -> Applier<'a> {
-    Applier { cfg: cfg, ops: ops, gen: gen, c: c }
+  pub fn new(
+    cfg: Config,
+    ops: Arc<AtomicU64>,
+    gen: &'a Generator,
+    clients: BTreeMap<NodeID, RastClient>,
+  ) -> Applier<'a> {
+    Applier { cfg, ops, gen, clients }
   }
 
   pub async fn worker(&self, worker_idx: u64, rng: &mut impl Rng) -> Vec<Op> {
     let mut results = vec![];
+    let mut leader = *self.clients.keys().next().unwrap();
     loop {
       let op_idx = self.ops.fetch_add(1, Ordering::SeqCst);
       if op_idx >= self.cfg.ops {
         return results;
       }
       let op = self.gen.op(rng);
-      match op {
-        OpReq::Read(req) => {
-          let res = self.read(worker_idx, req).await;
-          results.push(res);
-        }
-        OpReq::Write(req) => {
-          let res = self.write(worker_idx, req).await;
-          results.push(res);
-        }
+      let res = match op {
+        OpReq::Read(req) => self.read(worker_idx, leader, req).await,
+        OpReq::Write(req) => self.write(worker_idx, leader, req).await,
+      };
+      let err = match &res {
+        Op::Read(read) => read.res.clone().err(),
+        Op::Write(write) => write.res.clone().err(),
+      };
+      if let Some(ClientError::NotLeaderError(err)) = err {
+        // Follow the hint, if there's a useful one, otherwise try the next
+        // node. Back off a bit so an election has time to finish.
+        leader = err.hint.filter(|hint| *hint != leader).unwrap_or_else(|| {
+          let mut next = self.clients.keys().skip_while(|id| **id != leader).skip(1);
+          *next.next().unwrap_or_else(|| self.clients.keys().next().unwrap())
+        });
+        thread::sleep(Duration::from_millis(1));
       }
+      results.push(res);
     }
   }
 
-  async fn read(&self, worker_idx: u64, req: ReadReq) -> Op {
+  async fn read(&self, worker_idx: u64, node: NodeID, req: ReadReq) -> Op {
     let start = Instant::now();
-    let res = self.c.read(req.clone()).await;
+    let res = self.clients[&node].read(req.clone()).await;
     let finish = Instant::now();
     Op::Read(ReadOp { worker_idx: worker_idx, start: start, req: req, res: res, finish: finish })
   }
 
-  async fn write(&self, worker_idx: u64, req: WriteReq) -> Op {
+  async fn write(&self, worker_idx: u64, node: NodeID, req: WriteReq) -> Op {
     let start = Instant::now();
-    let res = self.c.write(req.clone()).await;
+    let res = self.clients[&node].write(req.clone()).await;
     let finish = Instant::now();
     Op::Write(WriteOp { worker_idx: worker_idx, start: start, req: req, res: res, finish: finish })
   }
 }
 
 pub fn nemesis_test(cfg: Config) -> Result<(), ValidateError> {
-  let group = ConcurrentGroup::new(cfg.nodes);
+  let mut group = ConcurrentGroup::new(cfg.nodes);
   let workers = cfg.workers;
   let ops = Arc::new(AtomicU64::new(0));
-  let threads = (0..workers).map(|worker_idx| {
-    let cfg = cfg.clone();
-    let ops = ops.clone();
-    let generator = Generator::new(cfg.clone());
-    // TODO: round robin
-    let client = group.nodes.iter().next().unwrap().1.client();
-    let thread_name = format!("worker-{}", worker_idx);
-    thread::Builder::new()
-      .name(thread_name)
-      .spawn(move || {
-        let a = Applier::new(cfg, ops, &generator, client);
-        let mut rng = SmallRng::seed_from_u64(worker_idx);
-        extreme::run(a.worker(worker_idx, &mut rng))
+  // Shared so that every write payload is unique, which validate relies on.
+  let generator = Arc::new(Generator::new(cfg.clone()));
+  let threads: Vec<_> = (0..workers)
+    .map(|worker_idx| {
+      let cfg = cfg.clone();
+      let ops = ops.clone();
+      let generator = generator.clone();
+      let clients = group.nodes.iter().map(|(id, node)| (*id, node.client())).collect();
+      let thread_name = format!("worker-{}", worker_idx);
+      thread::Builder::new()
+        .name(thread_name)
+        .spawn(move || {
+          let mut rng = SmallRng::seed_from_u64(cfg.seed.wrapping_add(worker_idx));
+          let a = Applier::new(cfg, ops, &generator, clients);
+          extreme::run(a.worker(worker_idx, &mut rng))
+        })
+        .expect("WIP")
+    })
+    .collect();
+
+  let mut crashes = cfg.crashes.clone();
+  crashes.sort();
+  for (op, node) in crashes {
+    while ops.load(Ordering::SeqCst) < op {
+      thread::sleep(Duration::from_millis(1));
+    }
+    let node = group.nodes.get_mut(&node).expect("unknown node");
+    node.crash();
+    thread::sleep(cfg.downtime);
+    node.restart();
+  }
+
+  let results: Vec<_> = threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect();
+  let history = History::new(&cfg, &results);
+  let res = validate(results);
+  let path = match (&cfg.history, &res) {
+    (Some(path), _) => path.clone(),
+    (None, Err(_)) => {
+      let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+      let name = format!("rast-nemesis-{}-{}.json", process::id(), now.as_nanos());
+      std::env::temp_dir().join(name)
+    }
+    (None, Ok(_)) => return res,
+  };
+  let written = match history.save(&path) {
+    Ok(()) => format!("history written to {}", path.display()),
+    Err(err) => format!("writing history to {}: {}", path.display(), err),
+  };
+  res.map_err(|mut err| {
+    err.errs.push(written);
+    err
+  })
+}
+
+/// Re-runs validation on a history written by [`nemesis_test`].
+pub fn validate_saved(path: &Path) -> Result<(), ValidateError> {
+  let history = History::load(path)
+    .map_err(|err| ValidateError { errs: vec![format!("reading {}: {}", path.display(), err)] })?;
+  validate(history.ops())
+}
+
+// The serialized form of a nemesis run: its config, including the fault
+// schedule and seed, and every op that was run. Instants are written as
+// nanoseconds since the start of the first op.
+#[derive(Debug, Serialize, Deserialize)]
+struct History {
+  nodes: u64,
+  workers: u64,
+  ops: u64,
+  read: u64,
+  write: u64,
+  crashes: Vec<(u64, u64)>,
+  downtime_nanos: u64,
+  seed: u64,
+  history: Vec<OpRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum OpRecord {
+  Read {
+    worker: u64,
+    start_nanos: u64,
+    finish_nanos: u64,
+    payload: Vec<u8>,
+    res: Result<ReadResRecord, ClientErrorRecord>,
+  },
+  Write {
+    worker: u64,
+    start_nanos: u64,
+    finish_nanos: u64,
+    payload: Vec<u8>,
+    res: Result<WriteResRecord, ClientErrorRecord>,
+  },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReadResRecord {
+  term: u64,
+  index: u64,
+  payload: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WriteResRecord {
+  term: u64,
+  index: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum ClientErrorRecord {
+  NotLeader { hint: Option<u64> },
+  Persist { message: String },
+  UnknownIndex { index: u64 },
+}
+
+impl ClientErrorRecord {
+  fn new(err: &ClientError) -> ClientErrorRecord {
+    match err {
+      ClientError::NotLeaderError(err) => {
+        ClientErrorRecord::NotLeader { hint: err.hint.map(|hint| hint.0) }
+      }
+      ClientError::PersistError(err) => ClientErrorRecord::Persist { message: err.message.clone() },
+      ClientError::UnknownIndex(index) => ClientErrorRecord::UnknownIndex { index: index.0 },
+    }
+  }
+
+  fn err(&self) -> ClientError {
+    match self {
+      ClientErrorRecord::NotLeader { hint } => {
+        ClientError::NotLeaderError(NotLeaderError::new(hint.map(NodeID)))
+      }
+      ClientErrorRecord::Persist { message } => {
+        ClientError::PersistError(PersistError::new(message.clone()))
+      }
+      ClientErrorRecord::UnknownIndex { index } => ClientError::UnknownIndex(Index(*index)),
+    }
+  }
+}
+
+impl History {
+  fn new(cfg: &Config, ops: &[Op]) -> History {
+    let base = ops
+      .iter()
+      .map(|op| match op {
+        Op::Read(read) => read.start,
+        Op::Write(write) => write.start,
+      })
+      .min()
+      .unwrap_or_else(Instant::now);
+    let nanos = |t: Instant| t.duration_since(base).as_nanos() as u64;
+    let history = ops
+      .iter()
+      .map(|op| match op {
+        Op::Read(read) => OpRecord::Read {
+          worker: read.worker_idx,
+          start_nanos: nanos(read.start),
+          finish_nanos: nanos(read.finish),
+          payload: read.req.payload.clone(),
+          res: match &read.res {
+            Ok(res) => Ok(ReadResRecord {
+              term: res.term.0,
+              index: res.index.0,
+              payload: res.payload.clone(),
+            }),
+            Err(err) => Err(ClientErrorRecord::new(err)),
+          },
+        },
+        Op::Write(write) => OpRecord::Write {
+          worker: write.worker_idx,
+          start_nanos: nanos(write.start),
+          finish_nanos: nanos(write.finish),
+          payload: write.req.payload.clone(),
+          res: match &write.res {
+            Ok(res) => Ok(WriteResRecord { term: res.term.0, index: res.index.0 }),
+            Err(err) => Err(ClientErrorRecord::new(err)),
+          },
+        },
+      })
+      .collect();
+    History {
+      nodes: cfg.nodes,
+      workers: cfg.workers,
+      ops: cfg.ops,
+      read: cfg.read,
+      write: cfg.write,
+      crashes: cfg.crashes.iter().map(|(op, node)| (*op, node.0)).collect(),
+      downtime_nanos: cfg.downtime.as_nanos() as u64,
+      seed: cfg.seed,
+      history,
+    }
+  }
+
+  fn save(&self, path: &Path) -> io::Result<()> {
+    let f = File::create(path)?;
+    serde_json::to_writer_pretty(f, self)?;
+    Ok(())
+  }
+
+  fn load(path: &Path) -> io::Result<History> {
+    let f = File::open(path)?;
+    Ok(serde_json::from_reader(f)?)
+  }
+
+  // Returns the recorded ops. They're rebased onto the current time, which
+  // preserves their ordering.
+  fn ops(&self) -> Vec<Op> {
+    let base = Instant::now();
+    let at = |nanos: u64| base + Duration::from_nanos(nanos);
+    self
+      .history
+      .iter()
+      .map(|op| match op {
+        OpRecord::Read { worker, start_nanos, finish_nanos, payload, res } => Op::Read(ReadOp {
+          worker_idx: *worker,
+          start: at(*start_nanos),
+          req: ReadReq { consistency: ReadConsistency::Linearizable, payload: payload.clone() },
+          res: match res {
+            Ok(res) => Ok(ReadRes {
+              term: Term(res.term),
+              index: Index(res.index),
+              payload: res.payload.clone(),
+            }),
+            Err(err) => Err(err.err()),
+          },
+          finish: at(*finish_nanos),
+        }),
+        OpRecord::Write { worker, start_nanos, finish_nanos, payload, res } => Op::Write(WriteOp {
+          worker_idx: *worker,
+          start: at(*start_nanos),
+          req: WriteReq { payload: payload.clone() },
+          res: match res {
+            Ok(res) => Ok(WriteRes { term: Term(res.term), index: Index(res.index) }),
+            Err(err) => Err(err.err()),
+          },
+          finish: at(*finish_nanos),
+        }),
       })
-      .expect("WIP")
-  });
-  let results: Vec<_> = threads.flat_map(|thread| thread.join().unwrap()).collect();
-  validate(results)
+      .collect()
+  }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -195,59 +445,75 @@ impl Error for ValidateError {
 // - reads and writes from a single client are ordered
 // - raft invariant: election safety
 // - raft invariant: log matching
+//
+// The first four are covered by checking that the history is linearizable. The
+// raft invariants are checked by testutil::Invariants in the deterministic
+// harness.
 
-fn debug_print(payload: &Vec<u8>) -> &str {
-  match std::str::from_utf8(payload) {
-    Ok(payload) => payload,
-    Err(_) => "",
-  }
-}
-
-fn validate(mut ops: Vec<Op>) -> Result<(), ValidateError> {
-  // Sort everything by index, all of the logic below depends on it. Since a
-  // read of a given index _includes_ any writes at that index, sort writes
-  // before reads.
-  ops.sort_by_key(|op| match op {
-    Op::Write(write) => write.res.clone().map(|res| (res.index, 0)).ok(),
-    Op::Read(read) => read.res.clone().map(|res| (res.index, 1)).ok(),
-  });
-
-  let mut replicated_state: BTreeMap<Index, Vec<u8>> = BTreeMap::new();
+fn validate(ops: Vec<Op>) -> Result<(), ValidateError> {
   let mut errors: Vec<String> = Vec::new();
+
+  // Raft never acknowledges two writes at the same log index. This is cheap to
+  // check and says more about what went wrong than the linearizability checker
+  // would.
+  let mut indexes: HashSet<Index> = HashSet::new();
   for op in ops.iter() {
-    match op {
-      Op::Write(write) => write.res.iter().for_each(|res| {
-        let mut new_state = replicated_state.iter().next_back().map_or(vec![], |(index, state)| {
-          if *index >= res.index {
-            todo!()
-          }
-          state.clone()
-        });
-        new_state.extend(write.req.payload.iter());
-        replicated_state.insert(res.index, new_state);
-      }),
-      Op::Read(read) => read.res.iter().for_each(|res| {
-        let expected =
-          replicated_state.iter().next_back().map_or(vec![], |(_, state)| state.clone());
-        if res.payload != expected {
-          errors.push(format!(
-            "read at {:?} expected {:?} got {:?}",
-            res.index.0,
-            debug_print(&expected),
-            debug_print(&res.payload)
-          ));
-        }
-      }),
+    if let Op::Write(WriteOp { res: Ok(res), .. }) = op {
+      if !indexes.insert(res.index) {
+        errors.push(format!("multiple writes acknowledged at {:?}", res.index.0));
+      }
     }
   }
 
-  if errors.len() == 0 {
+  // Whether the history is linearizable is what decides the result.
+  if let Err(err) = check(&AppendLog, &history(&ops)) {
+    errors.push(err.to_string());
+  }
+
+  if errors.is_empty() {
     Ok(())
   } else {
     Err(ValidateError { errs: errors })
   }
 }
 
+fn history(ops: &[Op]) -> Vec<Operation<AppendLogInput, AppendLogOutput>> {
+  let reads: Vec<&Vec<u8>> = ops
+    .iter()
+    .filter_map(|op| match op {
+      Op::Read(ReadOp { res: Ok(res), .. }) => Some(&res.payload),
+      _ => None,
+    })
+    .collect();
+  ops
+    .iter()
+    .filter_map(|op| match op {
+      Op::Read(read) => read.res.as_ref().ok().map(|res| Operation {
+        client: read.worker_idx,
+        input: AppendLogInput::Read,
+        call: read.start,
+        output: Some((AppendLogOutput::Read(res.payload.clone()), read.finish)),
+      }),
+      // A failed read tells us nothing. A failed write may or may not have been
+      // applied, but if no read ever saw it then it's equivalent to pretend it
+      // wasn't. Leaving those out keeps the search from guessing about them.
+      Op::Write(write) => match &write.res {
+        Err(_) if !reads.iter().any(|read| contains(read, &write.req.payload)) => None,
+        res => Some(Operation {
+          client: write.worker_idx,
+          input: AppendLogInput::Append(write.req.payload.clone()),
+          call: write.start,
+          output: res.as_ref().ok().map(|_| (AppendLogOutput::Append, write.finish)),
+        }),
+      },
+    })
+    .collect()
+}
+
+fn contains(payload: &[u8], needle: &[u8]) -> bool {
+  needle.is_empty() || payload.windows(needle.len()).any(|window| window == needle)
+}
+
 #[cfg(test)]
 mod tests {
   #![allow(clippy::wildcard_imports)]
@@ -258,7 +524,7 @@ mod tests {
       worker_idx: 0,
       start: Instant::now(),
       finish: Instant::now(),
-      req: ReadReq { payload: vec![] },
+      req: ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] },
       res: Ok(ReadRes { term: Term(1), index: Index(index), payload: payload.as_bytes().to_vec() }),
     })
   }
@@ -310,7 +576,9 @@ mod tests {
         read(1, "1"),
         write(2, "1"),
       ];
-      assert_eq!(validate(ops), err(r#"read at 1 expected "" got "1""#));
+      let errs = validate(ops).unwrap_err().errs;
+      assert_eq!(errs.len(), 1);
+      assert!(errs[0].starts_with("not linearizable: "));
     }
 
     // Writes in wrong order
@@ -321,14 +589,123 @@ mod tests {
         write(2, "2"),
         read(2, "21"),
       ];
-      assert_eq!(validate(ops), err(r#"read at 2 expected "12" got "21""#));
+      let errs = validate(ops).unwrap_err().errs;
+      assert_eq!(errs.len(), 1);
+      assert!(errs[0].starts_with("not linearizable: "));
     }
+
+    // Two writes acknowledged at the same index
+    {
+      #[rustfmt::skip]
+      let ops = vec![
+        write(1, "1"),
+        write(1, "2"),
+      ];
+      assert_eq!(validate(ops), err("multiple writes acknowledged at 1"));
+    }
+
+    // A failed write that was applied anyway, before a write whose payload
+    // contains it
+    {
+      let failed = Op::Write(WriteOp {
+        worker_idx: 1,
+        start: Instant::now(),
+        finish: Instant::now(),
+        req: WriteReq { payload: b"a".to_vec() },
+        res: Err(ClientError::NotLeaderError(NotLeaderError::new(None))),
+      });
+      #[rustfmt::skip]
+      let ops = vec![
+        failed,
+        write(2, "ab"),
+        read(2, "aab"),
+      ];
+      assert_eq!(validate(ops), Ok(()));
+    }
+
+    // Read started after a write finished but doesn't see it
+    {
+      #[rustfmt::skip]
+      let ops = vec![
+        write(2, "2"),
+        read(1, ""),
+      ];
+      let errs = validate(ops).unwrap_err().errs;
+      assert_eq!(errs.len(), 1);
+      assert!(errs[0].starts_with("not linearizable: [client 0 Append([50]) at 0ns -> Append"));
+    }
+  }
+
+  #[test]
+  fn saved_history() {
+    testutil::log_init();
+    let path = std::env::temp_dir().join(format!("rast-saved-history-{}.json", process::id()));
+    let cfg = Config {
+      nodes: 1,
+      workers: 1,
+      ops: 3,
+      read: 50,
+      write: 50,
+      crashes: vec![(1, NodeID(0))],
+      downtime: Duration::from_millis(1),
+      seed: 7,
+      history: None,
+    };
+
+    // A history that fails validation round trips, errors and all.
+    #[rustfmt::skip]
+    let ops = vec![
+      write(1, "1"),
+      read(1, ""),
+      Op::Write(WriteOp {
+        worker_idx: 1,
+        start: Instant::now(),
+        finish: Instant::now(),
+        req: WriteReq { payload: b"2".to_vec() },
+        res: Err(ClientError::NotLeaderError(NotLeaderError::new(Some(NodeID(2))))),
+      }),
+    ];
+    History::new(&cfg, &ops).save(&path).expect("save");
+    let loaded = History::load(&path).expect("load");
+    assert_eq!(loaded.seed, 7);
+    assert_eq!(loaded.crashes, vec![(1, 0)]);
+    // Instants are rebased when loaded, but the times relative to the first op
+    // are the same.
+    let json = |ops| serde_json::to_string(&History::new(&cfg, ops)).expect("json");
+    assert_eq!(json(&loaded.ops()), json(&ops));
+    assert_eq!(validate_saved(&path), validate(ops));
+    let errs = validate_saved(&path).unwrap_err().errs;
+    assert_eq!(errs.len(), 1);
+    assert!(errs[0].starts_with("not linearizable: "));
+
+    std::fs::remove_file(&path).expect("remove");
+    assert!(validate_saved(&path).is_err());
+  }
+
+  // Re-checks a history saved by a failed nemesis run. Run it with
+  // `RAST_NEMESIS_HISTORY=<path> cargo test nemesis_replay -- --ignored`.
+  #[test]
+  #[ignore]
+  fn nemesis_replay() {
+    testutil::log_init();
+    let path = std::env::var("RAST_NEMESIS_HISTORY").expect("RAST_NEMESIS_HISTORY is not set");
+    validate_saved(Path::new(&path)).expect("consistency violation");
   }
 
   #[test]
   fn nemesis_single() {
     testutil::log_init();
-    let cfg = Config { nodes: 1, workers: 4, ops: 100, read: 50, write: 50 };
+    let cfg = Config {
+      nodes: 1,
+      workers: 4,
+      ops: 100,
+      read: 50,
+      write: 50,
+      crashes: vec![],
+      downtime: Duration::from_millis(0),
+      seed: 0,
+      history: None,
+    };
     let failures = nemesis_test(cfg);
     failures.expect("consistency violation");
   }
@@ -336,7 +713,35 @@ mod tests {
   #[test]
   fn nemesis_multi() {
     testutil::log_init();
-    let cfg = Config { nodes: 3, workers: 4, ops: 100, read: 50, write: 50 };
+    let cfg = Config {
+      nodes: 3,
+      workers: 4,
+      ops: 100,
+      read: 50,
+      write: 50,
+      crashes: vec![],
+      downtime: Duration::from_millis(0),
+      seed: 0,
+      history: None,
+    };
+    let failures = nemesis_test(cfg);
+    failures.expect("consistency violation");
+  }
+
+  #[test]
+  fn nemesis_crash() {
+    testutil::log_init();
+    let cfg = Config {
+      nodes: 3,
+      workers: 4,
+      ops: 300,
+      read: 50,
+      write: 50,
+      crashes: vec![(50, NodeID(0)), (100, NodeID(1)), (150, NodeID(2)), (200, NodeID(0))],
+      downtime: Duration::from_millis(50),
+      seed: 0,
+      history: None,
+    };
     let failures = nemesis_test(cfg);
     failures.expect("consistency violation");
   }
@@ -1,22 +1,47 @@
 // Copyright 2020 Daniel Harrison. All Rights Reserved.
 
 use std::cmp;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::iter::Extend;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use super::compressed_log::CompressedLog;
 pub use super::error::*;
+use super::event::{Event, EventSink, StepDownReason, VoteDeniedReason};
 pub use super::future::*;
+use super::metrics::{Metrics, NoopMetrics};
 pub use super::serde::*;
 
 /// Raft tunables.
 ///
 /// The configuration of all nodes in a group should match, but certain
 /// variations between them are permissible when altering the configuration in a
-/// rolling restart. TODO: Describe how this would work.
+/// rolling restart. The timing tunables (`election_timeout` and
+/// `heartbeat_interval`) only affect when the local node calls an election or
+/// sends a heartbeat, so they may differ between nodes, as long as every
+/// leader's `heartbeat_interval` stays less than every follower's
+/// `election_timeout`. To shorten the election timeout, first lower the
+/// heartbeat interval on every node, then the election timeout. To lengthen the
+/// heartbeat interval, do the reverse. Each step can be applied to a live node
+/// with [`Raft::update_config`] instead of a restart.
+///
+/// The `cluster_id` of every node in a group must match and can't be changed.
+/// Rolling upgrades to a release with a new [`PROTOCOL_VERSION`] use
+/// `protocol_version`: first upgrade every node while leaving it at the old
+/// version, then raise it on every node.
 #[derive(Debug, Clone)]
 pub struct Config {
+  /// The group this node belongs to. Rpcs from nodes in any other group are
+  /// rejected, so a node pointed at the wrong group can't disrupt it.
+  pub cluster_id: ClusterID,
+  /// The protocol version this node speaks to its peers. It must be between 1
+  /// and [`PROTOCOL_VERSION`], inclusive.
+  ///
+  /// A node accepts rpcs at any version it understands, but only sends ones at
+  /// this version, so nothing is sent that a node running an older release
+  /// can't decode (such as a payload added in a later version).
+  pub protocol_version: u32,
   /// The interval after which a node will assume the current leader is dead and
   /// call an election. TODO: Notes on tuning this.
   pub election_timeout: Duration,
@@ -26,9 +51,38 @@ pub struct Config {
   pub heartbeat_interval: Duration,
 }
 
+/// The newest protocol version this release understands, see
+/// [`Config::protocol_version`].
+pub const PROTOCOL_VERSION: u32 = 1;
+
+impl Config {
+  /// Returns an error describing the first unsafe combination of tunables in
+  /// this config, if any.
+  pub fn validate(&self) -> Result<(), ConfigError> {
+    if self.protocol_version == 0 || self.protocol_version > PROTOCOL_VERSION {
+      return Err(ConfigError::UnsupportedProtocolVersion(self.protocol_version));
+    }
+    if self.election_timeout == Duration::from_secs(0) {
+      return Err(ConfigError::ZeroElectionTimeout);
+    }
+    if self.heartbeat_interval == Duration::from_secs(0) {
+      return Err(ConfigError::ZeroHeartbeatInterval);
+    }
+    if self.heartbeat_interval >= self.election_timeout {
+      return Err(ConfigError::HeartbeatNotLessThanElectionTimeout {
+        heartbeat_interval: self.heartbeat_interval,
+        election_timeout: self.election_timeout,
+      });
+    }
+    Ok(())
+  }
+}
+
 impl Default for Config {
   fn default() -> Config {
     Config {
+      cluster_id: ClusterID(0),
+      protocol_version: PROTOCOL_VERSION,
       election_timeout: Duration::from_millis(100),
       heartbeat_interval: Duration::from_millis(10),
     }
@@ -58,8 +112,9 @@ pub enum Input<'a> {
   Read(ReadReq, ReadFuture),
   /// A communication to the Raft logic of the current time.
   ///
-  /// Correctness of this Raft implementation (including reads) is entirely
-  /// independant from clocks. However, Raft very much relies on a periodic
+  /// Correctness of this Raft implementation (including reads, other than
+  /// [`ReadConsistency::MaxStaleness`] ones) is entirely independant from
+  /// clocks. However, Raft very much relies on a periodic
   /// clock tick for availability. If ticks are delayed, unnecessary elections
   /// and leadership transfers will happen, which affects tail latencies.
   ///
@@ -71,6 +126,8 @@ pub enum Input<'a> {
   PersistRes(PersistRes),
   /// A communication that a [`Output::ReadStateMachineReq`] has completed.
   ReadStateMachineRes(ReadStateMachineRes),
+  /// A communication that a [`Output::ReadLogReq`] has completed.
+  ReadLogRes(ReadLogRes),
 }
 
 /// An owned version of [`Input`].
@@ -88,6 +145,8 @@ pub enum OwnedInput {
   PersistRes(PersistRes),
   /// An owned version of [`Input::ReadStateMachineRes`].
   ReadStateMachineRes(ReadStateMachineRes),
+  /// An owned version of [`Input::ReadLogRes`].
+  ReadLogRes(ReadLogRes),
 }
 
 impl OwnedInput {
@@ -101,6 +160,7 @@ impl OwnedInput {
       OwnedInput::Message(msg) => Input::Message(msg.capnp_as_ref()),
       OwnedInput::PersistRes(res) => Input::PersistRes(res.clone()),
       OwnedInput::ReadStateMachineRes(res) => Input::ReadStateMachineRes(res.clone()),
+      OwnedInput::ReadLogRes(res) => Input::ReadLogRes(res.clone()),
     }
   }
 }
@@ -114,6 +174,7 @@ impl<'a> From<Input<'a>> for OwnedInput {
       Input::Message(msg) => OwnedInput::Message(msg.capnp_to_owned()),
       Input::PersistRes(res) => OwnedInput::PersistRes(res),
       Input::ReadStateMachineRes(res) => OwnedInput::ReadStateMachineRes(res),
+      Input::ReadLogRes(res) => OwnedInput::ReadLogRes(res),
     }
   }
 }
@@ -128,7 +189,24 @@ impl<'a> From<Input<'a>> for OwnedInput {
 /// for availability if messages between any two nodes are a delivered in order.
 ///
 /// All disk outputs must be processed and in the order they are emitted. This
-/// applies to the `PersistReq`, `ApplyReq`, and `ReadStateMachineReq` outputs.
+/// applies to the `PersistReq`, `ApplyReq`, `ReadStateMachineReq`, and
+/// `ReadLogReq` outputs.
+///
+/// # Catching up followers
+///
+/// A leader only keeps the terms of its log's entries in memory, so it reads
+/// the entries a follower is missing back from the log with a `ReadLogReq`.
+/// One is emitted when a follower rejects an AppendEntries because its log
+/// doesn't have the entry the new ones follow. The leader moves its next index
+/// for that follower back, skipping to just after the follower's last entry if
+/// that's earlier, and requests every entry from there through the end of its
+/// own log. Nothing is requested if that range is empty.
+///
+/// A `ReadLogReq` is a disk output, so it must be processed after every
+/// `PersistReq` emitted before it. That way the read sees every entry the
+/// leader has appended so far. The resulting [`Input::ReadLogRes`] may arrive
+/// after the node stopped being leader of the term it was requested in, in
+/// which case it's ignored.
 #[derive(Debug)]
 pub enum Output {
   /// An rpc to be sent to another node by the runtime.
@@ -141,15 +219,26 @@ pub enum Output {
   PersistReq(PersistReq),
   /// A request that the given entries be applied to the state machine.
   ///
+  /// Entries with a kind of [`EntryKind::Noop`] are written by Raft itself and
+  /// must be skipped when applying.
+  ///
   /// No communication of completion is necessary but processing this request is
   /// subject to the ordering requirements described on [`Output`].
   ApplyReq(Index),
-  /// A request that the state machine's current state be read.
+  /// A request that the state machine's current state be read, once for each
+  /// of a batch of reads.
   ///
   /// Completion is communciated to Raft by an [`Input::ReadStateMachineRes`].
   /// Processing this request is subject to the ordering requirements described
   /// on [`Output`].
   ReadStateMachineReq(ReadStateMachineReq),
+  /// A request that a range of entries be read back from the Raft log.
+  ///
+  /// A leader uses this to resend entries to a peer that is missing them.
+  /// Completion is communciated to Raft by an [`Input::ReadLogRes`]. Processing
+  /// this request is subject to the ordering requirements described on
+  /// [`Output`].
+  ReadLogReq(ReadLogReq),
 }
 
 /// See [`Output::PersistReq`].
@@ -161,7 +250,18 @@ pub struct PersistReq {
   /// This must be copied to the resulting `PersistRes`.
   pub read_id: ReadID,
   /// The Raft log entries to be durably persisted to disk.
+  ///
+  /// This is empty when a node whose storage failed is checking whether it has
+  /// recovered. The write must still be attempted (for example, by syncing)
+  /// and fail if the storage is still failing.
   pub entries: Vec<EntryShared>,
+  /// This must be copied to the resulting `PersistRes`.
+  ///
+  /// Raft increments this each time a write fails. Once a write fails, every
+  /// later `PersistReq` with the same (or a lesser) generation must fail too,
+  /// without writing anything, because the log may be missing entries that
+  /// they follow.
+  pub generation: u64,
 }
 
 /// See [`Input::PersistRes`].
@@ -174,36 +274,140 @@ pub struct PersistRes {
   pub read_id: ReadID,
   /// TODO: Remove this.
   pub log_index: Index,
+  /// This must be copied from the corresponding `PersistReq`.
+  pub generation: u64,
+  /// Set if the entries couldn't be durably persisted, for example because the
+  /// disk is full.
+  ///
+  /// Some of the entries may have been written anyway. Until a write succeeds
+  /// again, the node won't vote or campaign for leadership. Every later write
+  /// with the same (or a lesser) generation must fail too, see
+  /// [`Output::PersistReq`].
+  pub error: Option<PersistError>,
 }
 
 /// See [`Output::ReadStateMachineReq`].
 #[derive(Debug)]
 pub struct ReadStateMachineReq {
-  /// This must be copied to the resulting `ReadStateMachineRes`. TODO: Remove
-  /// this.
+  /// The index the state machine has been applied through. This must be copied
+  /// to the resulting `ReadStateMachineRes`. TODO: Remove this.
   pub index: Index,
-  /// This must be copied to the resulting `ReadStateMachineRes`.
-  pub read_id: ReadID,
-  /// The read payload to be handed to the replicated state machine.
+  /// The reads to serve, all from the same state. Each has an id, which must
+  /// be copied to the resulting `ReadStateMachineRes`, and the read payload to
+  /// be handed to the replicated state machine.
   ///
-  /// For example: This could be a key when the replicated state machine is a
-  /// key-value store.
-  pub payload: Vec<u8>,
+  /// For example: The payloads could be keys when the replicated state machine
+  /// is a key-value store.
+  pub reads: Vec<(ReadID, Vec<u8>)>,
 }
 
 /// See [`Input::ReadStateMachineRes`].
 #[derive(Clone, Debug)]
 pub struct ReadStateMachineRes {
-  /// This must be copied from the corresponding `PersistReq`. TODO: Remove
-  /// this.
+  /// This must be copied from the corresponding `ReadStateMachineReq`. TODO:
+  /// Remove this.
   pub index: Index,
-  /// This must be copied from the corresponding `PersistReq`.
-  pub read_id: ReadID,
-  /// The result of reading the state machine with the request's payload.
+  /// The id of each read in the corresponding `ReadStateMachineReq` and the
+  /// result of reading the state machine with its payload.
   ///
-  /// For example: This could be a value when the replicated state machine is a
-  /// key-value store.
-  pub payload: Vec<u8>,
+  /// For example: The results could be values when the replicated state
+  /// machine is a key-value store.
+  pub reads: Vec<(ReadID, Vec<u8>)>,
+}
+
+/// See [`Output::ReadLogReq`].
+#[derive(Debug)]
+pub struct ReadLogReq {
+  /// The peer the entries will be sent to. This must be copied to the
+  /// resulting `ReadLogRes`.
+  pub peer: NodeID,
+  /// The term of the leader that requested the entries. This must be copied to
+  /// the resulting `ReadLogRes`.
+  pub term: Term,
+  /// The index of the first entry to read.
+  pub start: Index,
+  /// The index of the last entry to read.
+  pub end: Index,
+}
+
+/// See [`Input::ReadLogRes`].
+#[derive(Clone, Debug)]
+pub struct ReadLogRes {
+  /// This must be copied from the corresponding `ReadLogReq`.
+  pub peer: NodeID,
+  /// This must be copied from the corresponding `ReadLogReq`.
+  pub term: Term,
+  /// Every entry in the log from the request's `start` through its `end`, in
+  /// order.
+  pub entries: Vec<EntryShared>,
+}
+
+/// The state a node must persist before it sends any rpc that depends on it, so
+/// that it's still there when the node restarts.
+///
+/// See [`Raft::hard_state`] and [`Raft::restart`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardState {
+  /// The latest term the node has seen.
+  pub current_term: Term,
+  /// The candidate the node voted for in `current_term`, if any.
+  pub voted_for: Option<NodeID>,
+}
+
+/// The role a node is currently playing in its Raft group.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+  /// The node is campaigning for (or waiting to campaign for) leadership.
+  Candidate,
+  /// The node is replicating the log of a leader.
+  Follower,
+  /// The node is the leader and is serving reads and writes.
+  Leader,
+}
+
+/// A point-in-time snapshot of a node's view of its Raft group.
+///
+/// See [`Raft::status`].
+#[derive(Debug, Clone)]
+pub struct Status {
+  /// The unique id of this node.
+  pub id: NodeID,
+  /// The role this node is currently playing.
+  pub role: Role,
+  /// The latest term this node has seen.
+  pub current_term: Term,
+  /// The candidate this node voted for in `current_term`, if any.
+  pub voted_for: Option<NodeID>,
+  /// The node this node believes to be the leader, if any.
+  pub leader_hint: Option<NodeID>,
+  /// The highest log index known to be committed.
+  pub commit_index: Index,
+  /// The highest log index handed to the state machine to be applied.
+  pub last_applied: Index,
+  /// The term and index of the first entry in the log.
+  pub log_first: (Term, Index),
+  /// The term and index of the last entry in the log.
+  pub log_last: (Term, Index),
+  /// Replication progress of every other node in the group.
+  ///
+  /// This is only tracked by leaders and is empty for any other role.
+  pub peers: Vec<PeerStatus>,
+}
+
+/// A leader's view of the replication progress of one of its peers.
+///
+/// See [`Status::peers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerStatus {
+  /// The unique id of the peer.
+  pub id: NodeID,
+  /// The highest log index known to be replicated on the peer.
+  pub match_index: Index,
+  /// The index of the next log entry to send to the peer.
+  pub next_index: Index,
+  /// The time (as of the most recent [`Input::Tick`]) at which this leader last
+  /// heard from the peer, if ever.
+  pub last_contact: Option<Instant>,
 }
 
 /// An implementation of the [raft consensus protocol].
@@ -261,6 +465,8 @@ impl Raft {
   /// all-time. It must be reused if the node restarts and cannot ever be reused
   /// (whether by another node or this one if it loses data). The `peers` must
   /// contain all nodes in the group, including this one.
+  ///
+  /// The `cfg` is not checked, see [`Config::validate`].
   pub fn new(id: NodeID, peers: Vec<NodeID>, cfg: Config) -> Raft {
     let state = State::Candidate(Candidate {
       shared: SharedState {
@@ -274,8 +480,72 @@ impl Raft {
         peers: peers,
         current_time: None,
         last_communication: None,
+        persisted: Index(0),
+        persist_generation: 0,
+        persist_inflight: 0,
+        persist_superseded: 0,
+        persist_truncated: Index(0),
+        storage_failed: false,
+        storage_probe: None,
+        next_read_id: ReadID(0),
+        leader_commit: None,
+        waiting_reads: BTreeMap::new(),
+        local_reads: BTreeMap::new(),
+        metrics: Arc::new(NoopMetrics),
+        events: None,
+      },
+      received_votes: None,
+    });
+    Raft { state: Some(state) }
+  }
+
+  /// Returns a Raft node restarted from what it persisted before it stopped.
+  ///
+  /// The `hard_state` must be the last one persisted and `entries` must be every
+  /// entry in the node's log that was acknowledged as durable by a
+  /// [`Input::PersistRes`], in order. Entries that were requested to be
+  /// persisted but never acknowledged may be included or left out. The `id`,
+  /// `peers`, and `cfg` are as described in [`Raft::new`].
+  ///
+  /// Nothing is known about what's committed until the node hears from a
+  /// leader, so it starts over applying its log from the beginning.
+  pub fn restart(
+    id: NodeID,
+    peers: Vec<NodeID>,
+    cfg: Config,
+    hard_state: HardState,
+    entries: &[EntryRef<'_>],
+  ) -> Raft {
+    let mut log = CompressedLog::new();
+    log.extend(entries);
+    let persisted = log.last().1;
+    let state = State::Candidate(Candidate {
+      shared: SharedState {
+        id,
+        cfg,
+        current_term: hard_state.current_term,
+        voted_for: hard_state.voted_for,
+        log,
+        commit_index: Index(0),
+        last_applied: Index(0),
+        peers,
+        current_time: None,
+        last_communication: None,
+        persisted,
+        persist_generation: 0,
+        persist_inflight: 0,
+        persist_superseded: 0,
+        persist_truncated: Index(0),
+        storage_failed: false,
+        storage_probe: None,
+        next_read_id: ReadID(0),
+        leader_commit: None,
+        waiting_reads: BTreeMap::new(),
+        local_reads: BTreeMap::new(),
+        metrics: Arc::new(NoopMetrics),
+        events: None,
       },
-      received_votes: 0,
+      received_votes: None,
     });
     Raft { state: Some(state) }
   }
@@ -285,6 +555,70 @@ impl Raft {
     return self.state_ref().id();
   }
 
+  /// The tunables this node is currently running with.
+  pub fn config(&self) -> &Config {
+    &self.state_ref().shared().cfg
+  }
+
+  /// Replaces the tunables of this live node.
+  ///
+  /// The new timings take effect as of the next [`Input::Tick`] and the new
+  /// protocol version with the next rpc sent. An invalid `cfg` is rejected and
+  /// the current one is kept. See [`Config`] for how to safely roll a change
+  /// out to a group.
+  pub fn update_config(&mut self, cfg: Config) -> Result<(), ConfigError> {
+    cfg.validate()?;
+    if cfg.cluster_id != self.config().cluster_id {
+      return Err(ConfigError::ClusterIDChanged);
+    }
+    debug!("  {:3}: update_config {:?}", self.id().0, cfg);
+    // TODO: this is not actually "unreachable" if step panics, handle this
+    self.state.as_mut().expect("unreachable").shared_mut().cfg = cfg;
+    Ok(())
+  }
+
+  /// Returns the state this node must persist to be restarted with
+  /// [`Raft::restart`].
+  ///
+  /// TODO: This should be output for persistence as needed instead. Until it
+  /// is, it must be persisted after every call to [`step`](Raft::step) and
+  /// before any of the resulting outputs are processed.
+  pub fn hard_state(&self) -> HardState {
+    let shared = self.state_ref().shared();
+    HardState { current_term: shared.current_term, voted_for: shared.voted_for }
+  }
+
+  /// Returns a snapshot of this node's view of the Raft group.
+  ///
+  /// This is intended for admin tooling and health checks. It's a copy and so
+  /// does not update as the node continues to step.
+  pub fn status(&self) -> Status {
+    self.state_ref().status()
+  }
+
+  /// Installs a hook for recording metrics about this node.
+  ///
+  /// The [`runtime`](crate::runtime) also records to these metrics, so this
+  /// should be called before the node is handed to it.
+  pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+    // TODO: this is not actually "unreachable" if step panics, handle this
+    self.state.as_mut().expect("unreachable").shared_mut().metrics = metrics;
+  }
+
+  /// Returns the hook for recording metrics about this node.
+  pub fn metrics(&self) -> Arc<dyn Metrics> {
+    self.state_ref().shared().metrics.clone()
+  }
+
+  /// Installs a sink for a typed [`Event`] stream of this node's state
+  /// transitions.
+  ///
+  /// By default, no events are constructed.
+  pub fn set_events(&mut self, events: Arc<dyn EventSink>) {
+    // TODO: this is not actually "unreachable" if step panics, handle this
+    self.state.as_mut().expect("unreachable").shared_mut().events = Some(events);
+  }
+
   #[cfg(test)]
   pub fn current_time(&self) -> Option<Instant> {
     return self.state_ref().shared().current_time;
@@ -304,9 +638,28 @@ impl Raft {
   ///
   /// This is guaranteed to be non-blocking. Any blocking work (network/disk IO)
   /// is emitted as an [`Output`] entry.
-  pub fn step(&mut self, output: &mut impl Extend<Output>, input: Input) {
+  ///
+  /// An [`Input::Message`] that no correct peer would have sent is dropped
+  /// without affecting this node, and what was wrong with it is returned (and
+  /// emitted as an [`Event::MessageRejected`]). Every other input is accepted.
+  pub fn step(
+    &mut self,
+    output: &mut impl Extend<Output>,
+    input: Input,
+  ) -> Result<(), ProtocolError> {
+    if let Input::Message(message) = &input {
+      let state = self.state_ref();
+      if let Err(error) = state.validate(message) {
+        // NB: The message may not decode, so it isn't printed.
+        debug!("  {:3}: rejected message from {:?}: {}", state.id().0, message.src(), error);
+        let src = message.src();
+        state.shared().emit(|| Event::MessageRejected { src, error: error.clone() });
+        return Err(error);
+      }
+    }
     // TODO: this is not actually "unreachable" if step panics, handle this
     self.state = Some(self.state.take().expect("unreachable").step(output, input));
+    Ok(())
   }
 
   fn state_ref(&self) -> &State {
@@ -318,9 +671,11 @@ impl Raft {
   pub fn start_election(&mut self, output: &mut impl Extend<Output>, new_leader: NodeID) {
     let current_term = self.state_ref().shared().current_term;
     let msg = PayloadShared::StartElectionReq(StartElectionReqShared::new(current_term));
-    let msg = MessageShared::new(self.id(), new_leader, msg);
+    let msg = self.state_ref().shared().message(new_leader, msg);
     if msg.capnp_as_ref().dest() == self.id() {
-      self.step(output, Input::Message(msg.capnp_as_ref()));
+      // This node constructed the message itself, so it's valid.
+      let state = self.state.take().expect("unreachable");
+      self.state = Some(state.step(output, Input::Message(msg.capnp_as_ref())));
     } else {
       output.extend(vec![Output::Message(msg)]);
     }
@@ -350,29 +705,118 @@ struct SharedState {
   current_time: Option<Instant>,
   // TODO: this is overloaded fixme
   last_communication: Option<Instant>,
+  // The index of the last entry acknowledged as durable by a PersistRes.
+  persisted: Index,
+  // See PersistReq::generation.
+  persist_generation: u64,
+  // The number of PersistReqs output but not yet answered by a PersistRes and,
+  // of those, how many were output before the log was last truncated. Only the
+  // entries in a superseded write before persist_truncated are still the ones
+  // in the log.
+  persist_inflight: u64,
+  persist_superseded: u64,
+  persist_truncated: Index,
+  // Whether the last PersistRes failed.
+  storage_failed: bool,
+  // The ReadID of the outstanding write, with no entries, that checks whether
+  // failed storage has recovered. See start_election.
+  storage_probe: Option<ReadID>,
+  // invariant: every outgoing AppendEntries round and every read gets a ReadID
+  // that's unique for the lifetime of this node.
+  next_read_id: ReadID,
+  // When this node last heard from a leader and what that leader's commit
+  // index was, see ReadConsistency::MaxStaleness.
+  leader_commit: Option<(Instant, Index)>,
+  // Reads served from this node's applied state without confirming leadership,
+  // see ReadConsistency. The ones waiting for last_applied to reach the index
+  // they need are keyed by it and the ones handed to the state machine by the
+  // index they were served at.
+  //
+  // invariant: shared.last_applied < all waiting Indexes <= known_index()
+  // invariant: all local Indexes == shared.last_applied
+  waiting_reads: BTreeMap<(Index, ReadID), (ReadReq, ReadFuture)>,
+  local_reads: BTreeMap<(Index, ReadID), ReadFuture>,
+
+  metrics: Arc<dyn Metrics>,
+  events: Option<Arc<dyn EventSink>>,
+}
+
+impl SharedState {
+  // Returns a message from this node to the given one.
+  fn message(&self, dest: NodeID, payload: PayloadShared) -> MessageShared {
+    MessageShared::new(self.id, dest, payload, self.cfg.cluster_id, self.cfg.protocol_version)
+  }
+
+  fn emit(&self, event: impl FnOnce() -> Event) {
+    if let Some(events) = &self.events {
+      events.event(self.id, event());
+    }
+  }
+
+  fn next_read_id(&mut self) -> ReadID {
+    let read_id = self.next_read_id;
+    self.next_read_id = ReadID(read_id.0 + 1);
+    read_id
+  }
+
+  // The highest index this node knows will be in the log once it's caught up,
+  // see ReadConsistency::AtLeastIndex.
+  fn known_index(&self) -> Index {
+    cmp::max(self.log.last().1, self.leader_commit.map_or(Index(0), |(_, index)| index))
+  }
+
+  // Fails the waiting reads of any index this node no longer knows of, because
+  // the entries it had there were truncated. Otherwise they could wait forever.
+  fn fail_unknown_reads(&mut self) {
+    let known = self.known_index();
+    let unknown: Vec<_> =
+      self.waiting_reads.keys().filter(|(index, _)| *index > known).copied().collect();
+    for key in unknown {
+      if let Some((_, mut res)) = self.waiting_reads.remove(&key) {
+        res.fill(Err(ClientError::UnknownIndex(key.0)));
+      }
+    }
+  }
+
+  // Whether this node may start an election, see start_election.
+  fn can_campaign(&self) -> bool {
+    // The last possible term is never used, see ProtocolError::TermExhausted.
+    !self.storage_failed && self.current_term < Term(u64::MAX - 1)
+  }
 }
 
 struct Candidate {
   shared: SharedState,
 
-  received_votes: usize,
+  // NB: This is a set so that a duplicated vote isn't counted twice.
+  //
+  // None if this node hasn't started an election in the current term. It may
+  // have already been leader of this term, so it must not count any late votes
+  // for it.
+  received_votes: Option<HashSet<NodeID>>,
 }
 
 struct Leader {
   shared: SharedState,
 
-  _next_index: HashMap<NodeID, Index>,
+  next_index: HashMap<NodeID, Index>,
   match_index: HashMap<NodeID, (Index, ReadID)>,
-  write_buffer: HashMap<(Term, Index), WriteFuture>,
-
-  // invariant: every outgoing AppendEntries round gets a (Term, ReadID) that's
-  // unique all time.
-  next_read_id: ReadID,
-
+  last_contact: HashMap<NodeID, Instant>,
+  // The ReadID and send time of each recent AppendEntries round, oldest first,
+  // and for each peer, when the latest round it responded to was sent. The
+  // peer hadn't moved on to a later term as of then, see fresh_index.
+  rounds_sent: VecDeque<(ReadID, Instant)>,
+  acked_sent: HashMap<NodeID, Instant>,
+  // The time each write was proposed is kept for the commit latency metric.
+  write_buffer: HashMap<(Term, Index), (WriteFuture, Option<Instant>)>,
+
+  // Every AppendEntries round sent as leader of this term has a ReadID at or
+  // after this one.
+  first_read_id: ReadID,
   max_outstanding_read_id: Option<ReadID>,
   max_confirmed_read_id: Option<ReadID>,
 
-  // invariant: all ReadIDs < next_read_id
+  // invariant: all ReadIDs < shared.next_read_id
   // invariant: shared.last_applied <= all Indexes <= shared.commit_index
   read_buffer: BTreeMap<(Index, ReadID), (Option<ReadReq>, ReadFuture)>,
 }
@@ -380,7 +824,8 @@ struct Leader {
 struct Follower {
   shared: SharedState,
 
-  leader_hint: NodeID,
+  // None if this node stepped down on its own, without hearing of a new leader.
+  leader_hint: Option<NodeID>,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -413,6 +858,40 @@ impl State {
     }
   }
 
+  fn status(&self) -> Status {
+    let shared = self.shared();
+    let (role, leader_hint, peers) = match self {
+      State::Candidate(_) => (Role::Candidate, None, vec![]),
+      State::Follower(follower) => (Role::Follower, follower.leader_hint, vec![]),
+      State::Leader(leader) => {
+        let peers = shared
+          .peers
+          .iter()
+          .filter(|peer| **peer != shared.id)
+          .map(|peer| PeerStatus {
+            id: *peer,
+            match_index: leader.match_index.get(peer).map_or(Index(0), |(index, _)| *index),
+            next_index: leader.next_index.get(peer).copied().unwrap_or(Index(1)),
+            last_contact: leader.last_contact.get(peer).copied(),
+          })
+          .collect();
+        (Role::Leader, Some(shared.id), peers)
+      }
+    };
+    Status {
+      id: shared.id,
+      role,
+      current_term: shared.current_term,
+      voted_for: shared.voted_for,
+      leader_hint,
+      commit_index: shared.commit_index,
+      last_applied: shared.last_applied,
+      log_first: shared.log.first(),
+      log_last: shared.log.last(),
+      peers,
+    }
+  }
+
   // TODO: impl Debug instead
   #[cfg(test)]
   fn debug(&self) -> &'static str {
@@ -435,6 +914,7 @@ impl State {
       Input::Tick(now) => self.tick(output, now),
       Input::PersistRes(res) => self.persist_res(output, res),
       Input::ReadStateMachineRes(res) => self.read_state_machine_res(output, res),
+      Input::ReadLogRes(res) => self.read_log_res(output, res),
       Input::Message(message) => self.message(output, message),
     }
   }
@@ -442,13 +922,18 @@ impl State {
   fn maybe_wake_writes(mut leader: Leader) -> Leader {
     let current_term = leader.shared.current_term;
     let commit_index = leader.shared.commit_index;
+    let current_time = leader.shared.current_time;
+    let metrics = &leader.shared.metrics;
     #[cfg(feature = "log")]
     let id = leader.shared.id;
-    leader.write_buffer.retain(|(term, index), future| {
+    leader.write_buffer.retain(|(term, index), (future, proposed)| {
       debug_assert!(*term == current_term);
-      if *index >= commit_index {
+      if *index <= commit_index {
         let res = WriteRes { term: *term, index: *index };
         debug!("  {:3}: write success {:?}", id.0, res);
+        if let (Some(proposed), Some(current_time)) = (proposed, current_time) {
+          metrics.commit_latency(current_time.duration_since(*proposed));
+        }
         future.fill(Ok(res));
         false
       } else {
@@ -474,14 +959,52 @@ impl State {
     );
     // We can't advance this past any outstanding reads, else we'd make it
     // impossible to serve them later.
+    let local_bound = shared.local_reads.keys().next().map(|(index, _)| *index);
     let new_applied = upper_bound
-      .map_or(shared.commit_index, |upper_bound| cmp::min(upper_bound, shared.commit_index));
+      .into_iter()
+      .chain(local_bound)
+      .fold(shared.commit_index, |new_applied, upper_bound| cmp::min(upper_bound, new_applied));
     if new_applied > shared.last_applied {
       shared.last_applied = new_applied;
       output.extend(vec![Output::ApplyReq(shared.last_applied)]);
     }
   }
 
+  // Hands the state machine every waiting local read that last_applied has
+  // caught up to, in one batch.
+  fn maybe_serve_local_reads(shared: &mut SharedState, output: &mut impl Extend<Output>) {
+    let reads = State::ready_local_reads(shared);
+    State::serve_reads(shared, output, reads);
+  }
+
+  // Moves every waiting local read that last_applied has caught up to into
+  // local_reads, returning the id and payload of each.
+  fn ready_local_reads(shared: &mut SharedState) -> Vec<(ReadID, Vec<u8>)> {
+    let waiting = shared.waiting_reads.split_off(&(shared.last_applied + 1, ReadID(0)));
+    let ready = std::mem::replace(&mut shared.waiting_reads, waiting);
+    let mut reads = Vec::with_capacity(ready.len());
+    for ((_, read_id), (req, res)) in ready {
+      reads.push((read_id, req.payload));
+      shared.local_reads.insert((shared.last_applied, read_id), res);
+    }
+    reads
+  }
+
+  // Hands the state machine the given reads, all at last_applied, in one
+  // batch.
+  fn serve_reads(
+    shared: &mut SharedState,
+    output: &mut impl Extend<Output>,
+    reads: Vec<(ReadID, Vec<u8>)>,
+  ) {
+    if reads.is_empty() {
+      return;
+    }
+    shared.metrics.read_batch_size(reads.len());
+    let msg = ReadStateMachineReq { index: shared.last_applied, reads };
+    output.extend(vec![Output::ReadStateMachineReq(msg)]);
+  }
+
   fn leader_maybe_advance_reads(mut leader: Leader, output: &mut impl Extend<Output>) -> Leader {
     debug!(
       "  {:3}: leader_maybe_advance_reads max_applied={:?} outstanding={:?} confirmed={:?} read_buffer={:?}",
@@ -500,16 +1023,26 @@ impl State {
         leader = State::leader_heartbeat(leader, output);
       }
     }
+    State::leader_serve_reads(leader, output)
+  }
 
+  // Hands the state machine every read that's ready to be served, both the
+  // confirmed linearizable ones and the local ones.
+  fn leader_serve_reads(mut leader: Leader, output: &mut impl Extend<Output>) -> Leader {
     let can_serve = (leader.shared.last_applied, leader.max_confirmed_read_id.unwrap_or(ReadID(0)));
     debug!("  {:3}: can_serve={:?}", leader.shared.id.0, can_serve);
+    // NB: Because applying is held up by every buffered read, these are all
+    // at last_applied, so they're served from the same state in one batch.
+    let mut reads = vec![];
     for ((index, read_id), (req, _)) in leader.read_buffer.range_mut(..can_serve) {
+      debug_assert_eq!(*index, leader.shared.last_applied);
       // NB: Only do this once for each read.
       if let Some(req) = req.take() {
-        let msg = ReadStateMachineReq { index: *index, read_id: *read_id, payload: req.payload };
-        output.extend(vec![Output::ReadStateMachineReq(msg)]);
+        reads.push((*read_id, req.payload));
       }
     }
+    reads.extend(State::ready_local_reads(&mut leader.shared));
+    State::serve_reads(&mut leader.shared, output, reads);
     leader
   }
 
@@ -530,9 +1063,16 @@ impl State {
     }
     match self {
       State::Leader(leader) => {
-        State::Leader(State::leader_write(leader, output, vec![(payload, res)]))
+        State::Leader(State::leader_write(leader, output, vec![(EntryKind::User, payload, res)]))
       }
       State::Candidate(candidate) => match candidate.shared.voted_for {
+        None if !candidate.shared.can_campaign() => {
+          // This node can't campaign, see start_election.
+          if let Some(mut res) = res.take() {
+            res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))));
+          };
+          State::Candidate(candidate)
+        }
         Some(voted_for) => {
           // TODO: if voted_for is this node, we may want to wait and see if we
           // win the election
@@ -555,9 +1095,7 @@ impl State {
       },
       State::Follower(follower) => {
         if let Some(mut res) = res.take() {
-          res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(Some(
-            follower.leader_hint,
-          )))));
+          res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(follower.leader_hint))));
         };
         State::Follower(follower)
       }
@@ -571,28 +1109,60 @@ impl State {
     State::leader_write(leader, output, vec![])
   }
 
+  // Records when the AppendEntries round with the given ReadID was sent.
+  //
+  // NB: Rounds sent more than an election timeout ago are forgotten, so that
+  // these don't pile up while no peer is responding. A response to one of them
+  // is still handled, it just doesn't count toward fresh_index.
+  fn round_sent(leader: &mut Leader, read_id: ReadID) {
+    let now = match leader.shared.current_time {
+      Some(now) => now,
+      None => return,
+    };
+    let timeout = leader.shared.cfg.election_timeout;
+    while let Some((_, sent)) = leader.rounds_sent.front() {
+      if now.saturating_duration_since(*sent) <= timeout {
+        break;
+      }
+      leader.rounds_sent.pop_front();
+    }
+    leader.rounds_sent.push_back((read_id, now));
+  }
+
+  fn leader_noop(leader: Leader, output: &mut impl Extend<Output>) -> Leader {
+    // A leader cannot use the current-term rule to commit entries from previous
+    // terms (§5.4.2) until an entry from its own term is replicated, so a new
+    // leader appends a no-op entry right away instead of waiting for a client
+    // to write (§8). This also commits everything in its log before any read
+    // it serves.
+    State::leader_write(leader, output, vec![(EntryKind::Noop, vec![], None)])
+  }
+
   fn leader_write(
     mut leader: Leader,
     output: &mut impl Extend<Output>,
-    payloads: Vec<(Vec<u8>, Option<WriteFuture>)>,
+    payloads: Vec<(EntryKind, Vec<u8>, Option<WriteFuture>)>,
   ) -> Leader {
     let (prev_log_term, prev_log_index) = leader.shared.log.last();
-    let read_id = leader.next_read_id;
-    leader.next_read_id = ReadID(leader.next_read_id.0 + 1);
+    let read_id = leader.shared.next_read_id();
     leader.max_outstanding_read_id = Some(read_id);
+    State::round_sent(&mut leader, read_id);
+    let proposed = leader.shared.current_time;
     let entries: Vec<_> = payloads
       .into_iter()
       .enumerate()
-      .map(|(offset, (payload, res))| {
+      .map(|(offset, (kind, payload, res))| {
         let entry = EntryShared::new(
           leader.shared.current_term,
           prev_log_index + offset as u64 + 1,
           &payload,
+          kind,
         );
         let entry_ref: EntryRef = entry.capnp_as_ref();
         debug_assert!(leader.write_buffer.get(&(entry_ref.term(), entry_ref.index())).is_none());
         if let Some(res) = res {
-          leader.write_buffer.insert((entry_ref.term(), entry_ref.index()), res);
+          leader.shared.metrics.proposal();
+          leader.write_buffer.insert((entry_ref.term(), entry_ref.index()), (res, proposed));
         }
         entry
       })
@@ -603,8 +1173,13 @@ impl State {
     // TODO: this is duplicated with the one in `follower_append_entries`
     debug!("  {:3}: persist {:?}", leader.shared.id.0, &entries);
     if entries.len() > 0 {
-      let msg =
-        PersistReq { leader_id: leader.shared.id, read_id: read_id, entries: entries.clone() };
+      let msg = PersistReq {
+        leader_id: leader.shared.id,
+        read_id,
+        entries: entries.clone(),
+        generation: leader.shared.persist_generation,
+      };
+      leader.shared.persist_inflight += 1;
       output.extend(vec![Output::PersistReq(msg)]);
     } else {
       let id = leader.shared.id;
@@ -620,6 +1195,7 @@ impl State {
       &entries,
     ));
     State::message_to_all_other_nodes(&leader.shared, output, payload);
+    leader.shared.metrics.append_entries_sent(leader.shared.peers.len() - 1);
     leader
   }
 
@@ -633,7 +1209,7 @@ impl State {
   ///
   /// 1) The leader must have committed some entry, thus committing everything
   ///    that was in its log when it was elected. This is only interesting for
-  ///    new leaders.
+  ///    new leaders, which append a no-op entry on election for this purpose.
   /// 2) The leader snapshots its highest log index (the per-read "read index")
   ///    at the time the read is queued and must wait for a
   ///    heartbeat/AppendEntries to succeed. This confirms the leader was still
@@ -643,9 +1219,9 @@ impl State {
   /// Mechanically, this is implemented as follows:
   ///
   /// - An id space, [`ReadID`], is introduced for AppendEntries and reads. This
-  ///   resets to 0 for each term and increments for each read and for each
-  ///   "round" of AppendEntries. This means that (Term, ReadID) gives a total
-  ///   ordering to reads and AppendEntries.
+  ///   increments for each read and for each "round" of AppendEntries. This
+  ///   means that (Term, ReadID) gives a total ordering to reads and
+  ///   AppendEntries.
   /// - When a read is queued, the highest log index is snapshotted and the next
   ///   ReadID is taken. These are buffered with the read request and response
   ///   future.
@@ -669,8 +1245,79 @@ impl State {
   ///   `ReadStateMachineReq`s that it receives without worrying about the
   ///   indexes of what it's applied. (This assumes it has handled all
   ///   `PersistReq`s and `ApplyReq`s as is contractually required by `step`.)
-  fn read(self, output: &mut impl Extend<Output>, req: ReadReq, mut res: ReadFuture) -> State {
+  ///
+  /// Reads with any other [`ReadConsistency`] are served locally, by any node,
+  /// from whatever it has applied. Each needs some index to have been applied
+  /// first, so these wait in a second buffer until it has been (which never
+  /// holds up applying). After that, they're handed to the replicated state
+  /// machine and hold up applying later indexes in the same way as above.
+  fn read(mut self, output: &mut impl Extend<Output>, req: ReadReq, mut res: ReadFuture) -> State {
     debug!("  {:3}: read {:?}", self.id().0, req);
+    let index = match req.consistency {
+      ReadConsistency::Linearizable => None,
+      ReadConsistency::AtLeastIndex(index) => {
+        // This node may never apply an index it hasn't heard of, so a read of
+        // one would wait forever. The same goes for one whose entry is later
+        // truncated, see fail_unknown_reads.
+        if index > self.shared().known_index() {
+          res.fill(Err(ClientError::UnknownIndex(index)));
+          return self;
+        }
+        Some(index)
+      }
+      ReadConsistency::MaxStaleness(staleness) => self.fresh_index(staleness),
+    };
+    match index {
+      Some(index) => {
+        let shared = self.shared_mut();
+        let read_id = shared.next_read_id();
+        shared.waiting_reads.insert((index, read_id), (req, res));
+        State::maybe_serve_local_reads(shared, output);
+        self
+      }
+      // NB: A read that's too stale to serve locally falls back to a
+      // linearizable one, which is never stale.
+      None => self.linearizable_read(output, req, res),
+    }
+  }
+
+  // Returns the index that must be applied for this node's state to be no more
+  // than `staleness` behind the leader's, or None if it can't be served locally.
+  fn fresh_index(&self, staleness: Duration) -> Option<Index> {
+    let now = self.shared().current_time?;
+    let (as_of, index) = match self {
+      State::Leader(leader) => {
+        // Until an entry from its term is committed, a new leader doesn't know
+        // everything that's committed (§5.4.2).
+        let shared = &leader.shared;
+        if shared.log.index_term(shared.commit_index) != Some(shared.current_term) {
+          return None;
+        }
+        // This node was still leader as of when it sent the latest round that
+        // a majority of the group, including itself, responded to. NB: Not when
+        // the responses arrived, a new leader may have been elected since the
+        // round was sent.
+        let mut contact: Vec<Instant> = leader.acked_sent.values().copied().collect();
+        contact.push(now);
+        contact.sort_unstable_by(|a, b| b.cmp(a));
+        (*contact.get(State::majority(shared) - 1)?, shared.commit_index)
+      }
+      State::Candidate(candidate) => candidate.shared.leader_commit?,
+      State::Follower(follower) => follower.shared.leader_commit?,
+    };
+    if now.saturating_duration_since(as_of) <= staleness {
+      Some(index)
+    } else {
+      None
+    }
+  }
+
+  fn linearizable_read(
+    self,
+    output: &mut impl Extend<Output>,
+    req: ReadReq,
+    mut res: ReadFuture,
+  ) -> State {
     match self {
       State::Leader(leader) => {
         // Only a leader can serve a read, let's go.
@@ -678,6 +1325,11 @@ impl State {
       }
       // TODO: dedeup these with the ones in write
       State::Candidate(candidate) => match candidate.shared.voted_for {
+        None if !candidate.shared.can_campaign() => {
+          // This node can't campaign, see start_election.
+          res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))));
+          State::Candidate(candidate)
+        }
         Some(voted_for) => {
           // TODO: if voted_for is this node, we may want to wait and see if we
           // win the election
@@ -688,11 +1340,11 @@ impl State {
           // We haven't voted yet so start an election, then try the read
           // again, maybe we'll be able to serve it.
           let state = State::start_election(candidate, output);
-          state.read(output, req, res)
+          state.linearizable_read(output, req, res)
         }
       },
       State::Follower(follower) => {
-        res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(Some(follower.leader_hint)))));
+        res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(follower.leader_hint))));
         State::Follower(follower)
       }
     }
@@ -704,8 +1356,7 @@ impl State {
     req: ReadReq,
     res: ReadFuture,
   ) -> Leader {
-    let read_id = leader.next_read_id;
-    leader.next_read_id = ReadID(leader.next_read_id.0 + 1);
+    let read_id = leader.shared.next_read_id();
     let index = leader.shared.log.last().1;
     leader.read_buffer.insert((index, read_id), (Some(req), res));
     State::leader_maybe_advance_reads(leader, output)
@@ -726,9 +1377,21 @@ impl State {
     match self {
       State::Candidate(mut candidate) => {
         // Candidates (§5.2): If election timeout elapses: start new election
-        let timed_out = candidate.shared.last_communication.map_or(true, |last_communication| {
-          now.duration_since(last_communication) >= candidate.shared.cfg.election_timeout
-        });
+        let timed_out = match candidate.shared.last_communication {
+          Some(last_communication) => {
+            now.duration_since(last_communication) >= candidate.shared.cfg.election_timeout
+          }
+          // A node in a brand new group calls an election right away, there's
+          // no leader to wait for.
+          None if candidate.shared.current_term == Term(0) => true,
+          None => {
+            // A restarted node waits out a full election timeout first, so the
+            // current leader (if any) has a chance to reach it before it
+            // disrupts the group with a new term.
+            candidate.shared.last_communication = Some(now);
+            false
+          }
+        };
         candidate.shared.current_time = Some(now);
         if timed_out {
           return State::start_election(candidate, output);
@@ -739,9 +1402,17 @@ impl State {
         // Followers (§5.2): If election timeout elapses without receiving
         // AppendEntries rpc from current leader or granting vote to candidate:
         // convert to candidate
-        let timed_out = follower.shared.last_communication.map_or(true, |last_communication| {
-          now.duration_since(last_communication) >= follower.shared.cfg.election_timeout
-        });
+        let timed_out = match follower.shared.last_communication {
+          Some(last_communication) => {
+            now.duration_since(last_communication) >= follower.shared.cfg.election_timeout
+          }
+          None => {
+            // This follower heard from a leader (or granted a vote) before its
+            // first tick, so start its election timer now.
+            follower.shared.last_communication = Some(now);
+            false
+          }
+        };
         follower.shared.current_time = Some(now);
         if timed_out {
           return State::follower_convert_to_candidate(follower, output);
@@ -764,14 +1435,46 @@ impl State {
     }
   }
 
-  fn persist_res(self, output: &mut impl Extend<Output>, res: PersistRes) -> State {
+  fn persist_res(mut self, output: &mut impl Extend<Output>, mut res: PersistRes) -> State {
+    let shared = self.shared_mut();
+    let stale = res.generation < shared.persist_generation;
+    shared.persist_inflight = shared.persist_inflight.saturating_sub(1);
+    let superseded = shared.persist_superseded > 0;
+    if superseded {
+      shared.persist_superseded -= 1;
+    }
+    let probe = res.leader_id == shared.id && shared.storage_probe == Some(res.read_id);
+    if probe {
+      shared.storage_probe = None;
+    }
+    if stale {
+      // This write was queued behind one that failed, so the log should have
+      // failed it too. It doesn't count, even if it didn't, and the failure it
+      // was queued behind was already handled.
+      return self;
+    }
+    if let Some(error) = res.error.take() {
+      shared.persist_generation += 1;
+      return self.persist_failed(output, res, error);
+    }
+    shared.storage_failed = false;
+    if probe {
+      // The probe has no entries, so there's nothing to acknowledge.
+      return self;
+    }
+    // NB: A duplicate AppendEntries may be persisted after a later one, so this
+    // never regresses.
+    let durable =
+      if superseded { cmp::min(res.log_index, shared.persist_truncated) } else { res.log_index };
+    shared.persisted = cmp::max(shared.persisted, durable);
+
     let payload = PayloadShared::AppendEntriesRes(AppendEntriesResShared::new(
       self.shared().current_term,
       1, // WIP true
       res.log_index,
       res.read_id,
     ));
-    let msg = MessageShared::new(self.id(), res.leader_id, payload);
+    let msg = self.shared().message(res.leader_id, payload);
     if msg.capnp_as_ref().src() == msg.capnp_as_ref().dest() {
       return State::step(self, output, Input::Message(msg.capnp_as_ref()));
     }
@@ -779,96 +1482,300 @@ impl State {
     self
   }
 
-  fn read_state_machine_res(
-    self,
+  fn persist_failed(
+    mut self,
     output: &mut impl Extend<Output>,
-    res: ReadStateMachineRes,
+    res: PersistRes,
+    error: PersistError,
   ) -> State {
-    let mut leader = match self {
-      State::Leader(leader) => leader,
-      // NB: It should be possible to serve reads even after losing leadership
-      // but it's subtle and also hard to avoid leaking the result future. Seems
-      // not worth it.
-      State::Candidate(candidate) => return State::Candidate(candidate),
-      State::Follower(follower) => return State::Follower(follower),
+    debug!("  {:3}: persist failed {:?}: {}", self.id().0, res, error);
+    let shared = self.shared_mut();
+    shared.storage_failed = true;
+    // Forget every entry that wasn't acknowledged as durable, so that none of
+    // them are acknowledged to a leader later by mistake. Entries are only
+    // committed once they're durable, so none of these are committed.
+    if shared.persisted < shared.log.last().1 {
+      shared.log.trim(shared.persisted);
+    }
+    let persisted = shared.persisted;
+    // Any votes were asked for with a log that's since been trimmed, so they
+    // can't be counted.
+    let state = match self {
+      State::Candidate(candidate) => {
+        let reason = StepDownReason::StorageFailed;
+        State::Follower(State::candidate_convert_to_follower(candidate, output, None, reason))
+      }
+      state => state,
     };
+    match state {
+      State::Leader(mut leader) => {
+        // The writes in the failed entries fail with the error. A leader that
+        // can't persist its log can't make progress (the failed write may even
+        // be from before it was elected), so it steps down and everything else
+        // outstanding fails as it usually would.
+        let id = leader.shared.id;
+        if res.leader_id == id {
+          let acked = leader.match_index.get(&id).map_or(Index(0), |(index, _)| *index);
+          leader.write_buffer.retain(|(_, index), (future, _)| {
+            if *index <= acked || *index > res.log_index {
+              return true;
+            }
+            future.fill(Err(ClientError::PersistError(error.clone())));
+            false
+          });
+        }
+        let reason = StepDownReason::StorageFailed;
+        State::Follower(State::leader_convert_to_follower(leader, output, None, reason))
+      }
+      state if res.leader_id == state.id() => {
+        // Written while this node was leader, but it's not anymore.
+        state
+      }
+      state => {
+        // Reject the AppendEntries, sending back the index of the last entry
+        // that is durable so the leader resends everything after it.
+        let payload = PayloadShared::AppendEntriesRes(AppendEntriesResShared::new(
+          state.shared().current_term,
+          0, // WIP false
+          persisted,
+          res.read_id,
+        ));
+        let msg = state.shared().message(res.leader_id, payload);
+        output.extend(vec![Output::Message(msg)]);
+        state
+      }
+    }
+  }
 
+  fn read_state_machine_res(
+    mut self,
+    output: &mut impl Extend<Output>,
+    res: ReadStateMachineRes,
+  ) -> State {
     let index = res.index;
-    let payload = res.payload;
-    // Remove the entry so ReadStateMachineRes is idempotent.
-    if let Some((_, mut res)) = leader.read_buffer.remove(&(res.index, res.read_id)) {
-      #[cfg(feature = "log")]
-      match std::str::from_utf8(&payload) {
-        Ok(payload) => {
-          debug!("  {:3}: read success {:?}", leader.shared.id.0, payload);
-        }
-        Err(_) => {
-          debug!("  {:3}: read success {:?}", leader.shared.id.0, payload);
+    let term = self.shared().current_term;
+    for (read_id, payload) in res.reads {
+      let key = (index, read_id);
+      // Remove the entry so ReadStateMachineRes is idempotent.
+      //
+      // NB: It should be possible to serve linearizable reads even after losing
+      // leadership but it's subtle and also hard to avoid leaking the result
+      // future. Seems not worth it. Local reads are served regardless.
+      let future = match &mut self {
+        State::Leader(leader) => leader.read_buffer.remove(&key).map(|(_, future)| future),
+        State::Candidate(_) | State::Follower(_) => None,
+      };
+      let future = future.or_else(|| self.shared_mut().local_reads.remove(&key));
+      if let Some(mut future) = future {
+        #[cfg(feature = "log")]
+        match std::str::from_utf8(&payload) {
+          Ok(payload) => {
+            debug!("  {:3}: read success {:?}", self.id().0, payload);
+          }
+          Err(_) => {
+            debug!("  {:3}: read success {:?}", self.id().0, payload);
+          }
         }
+        future.fill(Ok(ReadRes { term, index, payload }));
       }
-      res.fill(Ok(ReadRes { term: leader.shared.current_term, index: index, payload: payload }));
     }
     // If that was the last outstanding read, it may have unblocked applying new
     // entries.
-    leader = State::leader_maybe_apply(leader, output);
-    State::Leader(leader)
+    match self {
+      State::Leader(leader) => State::Leader(State::leader_maybe_apply(leader, output)),
+      State::Follower(follower) => State::Follower(State::follower_maybe_apply(follower, output)),
+      State::Candidate(candidate) => {
+        State::Candidate(State::candidate_maybe_apply(candidate, output))
+      }
+    }
   }
 
   fn leader_maybe_apply(mut leader: Leader, output: &mut impl Extend<Output>) -> Leader {
     let min_outstanding_read: Option<Index> =
       leader.read_buffer.iter().next().map(|((index, _), _)| *index);
     State::maybe_apply(&mut leader.shared, output, min_outstanding_read);
-    leader
+    State::leader_serve_reads(leader, output)
   }
 
   fn follower_maybe_apply(mut follower: Follower, output: &mut impl Extend<Output>) -> Follower {
     State::maybe_apply(&mut follower.shared, output, None);
+    State::maybe_serve_local_reads(&mut follower.shared, output);
     follower
   }
 
+  fn candidate_maybe_apply(
+    mut candidate: Candidate,
+    output: &mut impl Extend<Output>,
+  ) -> Candidate {
+    State::maybe_apply(&mut candidate.shared, output, None);
+    State::maybe_serve_local_reads(&mut candidate.shared, output);
+    candidate
+  }
+
+  // Returns an error if the given message is one that no correct peer would
+  // have sent, in which case it must not be stepped.
+  fn validate(&self, message: &MessageRef<'_>) -> Result<(), ProtocolError> {
+    let shared = self.shared();
+    if message.cluster_id() != shared.cfg.cluster_id {
+      return Err(ProtocolError::ClusterMismatch(message.cluster_id()));
+    }
+    if message.version() > PROTOCOL_VERSION {
+      return Err(ProtocolError::UnsupportedVersion(message.version()));
+    }
+    if message.dest() != shared.id {
+      return Err(ProtocolError::Misaddressed(message.dest()));
+    }
+    if !shared.peers.contains(&message.src()) {
+      return Err(ProtocolError::UnknownSender);
+    }
+    let payload = State::decode(message)?;
+    // NB: Every election increments the term, so a correct peer never gets
+    // this far and a node that adopted it couldn't campaign.
+    if State::term(&payload) == Term(u64::MAX) {
+      return Err(ProtocolError::TermExhausted);
+    }
+    let req = match payload {
+      Payload::AppendEntriesReq(req) => req,
+      Payload::AppendEntriesRes(res) => return State::validate_append_entries_res(self, res),
+      Payload::RequestVoteReq(req) if req.candidate_id() != message.src() => {
+        return Err(ProtocolError::SenderMismatch(req.candidate_id()));
+      }
+      _ => return Ok(()),
+    };
+    if req.leader_id() != message.src() {
+      return Err(ProtocolError::SenderMismatch(req.leader_id()));
+    }
+    let entries = req.entries().map_err(|err| ProtocolError::Malformed(err.to_string()))?;
+    let entries: Vec<EntryRef<'_>> = entries.iter().collect();
+    // Log matching (§5.3) means a leader's log has consecutive indexes and terms
+    // that never decrease and never exceed its own.
+    let (mut prev_term, mut prev_index) = (req.prev_log_term(), req.prev_log_index());
+    if prev_term > req.term() {
+      return Err(ProtocolError::InvalidEntries);
+    }
+    for entry in entries.iter() {
+      entry.kind().map_err(|err| ProtocolError::Malformed(err.to_string()))?;
+      entry.payload().map_err(|err| ProtocolError::Malformed(err.to_string()))?;
+      // NB: The index comes from a peer, so it may be the last one there is,
+      // which no leader could append after.
+      let consecutive = prev_index.0.checked_add(1) == Some(entry.index().0);
+      if !consecutive
+        || entry.index() == Index(u64::MAX)
+        || entry.term() < prev_term
+        || entry.term() > req.term()
+      {
+        return Err(ProtocolError::InvalidEntries);
+      }
+      prev_term = entry.term();
+      prev_index = entry.index();
+    }
+    if req.term() < shared.current_term {
+      // Stale, this is rejected when it's stepped.
+      return Ok(());
+    }
+    if let State::Leader(_) = self {
+      if req.term() == shared.current_term {
+        // Election safety (§5.2) means no other node can be leader in this
+        // term.
+        return Err(ProtocolError::DuplicateLeader(req.term()));
+      }
+    }
+    // Leader completeness (§5.4) means no later leader is missing a committed
+    // entry, so none of them ever have to be removed. See
+    // follower_append_entries.
+    let log_match = shared.log.index_term(req.prev_log_index()) == Some(req.prev_log_term());
+    if log_match {
+      let new = State::first_new(&shared.log, &entries);
+      if let Some(index) = State::first_truncated(&shared.log, &entries[new..]) {
+        if index <= shared.commit_index {
+          return Err(ProtocolError::TruncatesCommitted(index));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn validate_append_entries_res(&self, res: AppendEntriesResRef<'_>) -> Result<(), ProtocolError> {
+    let leader = match self {
+      State::Leader(leader) => leader,
+      // Stale, this is ignored when it's stepped.
+      _ => return Ok(()),
+    };
+    let stale = res.term() < leader.shared.current_term || res.read_id() < leader.first_read_id;
+    if stale || res.success() == 0 {
+      return Ok(());
+    }
+    // A peer can only acknowledge entries this leader sent it.
+    if res.index() > leader.shared.log.last().1 {
+      return Err(ProtocolError::AckedUnknownIndex(res.index()));
+    }
+    Ok(())
+  }
+
+  // Returns the payload of the given message or an error if it doesn't decode.
+  fn term(payload: &Payload<'_>) -> Term {
+    match payload {
+      Payload::AppendEntriesReq(req) => req.term(),
+      Payload::AppendEntriesRes(res) => res.term(),
+      Payload::RequestVoteReq(req) => req.term(),
+      Payload::RequestVoteRes(res) => res.term(),
+      Payload::StartElectionReq(req) => req.term(),
+    }
+  }
+
+  fn decode<'a>(message: &MessageRef<'a>) -> Result<Payload<'a>, ProtocolError> {
+    match message.payload() {
+      Ok(Ok(payload)) => Ok(payload),
+      Ok(Err(err)) => Err(ProtocolError::Malformed(err.to_string())),
+      Err(err) => Err(ProtocolError::Malformed(err.to_string())),
+    }
+  }
+
   fn message(mut self, output: &mut impl Extend<Output>, message: MessageRef<'_>) -> State {
+    // NB: Messages from peers were validated by Raft::step and the rest were
+    // constructed by this node.
+    let payload = State::decode(&message).expect("unreachable");
+    let src = message.src();
     {
       let mut shared = self.shared_mut();
-      let term = match &message.payload().expect("WIP").expect("WIP") {
-        Payload::AppendEntriesReq(req) => req.term(),
-        Payload::AppendEntriesRes(res) => res.term(),
-        Payload::RequestVoteReq(req) => req.term(),
-        Payload::RequestVoteRes(res) => res.term(),
-        Payload::StartElectionReq(req) => req.term(),
-      };
+      let term = State::term(&payload);
       if term > shared.current_term {
         // All Servers: If rpc request or response contains term T >
         // currentTerm: set currentTerm = T, convert to follower (§5.1)
         shared.current_term = term;
+        shared.metrics.term_changed(term);
         // TODO: probably want a helper for updating the term
         shared.voted_for = None;
         // TODO: do we really convert to follower on a RequestVoteReq with a
         // higher term?
-        self = State::Follower(self.convert_to_follower(output, message.src()));
+        let reason = StepDownReason::HigherTerm(src);
+        self = State::Follower(self.convert_to_follower(output, Some(src), reason));
       }
     }
     match self {
-      State::Candidate(candidate) => State::candidate_step(candidate, output, message),
-      State::Follower(follower) => State::follower_step(follower, output, message),
-      State::Leader(leader) => State::leader_step(leader, output, message),
+      State::Candidate(candidate) => State::candidate_step(candidate, output, src, payload),
+      State::Follower(follower) => State::follower_step(follower, output, payload),
+      State::Leader(leader) => State::leader_step(leader, output, src, payload),
     }
   }
 
   fn candidate_step<'i>(
     candidate: Candidate,
     output: &mut impl Extend<Output>,
-    message: MessageRef<'i>,
+    src: NodeID,
+    payload: Payload<'i>,
   ) -> State {
-    match message.payload().expect("WIP").expect("WIP") {
+    match payload {
       Payload::RequestVoteRes(res) => {
-        State::candidate_process_request_vote_res(candidate, output, res)
+        State::candidate_process_request_vote_res(candidate, output, src, res)
       }
       Payload::AppendEntriesReq(req) => {
-        if req.term() > candidate.shared.current_term {
+        if req.term() >= candidate.shared.current_term {
           // Candidates (§5.2): If AppendEntries rpc received from new leader:
           // convert to follower
-          let follower = State::candidate_convert_to_follower(candidate, output, message.src());
-          return State::Follower(follower).step(output, Input::Message(message));
+          let reason = StepDownReason::NewLeader(src);
+          let follower = State::candidate_convert_to_follower(candidate, output, Some(src), reason);
+          return State::Follower(State::follower_append_entries(follower, output, req));
         }
         State::Candidate(candidate)
       }
@@ -880,16 +1787,20 @@ impl State {
         }
         State::start_election(candidate, output)
       }
-      payload => todo!("{:?}", payload),
+      Payload::AppendEntriesRes(_) => {
+        // No-op, stale response to a request sent out by this node when it was
+        // a leader.
+        State::Candidate(candidate)
+      }
     }
   }
 
   fn follower_step<'i>(
     follower: Follower,
     output: &mut impl Extend<Output>,
-    message: MessageRef<'i>,
+    payload: Payload<'i>,
   ) -> State {
-    match message.payload().expect("WIP").expect("WIP") {
+    match payload {
       // Followers (§5.2): Respond to rpcs from candidates and leaders
       Payload::AppendEntriesReq(req) => {
         State::Follower(State::follower_append_entries(follower, output, req))
@@ -917,11 +1828,12 @@ impl State {
   fn leader_step<'i>(
     leader: Leader,
     output: &mut impl Extend<Output>,
-    message: MessageRef<'i>,
+    src: NodeID,
+    payload: Payload<'i>,
   ) -> State {
-    match message.payload().expect("WIP").expect("WIP") {
+    match payload {
       Payload::AppendEntriesRes(res) => {
-        State::Leader(State::leader_append_entries_res(leader, output, message.src(), res))
+        State::Leader(State::leader_append_entries_res(leader, output, src, res))
       }
       Payload::RequestVoteRes(_) => {
         // Already the leader, nothing to do here.
@@ -931,7 +1843,25 @@ impl State {
         // Already the leader, nothing to do here.
         State::Leader(leader)
       }
-      payload => todo!("{:?} {:?}", payload, leader.shared),
+      Payload::RequestVoteReq(req) => State::Leader(leader).process_request_vote(output, req),
+      Payload::AppendEntriesReq(req) if req.term() < leader.shared.current_term => {
+        // Reply false if term < currentTerm (§5.1)
+        let payload = PayloadShared::AppendEntriesRes(AppendEntriesResShared::new(
+          leader.shared.current_term,
+          0, // WIP false
+          Index(0),
+          req.read_id(),
+        ));
+        let msg = leader.shared.message(req.leader_id(), payload);
+        output.extend(vec![Output::Message(msg)]);
+        State::Leader(leader)
+      }
+      Payload::AppendEntriesReq(_) => {
+        // Election safety (§5.2) means no other node can be leader in this
+        // term, so only a faulty peer could have sent this and it was already
+        // rejected by validate.
+        State::Leader(leader)
+      }
     }
   }
 
@@ -948,7 +1878,7 @@ impl State {
         Index(0),
         req.read_id(),
       ));
-      let msg = MessageShared::new(follower.shared.id, req.leader_id(), payload);
+      let msg = follower.shared.message(req.leader_id(), payload);
       output.extend(vec![Output::Message(msg)]);
       return follower;
     }
@@ -958,6 +1888,11 @@ impl State {
       follower.shared.id.0, follower.shared.current_time
     );
     follower.shared.last_communication = follower.shared.current_time;
+    follower.shared.leader_commit =
+      follower.shared.current_time.map(|current_time| (current_time, req.leader_commit()));
+    // This may be the first we've heard from the leader of a new term, if this
+    // node was already a follower when the term changed.
+    follower.leader_hint = Some(req.leader_id());
 
     // Reply false if log doesn’t contain an entry at prevLogIndex whose term
     // matches prevLogTerm (§5.3)
@@ -967,14 +1902,15 @@ impl State {
       .index_term(req.prev_log_index())
       .map_or(false, |term| term == req.prev_log_term());
     if !log_match {
-      // TODO: send back a hint of what we do have
+      // Send back the index of our last entry as a hint so the leader can skip
+      // straight past anything we're definitely missing.
       let payload = PayloadShared::AppendEntriesRes(AppendEntriesResShared::new(
         follower.shared.current_term,
         0, // WIP false
-        Index(0),
+        follower.shared.log.last().1,
         req.read_id(),
       ));
-      let msg = MessageShared::new(follower.shared.id, req.leader_id(), payload);
+      let msg = follower.shared.message(req.leader_id(), payload);
       output.extend(vec![Output::Message(msg)]);
       return follower;
     }
@@ -982,50 +1918,193 @@ impl State {
     // If an existing entry conflicts with a new one (same index but different
     // terms), delete the existing entry and all that follow it (§5.3). Append
     // any new entries not already in the log
-    let entries = req.entries().expect("WIP");
+    // NB: These were decoded by validate, which also checked that none of the
+    // committed ones are removed.
+    let entries = req.entries().expect("unreachable");
+    let last_new_index = req.prev_log_index() + entries.len() as u64;
     if entries.len() > 0 {
       let entries = entries.iter().collect::<Vec<_>>();
-      follower.shared.log.extend(&entries);
+      // NB: This request may be a duplicate or may have been reordered with a
+      // later one, so only the entries after those already in the log are
+      // added. Otherwise, acknowledged entries could be removed.
+      let new = State::first_new(&follower.shared.log, &entries);
+      let truncated = State::first_truncated(&follower.shared.log, &entries[new..]);
+      if let Some(index) = truncated {
+        follower.shared.emit(|| Event::LogTruncated { index });
+        // What replaces the truncated entries isn't durable until it's been
+        // persisted, and the writes already in flight may still write the
+        // truncated ones.
+        let truncated = Index(index.0 - 1);
+        follower.shared.persisted = cmp::min(follower.shared.persisted, truncated);
+        if follower.shared.persist_superseded == 0 {
+          follower.shared.persist_truncated = truncated;
+        }
+        follower.shared.persist_truncated = cmp::min(follower.shared.persist_truncated, truncated);
+        follower.shared.persist_superseded = follower.shared.persist_inflight;
+      }
+      follower.shared.log.extend(&entries[new..]);
+      if truncated.is_some() {
+        follower.shared.fail_unknown_reads();
+      }
+      // NB: Entries already in the log are persisted again anyway (which is a
+      // no-op for the log), so that the response isn't sent until the original
+      // write of them has finished.
       let msg = PersistReq {
         leader_id: req.leader_id(),
         read_id: req.read_id(),
         entries: entries.iter().map(|e| e.capnp_to_owned()).collect(),
+        generation: follower.shared.persist_generation,
       };
+      follower.shared.persist_inflight += 1;
       output.extend(vec![Output::PersistReq(msg)]);
     } else {
       // TODO: duplicated with persist_res
+      //
+      // NB: Entries up to prev_log_index may still be being written (and that
+      // write may fail), so only those already durable are acknowledged.
       let payload = PayloadShared::AppendEntriesRes(AppendEntriesResShared::new(
         follower.shared.current_term,
-        1,                    // WIP true
-        req.prev_log_index(), // TODO: is this right?
+        1, // WIP true
+        cmp::min(req.prev_log_index(), follower.shared.persisted),
         req.read_id(),
       ));
-      let msg = MessageShared::new(follower.shared.id, req.leader_id(), payload);
+      let msg = follower.shared.message(req.leader_id(), payload);
       output.extend(vec![Output::Message(msg)]);
     }
 
     // If leaderCommit > commitIndex, set commitIndex = min(leaderCommit, index
     // of last new entry)
-    if req.leader_commit() > follower.shared.commit_index {
-      let last_entry_index = follower.shared.log.last().1;
-      follower.shared.commit_index = cmp::min(req.leader_commit(), last_entry_index);
+    //
+    // NB: Entries are only committed here once they're durable, otherwise one
+    // whose write fails would be applied anyway.
+    let durable = cmp::min(last_new_index, follower.shared.persisted);
+    if req.leader_commit() > follower.shared.commit_index && durable > follower.shared.commit_index
+    {
+      let old_commit_index = follower.shared.commit_index;
+      follower.shared.commit_index = cmp::min(req.leader_commit(), durable);
+      if follower.shared.commit_index > old_commit_index {
+        let new_commit_index = follower.shared.commit_index;
+        follower
+          .shared
+          .emit(|| Event::CommitAdvanced { from: old_commit_index, to: new_commit_index });
+      }
       follower = State::follower_maybe_apply(follower, output);
     }
     follower
   }
 
+  // Returns the offset of the first of the given entries that isn't already in
+  // the log.
+  fn first_new(log: &CompressedLog, entries: &[EntryRef<'_>]) -> usize {
+    entries
+      .iter()
+      .position(|entry| log.index_term(entry.index()) != Some(entry.term()))
+      .unwrap_or(entries.len())
+  }
+
+  // Returns the index of the first entry in the log that will be removed by
+  // extending it with the given entries, if any.
+  fn first_truncated(log: &CompressedLog, entries: &[EntryRef<'_>]) -> Option<Index> {
+    // NB: Extending the log removes everything from the first new entry on.
+    let last_index = log.last().1;
+    entries.first().map(|entry| entry.index()).filter(|index| *index <= last_index)
+  }
+
   fn leader_append_entries_res<'a>(
-    leader: Leader,
+    mut leader: Leader,
     output: &'a mut impl Extend<Output>,
     src: NodeID,
     res: AppendEntriesResRef<'a>,
   ) -> Leader {
+    // NB: A peer that moved to this term after a request from an earlier one
+    // responds to it with this term, so the ReadID is checked as well.
+    if res.term() < leader.shared.current_term || res.read_id() < leader.first_read_id {
+      // Stale response to a request sent out by this node when it was leader of
+      // an earlier term, ignore.
+      return leader;
+    }
+    if let Some(current_time) = leader.shared.current_time {
+      leader.last_contact.insert(src, current_time);
+    }
+    // NB: This node's own acks aren't counted, see fresh_index.
+    let read_id = res.read_id();
+    let round = leader.rounds_sent.binary_search_by_key(&read_id, |(read_id, _)| *read_id);
+    if let (Ok(idx), true) = (round, src != leader.shared.id) {
+      let sent = leader.rounds_sent[idx].1;
+      let acked = leader.acked_sent.entry(src).or_insert(sent);
+      *acked = cmp::max(*acked, sent);
+    }
     // If successful: update nextIndex and matchIndex for follower (§5.3)
     if res.success() > 0 {
       return State::ack_term_index(leader, output, src, res.index(), res.read_id());
     }
-    // If AppendEntries fails because of log inconsistency: decrement nextIndex and retry (§5.3)
-    todo!()
+    leader.shared.metrics.append_entries_rejected();
+    // If AppendEntries fails because of log inconsistency: decrement nextIndex
+    // and retry (§5.3)
+    //
+    // NB: The follower sends back the index of its last entry, so jump straight
+    // past anything it's definitely missing. Never go back past what it has
+    // already acknowledged, this rejection may have been delayed.
+    let last_index = leader.shared.log.last().1;
+    let match_index = leader.match_index.get(&src).map_or(Index(0), |(index, _)| *index);
+    let next_index = leader.next_index.get(&src).copied().unwrap_or(last_index + 1);
+    let hint = Index(res.index().0.saturating_add(1));
+    let next_index = cmp::min(Index(next_index.0.saturating_sub(1)), hint);
+    let next_index = cmp::max(cmp::max(next_index, match_index + 1), Index(1));
+    leader.next_index.insert(src, next_index);
+    if next_index > last_index {
+      // Nothing to resend, the next heartbeat will catch it up.
+      return leader;
+    }
+    // The leader only tracks the terms of its entries, so read the ones to
+    // resend back from the log.
+    let msg = ReadLogReq {
+      peer: src,
+      term: leader.shared.current_term,
+      start: next_index,
+      end: last_index,
+    };
+    output.extend(vec![Output::ReadLogReq(msg)]);
+    leader
+  }
+
+  fn read_log_res(self, output: &mut impl Extend<Output>, res: ReadLogRes) -> State {
+    let mut leader = match self {
+      State::Leader(leader) => leader,
+      // This node stopped being leader while the log was being read.
+      State::Candidate(candidate) => return State::Candidate(candidate),
+      State::Follower(follower) => return State::Follower(follower),
+    };
+    if res.term != leader.shared.current_term {
+      // Requested as leader of an earlier term, ignore.
+      return State::Leader(leader);
+    }
+    let prev_log_index = match res.entries.first() {
+      Some(entry) => Index(entry.capnp_as_ref().index().0 - 1),
+      None => return State::Leader(leader),
+    };
+    // NB: A leader never removes entries from its log, so this is there unless
+    // the runtime sent back something other than what was asked for. Either
+    // way, the next heartbeat will be rejected and the entries read again.
+    let prev_log_term = match leader.shared.log.index_term(prev_log_index) {
+      Some(prev_log_term) => prev_log_term,
+      None => return State::Leader(leader),
+    };
+    let read_id = leader.shared.next_read_id();
+    State::round_sent(&mut leader, read_id);
+    let payload = PayloadShared::AppendEntriesReq(AppendEntriesReqShared::new(
+      leader.shared.current_term,
+      leader.shared.id,
+      prev_log_index,
+      prev_log_term,
+      leader.shared.commit_index,
+      read_id,
+      &res.entries,
+    ));
+    let msg = leader.shared.message(res.peer, payload);
+    output.extend(vec![Output::Message(msg)]);
+    leader.shared.metrics.append_entries_sent(1);
+    State::Leader(leader)
   }
 
   fn ack_term_index(
@@ -1036,11 +2115,22 @@ impl State {
     read_id: ReadID,
   ) -> Leader {
     debug!("  {:3}: self.ack_term_index src={:?} index={:}", leader.shared.id.0, src, index.0);
+    let next = Index(index.0.saturating_add(1));
     leader
       .match_index
       .entry(src)
-      .and_modify(|index_read_id| *index_read_id = cmp::max(*index_read_id, (index, read_id)))
+      .and_modify(|(max_index, max_read_id)| {
+        // NB: Responses may arrive out of order, so these are tracked
+        // independently. Neither should ever regress.
+        *max_index = cmp::max(*max_index, index);
+        *max_read_id = cmp::max(*max_read_id, read_id);
+      })
       .or_insert((index, read_id));
+    leader
+      .next_index
+      .entry(src)
+      .and_modify(|next_index| *next_index = cmp::max(*next_index, next))
+      .or_insert(next);
 
     // See if max_confirmed_read_id has advanced.
     let mut read_ids: Vec<ReadID> =
@@ -1094,12 +2184,21 @@ impl State {
       if entry_index <= leader.shared.commit_index {
         break;
       }
+      // NB: Like on a follower, only entries that are durable here are
+      // committed, otherwise one whose write fails would be applied anyway.
+      if entry_index > leader.shared.persisted {
+        continue;
+      }
       // TODO: inefficient; instead, compute once the min index that has a
       // majority in match_index
       let count = leader.match_index.iter().filter(|(_, (index, _))| *index >= entry_index).count();
       if count >= needed {
         let new_commit_index = entry_index;
         debug!("  {:3}: new_commit_index={:?}", leader.shared.id.0, new_commit_index);
+        let old_commit_index = leader.shared.commit_index;
+        leader
+          .shared
+          .emit(|| Event::CommitAdvanced { from: old_commit_index, to: new_commit_index });
         leader.shared.commit_index = new_commit_index;
         leader = State::leader_maybe_apply(leader, output);
         // TODO: think about the order of these
@@ -1129,23 +2228,62 @@ impl State {
     );
     // Reply false if term < currentTerm (§5.1)
     if req.term() < shared.current_term {
+      shared.emit(|| Event::VoteDenied {
+        term: req.term(),
+        candidate: req.candidate_id(),
+        reason: VoteDeniedReason::StaleTerm,
+      });
       let payload =
         PayloadShared::RequestVoteRes(RequestVoteResShared::new(shared.current_term, 0));
-      let msg = MessageShared::new(self.id(), req.candidate_id(), payload);
+      let msg = self.shared().message(req.candidate_id(), payload);
       output.extend(vec![Output::Message(msg)]);
       return self;
     }
+    // A node whose log is failing may be missing entries it acknowledged, so
+    // its vote can't be trusted.
+    if shared.storage_failed {
+      shared.emit(|| Event::VoteDenied {
+        term: req.term(),
+        candidate: req.candidate_id(),
+        reason: VoteDeniedReason::StorageFailed,
+      });
+      return self;
+    }
     // If votedFor is null or candidateId, and candidate’s log is at least as
     // up-to-date as receiver’s log, grant vote (§5.2, §5.4)
-    let should_grant = match shared.voted_for {
+    let can_vote = match shared.voted_for {
       None => true,
       Some(voted_for) => voted_for == req.candidate_id(),
     };
+    if let (false, Some(voted_for)) = (can_vote, shared.voted_for) {
+      shared.emit(|| Event::VoteDenied {
+        term: req.term(),
+        candidate: req.candidate_id(),
+        reason: VoteDeniedReason::AlreadyVoted(voted_for),
+      });
+    }
+    // If the logs have last entries with different terms, then the log with the
+    // later term is more up-to-date. If the logs end with the same term, then
+    // whichever log is longer is more up-to-date. (§5.4.1)
+    let up_to_date = (req.last_log_term(), req.last_log_index()) >= shared.log.last();
+    if can_vote && !up_to_date {
+      shared.emit(|| Event::VoteDenied {
+        term: req.term(),
+        candidate: req.candidate_id(),
+        reason: VoteDeniedReason::LogNotUpToDate,
+      });
+    }
+    let should_grant = can_vote && up_to_date;
     if should_grant {
+      shared.emit(|| Event::VoteGranted { term: req.term(), candidate: req.candidate_id() });
       shared.voted_for = Some(req.candidate_id());
+      // Followers (§5.2): If election timeout elapses without receiving
+      // AppendEntries rpc from current leader or granting vote to candidate:
+      // convert to candidate
+      shared.last_communication = shared.current_time;
       let payload =
         PayloadShared::RequestVoteRes(RequestVoteResShared::new(shared.current_term, 1));
-      let msg = MessageShared::new(shared.id, req.candidate_id(), payload);
+      let msg = shared.message(req.candidate_id(), payload);
       output.extend(vec![Output::Message(msg)]);
     }
     self
@@ -1154,14 +2292,22 @@ impl State {
   fn candidate_process_request_vote_res<'a>(
     mut candidate: Candidate,
     output: &'a mut impl Extend<Output>,
+    src: NodeID,
     res: RequestVoteResRef<'a>,
   ) -> State {
-    // NB: The term was checked earlier so don't need to check it again.
+    // NB: A higher term was handled earlier, but a delayed response to a vote
+    // requested in an earlier election may still arrive.
+    if res.term() < candidate.shared.current_term {
+      return State::Candidate(candidate);
+    }
+    let received_votes = match &mut candidate.received_votes {
+      Some(received_votes) => received_votes,
+      None => return State::Candidate(candidate),
+    };
     if res.vote_granted() > 0 {
-      // TODO: this is not idempotent
-      candidate.received_votes += 1;
+      received_votes.insert(src);
       let needed_votes = State::majority(&candidate.shared);
-      if candidate.received_votes >= needed_votes {
+      if received_votes.len() >= needed_votes {
         // Candidates (§5.2): If votes received from majority of servers:
         // become leader
         return State::Leader(State::candidate_convert_to_leader(candidate, output));
@@ -1172,11 +2318,37 @@ impl State {
 
   fn start_election(mut candidate: Candidate, output: &mut impl Extend<Output>) -> State {
     debug!("  {:3}: start_election {:?}", candidate.shared.id.0, candidate.shared.current_time);
-    candidate.received_votes = 0;
+    if candidate.shared.storage_failed {
+      // Same as refusing to vote for anyone else, see process_request_vote.
+      // Nothing else is written until this node is a follower or leader again,
+      // so check whether the storage has recovered with a write of no entries.
+      // Once it succeeds, the next election timeout campaigns as usual.
+      if candidate.shared.storage_probe.is_none() {
+        let read_id = candidate.shared.next_read_id();
+        let msg = PersistReq {
+          leader_id: candidate.shared.id,
+          read_id,
+          entries: vec![],
+          generation: candidate.shared.persist_generation,
+        };
+        candidate.shared.storage_probe = Some(read_id);
+        candidate.shared.persist_inflight += 1;
+        output.extend(vec![Output::PersistReq(msg)]);
+      }
+      return State::Candidate(candidate);
+    }
+    if !candidate.shared.can_campaign() {
+      return State::Candidate(candidate);
+    }
+    candidate.received_votes = Some(HashSet::new());
     // TODO: this is awkward
     candidate.shared.voted_for = Some(candidate.shared.id);
+    candidate.shared.metrics.election_started();
     // Increment currentTerm
     candidate.shared.current_term = Term(candidate.shared.current_term.0 + 1);
+    candidate.shared.metrics.term_changed(candidate.shared.current_term);
+    let term = candidate.shared.current_term;
+    candidate.shared.emit(|| Event::BecameCandidate { term });
     // Reset election timer
     candidate.shared.last_communication = candidate.shared.current_time;
     // Send RequestVote rpcs to all other servers
@@ -1190,8 +2362,9 @@ impl State {
     debug!("  {:3}: reqvote {:?}", candidate.shared.id.0, payload);
     State::message_to_all_other_nodes(&candidate.shared, output, payload);
     // Vote for self
+    let id = candidate.shared.id;
     let res = RequestVoteResShared::new(candidate.shared.current_term, 1);
-    return State::candidate_process_request_vote_res(candidate, output, res.capnp_as_ref());
+    State::candidate_process_request_vote_res(candidate, output, id, res.capnp_as_ref())
   }
 
   fn message_to_all_other_nodes<'a>(
@@ -1204,13 +2377,13 @@ impl State {
         .peers
         .iter()
         .filter(|peer| **peer != shared.id)
-        .map(|node| Output::Message(MessageShared::new(shared.id, *node, payload.clone()))),
+        .map(|node| Output::Message(shared.message(*node, payload.clone()))),
     )
   }
 
   fn follower_convert_to_candidate(follower: Follower, output: &mut impl Extend<Output>) -> State {
     debug!("  {:3}: convert_to_candidate", follower.shared.id.0);
-    let candidate = Candidate { shared: follower.shared, received_votes: 0 };
+    let candidate = Candidate { shared: follower.shared, received_votes: None };
     // Candidates (§5.2): On conversion to candidate, start election:
     State::start_election(candidate, output)
   }
@@ -1218,13 +2391,16 @@ impl State {
   fn convert_to_follower(
     self,
     output: &mut impl Extend<Output>,
-    new_leader_hint: NodeID,
+    new_leader_hint: Option<NodeID>,
+    reason: StepDownReason,
   ) -> Follower {
     match self {
       State::Candidate(candidate) => {
-        State::candidate_convert_to_follower(candidate, output, new_leader_hint)
+        State::candidate_convert_to_follower(candidate, output, new_leader_hint, reason)
+      }
+      State::Leader(leader) => {
+        State::leader_convert_to_follower(leader, output, new_leader_hint, reason)
       }
-      State::Leader(leader) => State::leader_convert_to_follower(leader, output, new_leader_hint),
       State::Follower(follower) => {
         // NB: This can happen if a follower gets an AppendEntries from an
         // already elected leader in a new term.
@@ -1236,37 +2412,52 @@ impl State {
   fn candidate_convert_to_follower(
     candidate: Candidate,
     _output: &mut impl Extend<Output>,
-    new_leader_hint: NodeID,
+    new_leader_hint: Option<NodeID>,
+    reason: StepDownReason,
   ) -> Follower {
-    debug!("  {:3}: convert_to_follower leader={:?}", candidate.shared.id.0, new_leader_hint.0);
+    debug!("  {:3}: convert_to_follower leader={:?}", candidate.shared.id.0, new_leader_hint);
+    let term = candidate.shared.current_term;
+    candidate.shared.emit(|| Event::SteppedDown { term, from: Role::Candidate, reason });
     Follower { shared: candidate.shared, leader_hint: new_leader_hint }
   }
 
   fn leader_convert_to_follower(
     mut leader: Leader,
     _output: &mut impl Extend<Output>,
-    new_leader_hint: NodeID,
+    new_leader_hint: Option<NodeID>,
+    reason: StepDownReason,
   ) -> Follower {
-    debug!("  {:3}: convert_to_follower leader={:?}", leader.shared.id.0, new_leader_hint.0);
-    leader = State::clear_outstanding_requests(leader, Some(new_leader_hint));
+    debug!("  {:3}: convert_to_follower leader={:?}", leader.shared.id.0, new_leader_hint);
+    let term = leader.shared.current_term;
+    leader.shared.emit(|| Event::SteppedDown { term, from: Role::Leader, reason });
+    leader = State::clear_outstanding_requests(leader, new_leader_hint);
     Follower { shared: leader.shared, leader_hint: new_leader_hint }
   }
 
   fn candidate_convert_to_leader(candidate: Candidate, output: &mut impl Extend<Output>) -> Leader {
     debug!("  {:3}: convert_to_leader", candidate.shared.id.0);
-
+    candidate.shared.metrics.election_won();
+    let term = candidate.shared.current_term;
+    candidate.shared.emit(|| Event::BecameLeader { term });
+
+    // Leaders: nextIndex for each server, initialized to leader last log index
+    // + 1 (§5.3)
+    let next_index = candidate.shared.log.last().1 + 1;
+    let next_index = candidate.shared.peers.iter().map(|peer| (*peer, next_index)).collect();
+    let first_read_id = candidate.shared.next_read_id;
     let leader = Leader {
       shared: candidate.shared,
 
-      // next_read_id resets to 0 for each new term
-      next_read_id: ReadID(0),
-
       // TODO: roundtrip these through the other states and truncate them here
       // to save allocs
-      _next_index: HashMap::new(),
+      next_index,
       match_index: HashMap::new(),
+      last_contact: HashMap::new(),
+      rounds_sent: VecDeque::new(),
+      acked_sent: HashMap::new(),
       write_buffer: HashMap::new(),
 
+      first_read_id,
       max_outstanding_read_id: None,
       max_confirmed_read_id: None,
       read_buffer: BTreeMap::new(),
@@ -1274,11 +2465,14 @@ impl State {
     // Leaders: Upon election: send initial empty AppendEntries rpcs
     // (heartbeat) to each server; repeat during idle periods to prevent
     // election timeouts (§5.2)
-    State::leader_heartbeat(leader, output)
+    //
+    // NB: The no-op is sent in the initial AppendEntries in place of the empty
+    // heartbeat.
+    State::leader_noop(leader, output)
   }
 
   fn clear_outstanding_requests(mut leader: Leader, new_leader_hint: Option<NodeID>) -> Leader {
-    leader.write_buffer.drain().for_each(|(_, mut future)| {
+    leader.write_buffer.drain().for_each(|(_, (mut future, _))| {
       future.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(new_leader_hint))));
     });
     leader.read_buffer.iter_mut().for_each(|(_, (_, future))| {
@@ -1288,7 +2482,14 @@ impl State {
     leader
   }
 
-  fn shutdown(self) {
+  fn shutdown(mut self) {
+    let shared = self.shared_mut();
+    shared.waiting_reads.iter_mut().for_each(|(_, (_, future))| {
+      future.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))));
+    });
+    shared.local_reads.iter_mut().for_each(|(_, future)| {
+      future.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))));
+    });
     match self {
       State::Follower(_) | State::Candidate(_) => {} // No-op.
       State::Leader(leader) => {
@@ -1297,8 +2498,10 @@ impl State {
     }
   }
 
+  // The number of nodes (including this one) that make up a majority of the
+  // group.
   fn majority(shared: &SharedState) -> usize {
-    (shared.peers.len() + 1) / 2
+    shared.peers.len() / 2 + 1
   }
 }
 
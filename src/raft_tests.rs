@@ -1,100 +1,196 @@
 // Copyright 2020 Daniel Harrison. All Rights Reserved.
 
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::prelude::*;
+use crate::runtime::LogStore;
+use crate::serde::{
+  AppendEntriesReqShared, AppendEntriesResShared, PayloadShared, RequestVoteReqShared,
+  RequestVoteResShared,
+};
 use crate::testutil;
-use crate::testutil::{noopfuture, DeterministicGroup, DeterministicGroup1, DeterministicGroup3};
+use crate::testutil::{noopfuture, DeterministicGroup};
 
 #[test]
 fn election_one() {
   testutil::log_init();
 
-  let mut g = DeterministicGroup1::new();
-  assert_eq!(g.n.raft.debug(), "candidate");
+  let mut g = DeterministicGroup::new(1, Config::default());
+  assert_eq!(g.nodes[0].raft.debug(), "candidate");
 
-  g.n.start_election();
+  g.nodes[0].start_election();
   // TODO: this isn't accurate once we persist hard state
-  assert_eq!(g.n.raft.debug(), "leader");
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
 }
 
 #[test]
 fn election_multi() {
   testutil::log_init();
 
-  let mut g = DeterministicGroup3::new();
-  assert_eq!(g.n0.raft.debug(), "candidate");
+  let mut g = DeterministicGroup::new(3, Config::default());
+  assert_eq!(g.nodes[0].raft.debug(), "candidate");
 
-  g.n0.start_election();
-  assert_eq!(g.n0.raft.debug(), "candidate");
-  assert_eq!(g.n1.raft.debug(), "candidate");
-  assert_eq!(g.n2.raft.debug(), "candidate");
+  g.nodes[0].start_election();
+  assert_eq!(g.nodes[0].raft.debug(), "candidate");
+  assert_eq!(g.nodes[1].raft.debug(), "candidate");
+  assert_eq!(g.nodes[2].raft.debug(), "candidate");
 
   g.drain();
-  assert_eq!(g.n0.raft.debug(), "leader");
-  assert_eq!(g.n1.raft.debug(), "follower");
-  assert_eq!(g.n2.raft.debug(), "follower");
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  assert_eq!(g.nodes[1].raft.debug(), "follower");
+  assert_eq!(g.nodes[2].raft.debug(), "follower");
+}
+
+#[test]
+fn election_sizes() {
+  testutil::log_init();
+
+  for n in [1, 2, 5, 7] {
+    let mut g = DeterministicGroup::new(n, Config::default());
+    g.nodes[0].start_election();
+    g.drain();
+    let mut write = g.nodes[0].write(WriteReq { payload: b"1".to_vec() });
+    g.drain();
+    g.tick(g.cfg().heartbeat_interval);
+    g.drain();
+    assert_eq!(noopfuture::assert_ready(&mut write).unwrap().index, Index(2));
+    for node in g.nodes.iter() {
+      let status = node.raft.status();
+      assert_eq!(status.leader_hint, Some(NodeID(0)), "n={} {:?}", n, status);
+      assert_eq!(status.commit_index, Index(2), "n={} {:?}", n, status);
+    }
+  }
+}
+
+#[test]
+fn step_node() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
+
+  // Only n0 does anything, its vote requests are queued for the others.
+  assert!(g.step(NodeID(0)));
+  assert_eq!(g.nodes[1].input.len(), 1);
+  assert_eq!(g.nodes[2].input.len(), 1);
+  assert!(!g.step(NodeID(0)));
+
+  // One vote is enough.
+  assert!(g.step(NodeID(1)));
+  assert_eq!(g.nodes[0].input.len(), 1);
+  assert!(g.step(NodeID(0)));
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  assert_eq!(g.nodes[2].raft.debug(), "candidate");
+}
+
+#[test]
+fn isolate_and_skew() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(5, Config::default());
+  g.nodes[0].start_election();
+  g.drain();
+  g.tick(g.cfg().heartbeat_interval);
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+
+  // With n0 cut off, n1's fast clock has it call the first election and win it
+  // without n0.
+  g.isolate(NodeID(0));
+  g.set_skew(NodeID(1), 2.0);
+  g.tick(g.cfg().election_timeout / 2);
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  assert_eq!(g.nodes[1].raft.debug(), "leader");
+  assert!(g.nodes.iter().skip(2).all(|node| node.raft.status().leader_hint == Some(NodeID(1))));
+
+  // Once it's reachable again, n0 finds out about the new leader.
+  g.heal(NodeID(0));
+  g.tick(g.cfg().heartbeat_interval);
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "follower");
+  assert_eq!(g.nodes[0].raft.status().leader_hint, Some(NodeID(1)));
 }
 
 #[test]
 fn tick() {
   testutil::log_init();
 
-  let mut g = DeterministicGroup3::new();
+  let mut g = DeterministicGroup::new(3, Config::default());
 
   // all nodes call an election on startup
-  g.n0.tick(Duration::from_nanos(0));
-  g.n1.tick(Duration::from_nanos(0));
-  g.n2.tick(Duration::from_nanos(0));
-  assert_eq!(g.n0.raft.current_term(), Term(1));
+  g.tick_node(NodeID(0), Duration::from_nanos(0));
+  g.tick_node(NodeID(1), Duration::from_nanos(0));
+  g.tick_node(NodeID(2), Duration::from_nanos(0));
+  assert_eq!(g.nodes[0].raft.current_term(), Term(1));
 
   // Nothing happens for election_timeout, so n0 calls a fresh election with a
   // new term.
-  g.n0.tick(g.cfg().election_timeout);
-  assert_eq!(g.n0.raft.current_term(), Term(2));
+  g.tick_node(NodeID(0), g.cfg().election_timeout);
+  assert_eq!(g.nodes[0].raft.current_term(), Term(2));
 
   // This time it works.
   g.drain();
-  assert_eq!(g.n0.raft.debug(), "leader");
-  assert_eq!(g.n1.raft.debug(), "follower");
-  assert_eq!(g.n2.raft.debug(), "follower");
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  assert_eq!(g.nodes[1].raft.debug(), "follower");
+  assert_eq!(g.nodes[2].raft.debug(), "follower");
 
   // Once the heartbeat interval has elapsed, the leader sends out a heartbeat.
   // The followers do nothing.
-  g.n0.tick(g.cfg().heartbeat_interval);
-  g.n1.tick(g.cfg().heartbeat_interval);
-  g.n2.tick(g.cfg().heartbeat_interval);
+  g.tick_node(NodeID(0), g.cfg().heartbeat_interval);
+  g.tick_node(NodeID(1), g.cfg().heartbeat_interval);
+  g.tick_node(NodeID(2), g.cfg().heartbeat_interval);
   g.drain();
-  assert_eq!(g.n0.raft.debug(), "leader");
-  assert_eq!(g.n1.raft.debug(), "follower");
-  assert_eq!(g.n2.raft.debug(), "follower");
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  assert_eq!(g.nodes[1].raft.debug(), "follower");
+  assert_eq!(g.nodes[2].raft.debug(), "follower");
 
   // If the leader doesn't heartbeat for the timeout interval, an election is
   // called.
-  g.n1.tick(g.cfg().election_timeout);
+  g.tick_node(NodeID(1), g.cfg().election_timeout);
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "follower");
+  assert_eq!(g.nodes[1].raft.debug(), "leader");
+  assert_eq!(g.nodes[2].raft.debug(), "follower");
+}
+
+#[test]
+fn first_tick_after_leader_contact() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
   g.drain();
-  assert_eq!(g.n0.raft.debug(), "follower");
-  assert_eq!(g.n1.raft.debug(), "leader");
-  assert_eq!(g.n2.raft.debug(), "follower");
+  assert_eq!(g.nodes[1].raft.debug(), "follower");
+
+  // n1 heard from the leader before its first tick, so the tick starts its
+  // election timer instead of timing it out.
+  g.tick_node(NodeID(1), Duration::from_nanos(0));
+  assert_eq!(g.nodes[1].raft.debug(), "follower");
+
+  // Once the timeout elapses without another heartbeat, it calls an election.
+  g.tick_node(NodeID(1), g.cfg().election_timeout);
+  assert_eq!(g.nodes[1].raft.debug(), "candidate");
 }
 
 #[test]
 fn write_future() {
   testutil::log_init();
 
-  let mut g = DeterministicGroup3::new();
-  g.n0.start_election();
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
   g.drain();
 
   let payload = String::from("write_future").into_bytes();
-  let mut res = g.n0.write(WriteReq { payload: payload.clone() });
+  let mut res = g.nodes[0].write(WriteReq { payload: payload.clone() });
   noopfuture::assert_pending(&mut res);
 
   g.drain();
   let res = noopfuture::assert_ready(&mut res).unwrap();
   // TODO: don't assume that the leader has it synced, it's possible for the
   // majority to be all followers
-  assert_eq!(g.n0.log.get(res.index), Some(&payload));
+  assert_eq!(g.nodes[0].log.user_payloads(res.index, res.index).unwrap(), vec![payload]);
 }
 
 #[test]
@@ -106,13 +202,14 @@ fn read_future() {
   // - two reads while there are no outstanding append entries will batch
   // - read during each state transition pair, confirmed and non-confirmed
 
-  let mut g = DeterministicGroup3::new();
-  g.n0.start_election();
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
   g.drain();
 
   let payload = String::from("read_future").into_bytes();
-  g.n0.write(WriteReq { payload: payload.clone() });
-  let mut read = g.n0.read(ReadReq { payload: vec![] });
+  g.nodes[0].write(WriteReq { payload: payload.clone() });
+  let mut read =
+    g.nodes[0].read(ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] });
   noopfuture::assert_pending(&mut read);
 
   g.drain();
@@ -120,29 +217,714 @@ fn read_future() {
   assert_eq!(read.payload, payload);
 }
 
+#[test]
+fn status() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let status = g.nodes[0].raft.status();
+  assert_eq!(status.role, Role::Candidate);
+  assert_eq!(status.leader_hint, None);
+  assert_eq!(status.peers, vec![]);
+
+  g.nodes[0].start_election();
+  g.drain();
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("status").into_bytes() });
+  g.drain();
+  let _ = noopfuture::assert_ready(&mut res);
+
+  let status = g.nodes[0].raft.status();
+  assert_eq!(status.role, Role::Leader);
+  assert_eq!(status.current_term, Term(1));
+  assert_eq!(status.voted_for, Some(g.nodes[0].raft.id()));
+  assert_eq!(status.leader_hint, Some(g.nodes[0].raft.id()));
+  assert_eq!(status.commit_index, Index(2));
+  assert_eq!(status.last_applied, Index(2));
+  assert_eq!(status.log_last, (Term(1), Index(2)));
+  let peers: Vec<_> = status.peers.iter().map(|p| (p.id, p.match_index, p.next_index)).collect();
+  assert_eq!(
+    peers,
+    vec![(g.nodes[1].raft.id(), Index(2), Index(3)), (g.nodes[2].raft.id(), Index(2), Index(3))]
+  );
+
+  let status = g.nodes[1].raft.status();
+  assert_eq!(status.role, Role::Follower);
+  assert_eq!(status.leader_hint, Some(g.nodes[0].raft.id()));
+  assert_eq!(status.log_last, (Term(1), Index(2)));
+  assert_eq!(status.peers, vec![]);
+}
+
+#[test]
+fn metrics() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let metrics = Arc::new(MemMetrics::new());
+  g.nodes[0].raft.set_metrics(metrics.clone());
+
+  g.tick_node(NodeID(0), Duration::from_nanos(0));
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("metrics").into_bytes() });
+  let mut read =
+    g.nodes[0].read(ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] });
+  g.tick_node(NodeID(0), g.cfg().heartbeat_interval);
+  g.drain();
+  let _ = noopfuture::assert_ready(&mut res);
+  let _ = noopfuture::assert_ready(&mut read);
+
+  let m = metrics.snapshot();
+  assert_eq!(m.elections_started, 1);
+  assert_eq!(m.elections_won, 1);
+  assert_eq!(m.term_changes, 1);
+  assert_eq!(m.proposals, 1);
+  assert_eq!(m.commit_latencies, vec![g.cfg().heartbeat_interval]);
+  // The initial heartbeat, the write, the read, and the tick's heartbeat.
+  assert_eq!(m.append_entries_sent, 8);
+  assert_eq!(m.append_entries_rejected, 0);
+  assert_eq!(m.read_batch_sizes, vec![1]);
+}
+
+#[test]
+fn read_batch() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let metrics = Arc::new(MemMetrics::new());
+  g.nodes[0].raft.set_metrics(metrics.clone());
+  g.nodes[0].start_election();
+  g.drain();
+  let payload = String::from("a").into_bytes();
+  let mut res = g.nodes[0].write(WriteReq { payload: payload.clone() });
+
+  // Reads that become servable together, here when the write is applied, are
+  // handed to the state machine in one batch and served at the same index. This
+  // includes ones that don't need the leader to confirm it's still leader.
+  let consistencies = [
+    ReadConsistency::Linearizable,
+    ReadConsistency::AtLeastIndex(Index(2)),
+    ReadConsistency::Linearizable,
+    ReadConsistency::Linearizable,
+  ];
+  let mut reads: Vec<_> = consistencies
+    .iter()
+    .map(|consistency| g.nodes[0].read(ReadReq { consistency: *consistency, payload: vec![] }))
+    .collect();
+  g.drain();
+  let _ = noopfuture::assert_ready(&mut res);
+  for read in reads.iter_mut() {
+    let read = noopfuture::assert_ready(read).unwrap();
+    assert_eq!((read.index, read.payload), (Index(2), payload.clone()));
+  }
+  assert_eq!(metrics.snapshot().read_batch_sizes, vec![4]);
+}
+
+#[test]
+fn events() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let events = Arc::new(MemEvents::new());
+  g.nodes[0].raft.set_events(events.clone());
+  g.nodes[1].raft.set_events(events.clone());
+  g.nodes[2].raft.set_events(events.clone());
+  let (n0, n1, n2) = (g.nodes[0].raft.id(), g.nodes[1].raft.id(), g.nodes[2].raft.id());
+
+  g.nodes[0].start_election();
+  g.drain();
+  #[rustfmt::skip]
+  let expected = vec![
+    (n0, Event::BecameCandidate { term: Term(1) }),
+    (n1, Event::SteppedDown { term: Term(1), from: Role::Candidate, reason: StepDownReason::HigherTerm(n0) }),
+    (n1, Event::VoteGranted { term: Term(1), candidate: n0 }),
+    (n2, Event::SteppedDown { term: Term(1), from: Role::Candidate, reason: StepDownReason::HigherTerm(n0) }),
+    (n2, Event::VoteGranted { term: Term(1), candidate: n0 }),
+    (n0, Event::BecameLeader { term: Term(1) }),
+    (n0, Event::CommitAdvanced { from: Index(0), to: Index(1) }),
+  ];
+  assert_eq!(sorted_by_node(events.take()), sorted_by_node(expected));
+
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  g.drain();
+  let _ = noopfuture::assert_ready(&mut res);
+  // The followers hear about the no-op's commit with the write.
+  #[rustfmt::skip]
+  let expected = vec![
+    (n0, Event::CommitAdvanced { from: Index(1), to: Index(2) }),
+    (n1, Event::CommitAdvanced { from: Index(0), to: Index(1) }),
+    (n2, Event::CommitAdvanced { from: Index(0), to: Index(1) }),
+  ];
+  assert_eq!(sorted_by_node(events.take()), sorted_by_node(expected));
+
+  // An unfinished entry on n0 is later overwritten by n1.
+  g.nodes[0].write(WriteReq { payload: String::from("2").into_bytes() });
+  g.nodes[0].drop_messages();
+  g.nodes[1].start_election();
+  g.drain();
+  let mut res = g.nodes[1].write(WriteReq { payload: String::from("3").into_bytes() });
+  g.drain();
+  let _ = noopfuture::assert_ready(&mut res);
+  let taken = events.take();
+  assert!(taken.contains(&(
+    n0,
+    Event::SteppedDown {
+      term: Term(2),
+      from: Role::Leader,
+      reason: StepDownReason::HigherTerm(n1)
+    }
+  )));
+  assert!(taken.contains(&(n1, Event::BecameLeader { term: Term(2) })));
+  assert!(taken.contains(&(n0, Event::LogTruncated { index: Index(3) })));
+
+  // A vote request from an old term is denied.
+  let req =
+    PayloadShared::RequestVoteReq(RequestVoteReqShared::new(Term(1), n0, Index(1), Term(1)));
+  let msg = MessageShared::new(n0, n2, req, g.cfg().cluster_id, PROTOCOL_VERSION);
+  g.nodes[2].step(Input::Message(msg.capnp_as_ref()));
+  assert_eq!(
+    events.take(),
+    vec![(
+      n2,
+      Event::VoteDenied { term: Term(1), candidate: n0, reason: VoteDeniedReason::StaleTerm }
+    )]
+  );
+}
+
+#[test]
+fn persist_failed() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let events = Arc::new(MemEvents::new());
+  g.nodes[0].raft.set_events(events.clone());
+  let (n0, n1) = (g.nodes[0].raft.id(), g.nodes[1].raft.id());
+  g.nodes[0].start_election();
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  events.take();
+
+  // A write fails to persist on the leader, which fails the write and steps
+  // down.
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  g.nodes[0].drop_messages();
+  g.nodes[0].fail_persist(PersistError::new("disk full"));
+  assert_eq!(
+    noopfuture::assert_ready(&mut res),
+    Err(ClientError::PersistError(PersistError::new("disk full")))
+  );
+  assert_eq!(g.nodes[0].raft.debug(), "follower");
+  assert_eq!(
+    events.take(),
+    vec![(
+      n0,
+      Event::SteppedDown {
+        term: Term(1),
+        from: Role::Leader,
+        reason: StepDownReason::StorageFailed
+      }
+    )]
+  );
+
+  // It doesn't vote until a write succeeds again.
+  g.nodes[1].start_election();
+  g.drain();
+  assert_eq!(g.nodes[1].raft.debug(), "leader");
+  assert!(events.take().contains(&(
+    n0,
+    Event::VoteDenied { term: Term(2), candidate: n1, reason: VoteDeniedReason::StorageFailed }
+  )));
+
+  // A write fails to persist on a follower, which rejects the entries so the
+  // leader sends them again.
+  g.isolate(n0);
+  let mut res = g.nodes[1].write(WriteReq { payload: String::from("2").into_bytes() });
+  g.step(n1);
+  let inputs: Vec<_> = g.nodes[2].input.drain(..).collect();
+  inputs.iter().for_each(|input| g.nodes[2].step(input.as_ref()));
+  g.nodes[2].fail_persist(PersistError::new("disk full"));
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res), Ok(WriteRes { term: Term(2), index: Index(3) }));
+  assert_eq!(g.nodes[2].log.last_index(), Some(Index(3)));
+}
+
+#[test]
+fn persist_failed_heartbeat() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let (n0, n1, n2) = (g.nodes[0].raft.id(), g.nodes[1].raft.id(), g.nodes[2].raft.id());
+  g.nodes[0].start_election();
+  g.drain();
+  g.isolate(n2);
+
+  // n1 starts writing a new entry and a heartbeat arrives before it's done.
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  g.step(n0);
+  let inputs: Vec<_> = g.nodes[1].input.drain(..).collect();
+  inputs.iter().for_each(|input| g.nodes[1].step(input.as_ref()));
+  g.tick_node(n0, g.cfg().heartbeat_interval);
+  g.step(n0);
+  let inputs: Vec<_> = g.nodes[1].input.drain(..).collect();
+  inputs.iter().for_each(|input| g.nodes[1].step(input.as_ref()));
+
+  // The write fails. The heartbeat must not have acknowledged the entry, or
+  // it would be committed without being durable on a majority.
+  g.nodes[1].fail_persist(PersistError::new("disk full"));
+  g.step(n1);
+  g.step(n0);
+  assert_eq!(g.nodes[0].raft.status().commit_index, Index(1));
+  noopfuture::assert_pending(&mut res);
+
+  // The leader resends it and this time it works.
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res), Ok(WriteRes { term: Term(1), index: Index(2) }));
+}
+
+#[test]
+fn persist_failed_late_votes() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let (n0, n1, n2) = (g.nodes[0].raft.id(), g.nodes[1].raft.id(), g.nodes[2].raft.id());
+  g.nodes[0].start_election();
+  g.drain();
+
+  // The leader steps down after a failed write and its election timer runs
+  // out, but it can't campaign.
+  g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  g.nodes[0].drop_messages();
+  g.nodes[0].fail_persist(PersistError::new("disk full"));
+  g.tick_node(n0, Duration::from_nanos(0));
+  g.tick_node(n0, g.cfg().election_timeout * 2);
+  assert_eq!(g.nodes[0].raft.debug(), "candidate");
+  assert_eq!(g.nodes[0].raft.current_term(), Term(1));
+
+  // Duplicates of the votes that made it leader of this term arrive. It was
+  // already leader of the term once, so it must not become leader again.
+  for src in [n1, n2].iter() {
+    let payload = PayloadShared::RequestVoteRes(RequestVoteResShared::new(Term(1), 1));
+    let msg = MessageShared::new(*src, n0, payload, g.cfg().cluster_id, PROTOCOL_VERSION);
+    g.nodes[0].step(Input::Message(msg.capnp_as_ref()));
+  }
+  assert_eq!(g.nodes[0].raft.debug(), "candidate");
+}
+
+#[test]
+fn persist_failed_recovers() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(1, Config::default());
+  let n0 = g.nodes[0].raft.id();
+  g.nodes[0].start_election();
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+
+  // A write fails and the only node steps down.
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  g.nodes[0].fail_persist(PersistError::new("disk full"));
+  assert!(noopfuture::assert_ready(&mut res).is_err());
+  g.tick_node(n0, Duration::from_nanos(0));
+
+  // It can't campaign, so it checks whether its storage has recovered instead.
+  // The first check fails too.
+  g.tick_node(n0, g.cfg().election_timeout * 2);
+  assert_eq!(g.nodes[0].raft.debug(), "candidate");
+  assert_eq!(g.nodes[0].raft.current_term(), Term(1));
+  g.nodes[0].fail_persist(PersistError::new("disk full"));
+  g.tick_node(n0, g.cfg().election_timeout * 2);
+  assert_eq!(g.nodes[0].raft.current_term(), Term(1));
+
+  // The next one works, so the election after it does too.
+  g.drain();
+  assert_eq!(g.nodes[0].raft.current_term(), Term(1));
+  g.tick_node(n0, g.cfg().election_timeout * 2);
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  assert_eq!(g.nodes[0].raft.current_term(), Term(2));
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("2").into_bytes() });
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res), Ok(WriteRes { term: Term(2), index: Index(3) }));
+}
+
+#[test]
+fn noop() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
+  g.drain();
+
+  // A new leader commits a no-op without waiting for a client write.
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  assert_eq!(g.nodes[0].raft.status().commit_index, Index(1));
+  for node in [&g.nodes[0], &g.nodes[1], &g.nodes[2]].iter() {
+    let entries = node.log.read(Index(1), Index(1)).unwrap();
+    let entry = entries[0].capnp_as_ref();
+    assert_eq!(entry.term(), Term(1));
+    assert!(matches!(entry.kind(), Ok(EntryKind::Noop)));
+  }
+
+  // The no-op is not applied to the state machine.
+  let payload = String::from("noop").into_bytes();
+  let mut res = g.nodes[0].write(WriteReq { payload: payload.clone() });
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res), Ok(WriteRes { term: Term(1), index: Index(2) }));
+  let mut read =
+    g.nodes[0].read(ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] });
+  g.drain();
+  let read = noopfuture::assert_ready(&mut read).unwrap();
+  assert_eq!((read.index, read.payload), (Index(2), payload.clone()));
+
+  // A leader of a later term also commits everything from earlier terms before
+  // any client write.
+  g.nodes[1].start_election();
+  g.drain();
+  assert_eq!(g.nodes[1].raft.debug(), "leader");
+  assert_eq!(g.nodes[1].raft.status().commit_index, Index(3));
+  let mut read =
+    g.nodes[1].read(ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] });
+  g.drain();
+  let read = noopfuture::assert_ready(&mut read).unwrap();
+  assert_eq!((read.index, read.payload), (Index(3), payload));
+}
+
+#[test]
+fn read_consistency() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
+  g.drain();
+  g.tick(Duration::from_millis(1));
+  let payload = String::from("a").into_bytes();
+  let mut res = g.nodes[0].write(WriteReq { payload: payload.clone() });
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res), Ok(WriteRes { term: Term(1), index: Index(2) }));
+
+  // A follower waits to serve a read at an index until it has applied it.
+  let consistency = ReadConsistency::AtLeastIndex(Index(2));
+  let mut read = g.nodes[1].read(ReadReq { consistency, payload: vec![] });
+  g.drain();
+  noopfuture::assert_pending(&mut read);
+  g.tick(g.cfg().heartbeat_interval);
+  g.drain();
+  let read = noopfuture::assert_ready(&mut read).unwrap();
+  assert_eq!((read.index, read.payload), (Index(2), payload.clone()));
+
+  // A read of an index that no node has heard of fails instead of waiting for
+  // it forever.
+  for id in [NodeID(0), NodeID(1)].iter() {
+    let consistency = ReadConsistency::AtLeastIndex(Index(u64::MAX));
+    let mut read = g.nodes[id.0 as usize].read(ReadReq { consistency, payload: vec![] });
+    let err = ClientError::UnknownIndex(Index(u64::MAX));
+    assert_eq!(noopfuture::assert_ready(&mut read), Err(err));
+  }
+
+  // A follower that heard from the leader recently enough serves a bounded
+  // staleness read itself, as does a leader that heard from a majority.
+  let consistency = ReadConsistency::MaxStaleness(Duration::from_millis(1));
+  for id in [NodeID(0), NodeID(2)].iter() {
+    let mut read = g.nodes[id.0 as usize].read(ReadReq { consistency, payload: vec![] });
+    assert!(g.nodes[id.0 as usize]
+      .output
+      .iter()
+      .all(|output| !matches!(output, Output::Message(_))));
+    g.drain();
+    let read = noopfuture::assert_ready(&mut read).unwrap();
+    assert_eq!((read.index, read.payload), (Index(2), payload.clone()));
+  }
+
+  // Once it hasn't, the read falls back to being linearizable, which only the
+  // leader can serve.
+  g.tick_node(NodeID(2), Duration::from_millis(2));
+  let mut read = g.nodes[2].read(ReadReq { consistency, payload: vec![] });
+  let err = ClientError::NotLeaderError(NotLeaderError { hint: Some(NodeID(0)) });
+  assert_eq!(noopfuture::assert_ready(&mut read), Err(err));
+
+  // The leader only knows it was still leader as of when it sent the round its
+  // peers responded to, not when their responses arrived.
+  g.tick_node(NodeID(0), g.cfg().heartbeat_interval);
+  g.step(NodeID(0));
+  g.tick_node(NodeID(0), Duration::from_millis(2));
+  g.nodes[0].drop_messages();
+  g.drain();
+  g.isolate(NodeID(1));
+  g.isolate(NodeID(2));
+  let mut read = g.nodes[0].read(ReadReq { consistency, payload: vec![] });
+  g.drain();
+  noopfuture::assert_pending(&mut read);
+}
+
+#[test]
+fn read_at_truncated_index() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
+  g.drain();
+
+  // Two writes on n0 never reach the rest of the group, so they aren't
+  // committed. A read at the second one waits for it.
+  g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  g.nodes[0].write(WriteReq { payload: String::from("2").into_bytes() });
+  g.nodes[0].drop_messages();
+  g.drain();
+  let consistency = ReadConsistency::AtLeastIndex(Index(3));
+  let mut read = g.nodes[0].read(ReadReq { consistency, payload: vec![] });
+  g.drain();
+  noopfuture::assert_pending(&mut read);
+
+  // n1 is elected and its no-op replaces the writes on n0. Nothing else is
+  // written, so index 3 may never exist and the read fails instead of waiting.
+  g.nodes[1].start_election();
+  g.drain();
+  assert_eq!(g.nodes[1].raft.debug(), "leader");
+  assert_eq!(g.nodes[0].raft.status().log_last, (Term(2), Index(2)));
+  assert_eq!(noopfuture::assert_ready(&mut read), Err(ClientError::UnknownIndex(Index(3))));
+}
+
+#[test]
+fn protocol_errors() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let events = Arc::new(MemEvents::new());
+  g.nodes[0].raft.set_events(events.clone());
+  g.nodes[1].raft.set_events(events.clone());
+  let (n0, n1, n2) = (g.nodes[0].raft.id(), g.nodes[1].raft.id(), g.nodes[2].raft.id());
+  g.nodes[0].start_election();
+  g.drain();
+  g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  g.drain();
+  g.tick(g.cfg().heartbeat_interval);
+  g.drain();
+  assert_eq!(g.nodes[1].raft.status().commit_index, Index(2));
+  events.take();
+
+  let cluster_id = g.cfg().cluster_id;
+  let append_after = |src: NodeID, dest: NodeID, term: u64, prev: u64, entries: &[(u64, u64)]| {
+    let entries: Vec<_> = entries
+      .iter()
+      .map(|(term, index)| EntryShared::new(Term(*term), Index(*index), &[], EntryKind::Noop))
+      .collect();
+    let (prev_log_index, prev_log_term) = (Index(prev), Term(0));
+    let req = AppendEntriesReqShared::new(
+      Term(term),
+      src,
+      prev_log_index,
+      prev_log_term,
+      Index(0),
+      ReadID(0),
+      &entries,
+    );
+    let req = PayloadShared::AppendEntriesReq(req);
+    MessageShared::new(src, dest, req, cluster_id, PROTOCOL_VERSION)
+  };
+  let append = |src, dest, term, entries: &[(u64, u64)]| append_after(src, dest, term, 0, entries);
+  let vote = |src: NodeID, dest: NodeID, candidate_id: NodeID| {
+    let req = RequestVoteReqShared::new(Term(2), candidate_id, Index(2), Term(1));
+    let req = PayloadShared::RequestVoteReq(req);
+    MessageShared::new(src, dest, req, cluster_id, PROTOCOL_VERSION)
+  };
+  let cases = vec![
+    (vote(n0, n2, n0), ProtocolError::Misaddressed(n2)),
+    (vote(NodeID(3), n1, NodeID(3)), ProtocolError::UnknownSender),
+    (vote(n2, n1, n0), ProtocolError::SenderMismatch(n0)),
+    (append(n0, n1, 1, &[(1, 2)]), ProtocolError::InvalidEntries),
+    (append(n0, n1, 1, &[(1, 1), (0, 2)]), ProtocolError::InvalidEntries),
+    (append(n0, n1, 1, &[(2, 1)]), ProtocolError::InvalidEntries),
+    (append(n2, n1, 2, &[(2, 1)]), ProtocolError::TruncatesCommitted(Index(1))),
+    (append_after(n0, n1, 1, u64::MAX, &[(1, 0)]), ProtocolError::InvalidEntries),
+    (append_after(n0, n1, 1, u64::MAX - 1, &[(1, u64::MAX)]), ProtocolError::InvalidEntries),
+    (append(n0, n1, u64::MAX, &[]), ProtocolError::TermExhausted),
+  ];
+  for (msg, expected) in cases {
+    let before = format!("{:?}", g.nodes[1].raft.status());
+    let mut output = vec![];
+    let res = g.nodes[1].raft.step(&mut output, Input::Message(msg.capnp_as_ref()));
+    assert_eq!(res, Err(expected.clone()));
+    assert!(output.is_empty());
+    assert_eq!(format!("{:?}", g.nodes[1].raft.status()), before);
+    let src = msg.capnp_as_ref().src();
+    assert_eq!(events.take(), vec![(n1, Event::MessageRejected { src, error: expected })]);
+  }
+
+  // Only a faulty peer would claim to be leader of the term n0 is leader of.
+  let msg = append(n1, n0, 1, &[]);
+  let mut output = vec![];
+  let res = g.nodes[0].raft.step(&mut output, Input::Message(msg.capnp_as_ref()));
+  assert_eq!(res, Err(ProtocolError::DuplicateLeader(Term(1))));
+  assert_eq!(g.nodes[0].raft.status().role, Role::Leader);
+
+  // Or acknowledge an entry n0 doesn't have.
+  let res = AppendEntriesResShared::new(Term(1), 1, Index(u64::MAX), ReadID(u64::MAX));
+  let res = PayloadShared::AppendEntriesRes(res);
+  let msg = MessageShared::new(n1, n0, res, cluster_id, PROTOCOL_VERSION);
+  let res = g.nodes[0].raft.step(&mut output, Input::Message(msg.capnp_as_ref()));
+  assert_eq!(res, Err(ProtocolError::AckedUnknownIndex(Index(u64::MAX))));
+  assert_eq!(g.nodes[0].raft.status().role, Role::Leader);
+
+  // The group carries on as if none of that happened.
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("2").into_bytes() });
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res), Ok(WriteRes { term: Term(1), index: Index(3) }));
+}
+
+#[test]
+fn term_exhausted() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let (n0, n1) = (g.nodes[0].raft.id(), g.nodes[1].raft.id());
+
+  // A node can be moved to the second to last term, but it can't campaign for
+  // the last one.
+  let term = Term(u64::MAX - 1);
+  let res = AppendEntriesResShared::new(term, 0, Index(0), ReadID(0));
+  let res = PayloadShared::AppendEntriesRes(res);
+  let msg = MessageShared::new(n1, n0, res, g.cfg().cluster_id, PROTOCOL_VERSION);
+  g.nodes[0].step(Input::Message(msg.capnp_as_ref()));
+  g.tick_node(n0, Duration::from_nanos(0));
+  g.tick_node(n0, g.cfg().election_timeout * 2);
+  assert_eq!(g.nodes[0].raft.debug(), "candidate");
+  assert_eq!(g.nodes[0].raft.current_term(), term);
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  let err = ClientError::NotLeaderError(NotLeaderError { hint: None });
+  assert_eq!(noopfuture::assert_ready(&mut res), Err(err));
+}
+
+#[test]
+fn cluster_id() {
+  testutil::log_init();
+
+  let cfg = Config { cluster_id: ClusterID(7), ..Config::default() };
+  let mut g = DeterministicGroup::new(3, cfg.clone());
+  let (n0, n1) = (g.nodes[0].raft.id(), g.nodes[1].raft.id());
+  g.nodes[0].start_election();
+  g.drain();
+  g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  let sent: Vec<_> = g.nodes[0]
+    .output
+    .iter()
+    .filter_map(|output| match output {
+      Output::Message(msg) => Some((msg.capnp_as_ref().cluster_id(), msg.capnp_as_ref().version())),
+      _ => None,
+    })
+    .collect();
+  assert_eq!(sent, vec![(ClusterID(7), PROTOCOL_VERSION); 2]);
+  g.drain();
+  assert_eq!(g.nodes[1].raft.status().leader_hint, Some(n0));
+
+  // A node from another group, or one at a newer version than this one
+  // understands, is ignored.
+  let vote = |cluster_id: ClusterID, version: u32| {
+    let req = RequestVoteReqShared::new(Term(5), n0, Index(5), Term(5));
+    MessageShared::new(n0, n1, PayloadShared::RequestVoteReq(req), cluster_id, version)
+  };
+  let cases = vec![
+    (vote(ClusterID(8), PROTOCOL_VERSION), ProtocolError::ClusterMismatch(ClusterID(8))),
+    (
+      vote(ClusterID(7), PROTOCOL_VERSION + 1),
+      ProtocolError::UnsupportedVersion(PROTOCOL_VERSION + 1),
+    ),
+  ];
+  for (msg, expected) in cases {
+    let mut output = vec![];
+    let res = g.nodes[1].raft.step(&mut output, Input::Message(msg.capnp_as_ref()));
+    assert_eq!(res, Err(expected));
+    assert_eq!(g.nodes[1].raft.current_term(), Term(1));
+  }
+
+  // The group of a live node can't be changed.
+  let other = Config { cluster_id: ClusterID(8), ..cfg };
+  assert_eq!(g.nodes[1].raft.update_config(other), Err(ConfigError::ClusterIDChanged));
+}
+
+fn sorted_by_node(mut events: Vec<(NodeID, Event)>) -> Vec<(NodeID, Event)> {
+  // NB: This is a stable sort so the per-node order is preserved.
+  events.sort_by_key(|(id, _)| id.0);
+  events
+}
+
+#[test]
+fn config() {
+  testutil::log_init();
+
+  assert_eq!(Config::default().validate(), Ok(()));
+  let cfg = Config { election_timeout: Duration::from_millis(0), ..Config::default() };
+  assert_eq!(cfg.validate(), Err(ConfigError::ZeroElectionTimeout));
+  let cfg = Config { heartbeat_interval: Duration::from_millis(0), ..Config::default() };
+  assert_eq!(cfg.validate(), Err(ConfigError::ZeroHeartbeatInterval));
+  let cfg = Config { protocol_version: 0, ..Config::default() };
+  assert_eq!(cfg.validate(), Err(ConfigError::UnsupportedProtocolVersion(0)));
+  let cfg = Config { protocol_version: PROTOCOL_VERSION + 1, ..Config::default() };
+  assert_eq!(cfg.validate(), Err(ConfigError::UnsupportedProtocolVersion(PROTOCOL_VERSION + 1)));
+  let cfg = Config {
+    election_timeout: Duration::from_millis(10),
+    heartbeat_interval: Duration::from_millis(10),
+    ..Config::default()
+  };
+  assert_eq!(
+    cfg.validate().map_err(|err| err.to_string()),
+    Err("heartbeat_interval (10ms) must be less than election_timeout (10ms)".to_string())
+  );
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.tick_node(NodeID(0), Duration::from_nanos(0));
+  g.tick_node(NodeID(1), Duration::from_nanos(0));
+  g.tick_node(NodeID(2), Duration::from_nanos(0));
+  g.tick_node(NodeID(0), g.cfg().election_timeout);
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+
+  // An invalid config is rejected and the old one is kept.
+  assert!(g.nodes[1].raft.update_config(cfg).is_err());
+  assert_eq!(g.nodes[1].raft.config().election_timeout, g.cfg().election_timeout);
+
+  // n1 is given a longer election timeout and so no longer calls an election
+  // when the old one elapses.
+  let cfg = Config { election_timeout: g.cfg().election_timeout * 4, ..g.cfg().clone() };
+  g.nodes[1].raft.update_config(cfg).unwrap();
+  g.tick_node(NodeID(1), g.cfg().election_timeout * 2);
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  assert_eq!(g.nodes[1].raft.debug(), "follower");
+
+  // Once the new one elapses, it does.
+  g.tick_node(NodeID(1), g.cfg().election_timeout * 2);
+  g.drain();
+  assert_eq!(g.nodes[1].raft.debug(), "leader");
+}
+
 #[test]
 fn leader_timeout() {
   testutil::log_init();
 
-  let mut g = DeterministicGroup3::new();
-  g.n0.start_election();
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
   g.drain();
-  assert_eq!(g.n0.raft.debug(), "leader");
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+  // n1's election timer starts with its first tick.
+  g.tick_node(NodeID(1), Duration::from_nanos(0));
 
   // A write is sent to n0 while it's the leader.
   let payload = String::from("leader_timeout").into_bytes();
   let req = WriteReq { payload: payload };
-  let mut res = g.n0.write(req);
+  let mut res = g.nodes[0].write(req);
+  // The write's AppendEntries are lost, otherwise n1 (which didn't get the
+  // write) couldn't win the election below.
+  g.nodes[0].drop_messages();
 
   // n1 doesn't see a heartbeat from n0 for too long and calls an election.
-  g.n1.tick(g.cfg().election_timeout * 2);
+  g.tick_node(NodeID(1), g.cfg().election_timeout * 2);
   g.drain();
-  assert_eq!(g.n1.raft.debug(), "leader");
+  assert_eq!(g.nodes[1].raft.debug(), "leader");
 
   // The n0 write should have errored.
   assert_eq!(
     noopfuture::assert_ready(&mut res),
-    Err(ClientError::NotLeaderError(NotLeaderError::new(Some(g.n1.raft.id()))))
+    Err(ClientError::NotLeaderError(NotLeaderError::new(Some(g.nodes[1].raft.id()))))
   );
 }
 
@@ -150,43 +932,191 @@ fn leader_timeout() {
 fn overwrite_entries() {
   testutil::log_init();
 
-  let mut g = DeterministicGroup3::new();
-  g.n0.start_election();
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
   g.drain();
-  assert_eq!(g.n0.raft.debug(), "leader");
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
 
   // A write is committed with n0 as leader.
-  let mut res = g.n0.write(WriteReq { payload: String::from("1").into_bytes() });
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
   g.drain();
   let _ = noopfuture::assert_ready(&mut res);
 
   // Another write is started, but this one will not finish.
-  g.n0.write(WriteReq { payload: String::from("2").into_bytes() });
+  g.nodes[0].write(WriteReq { payload: String::from("2").into_bytes() });
+  g.nodes[0].drop_messages();
 
   // n1 is elected as the new leader.
-  g.n1.start_election();
+  g.nodes[1].start_election();
   g.drain();
-  assert_eq!(g.n1.raft.debug(), "leader");
+  assert_eq!(g.nodes[1].raft.debug(), "leader");
 
   // A write is committed with n1 as leader.
-  let mut res = g.n1.write(WriteReq { payload: String::from("3").into_bytes() });
+  let mut res = g.nodes[1].write(WriteReq { payload: String::from("3").into_bytes() });
   g.drain();
   let _ = noopfuture::assert_ready(&mut res);
 
   // Leadership is transferred back to n0.
-  g.n0.start_election();
+  g.nodes[0].start_election();
   g.drain();
-  assert_eq!(g.n0.raft.debug(), "leader");
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
 
   println!("\n\nWIP\n\n");
 
   // A read on n1 shouldn't have the unfinished write.
-  let mut res = g.n0.read(ReadReq { payload: vec![] });
+  let mut res =
+    g.nodes[0].read(ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] });
   g.drain();
   let res = noopfuture::assert_ready(&mut res).unwrap();
   assert_eq!(res.payload, String::from("13").into_bytes());
 }
 
+#[test]
+fn catch_up_follower() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
+  g.drain();
+  assert_eq!(g.nodes[0].raft.debug(), "leader");
+
+  // n2 misses a write, which is committed without it.
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  let n2 = g.nodes[2].raft.id();
+  g.nodes[0].output.retain(|output| match output {
+    Output::Message(msg) => msg.capnp_as_ref().dest() != n2,
+    _ => true,
+  });
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res).unwrap().index, Index(2));
+  assert_eq!(g.nodes[2].raft.status().log_last, (Term(1), Index(1)));
+
+  // n2 rejects the next heartbeat, so n0 reads the missing entry back from its
+  // log and resends it.
+  g.tick_node(NodeID(0), g.cfg().heartbeat_interval);
+  g.drain();
+  assert_eq!(g.nodes[2].raft.status().log_last, (Term(1), Index(2)));
+  assert_eq!(g.nodes[2].log.user_payloads(Index(2), Index(2)).unwrap(), vec![b"1".to_vec()]);
+}
+
+#[test]
+fn duplicate_append_entries() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
+  g.drain();
+
+  // The AppendEntries for the first write are delivered twice, the second time
+  // after a later write has been appended.
+  g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  let n1 = g.nodes[1].raft.id();
+  let dups: Vec<_> = g.nodes[0]
+    .output
+    .iter()
+    .filter_map(|output| match output {
+      Output::Message(msg) if msg.capnp_as_ref().dest() == n1 => {
+        Some(OwnedInput::Message(msg.clone()))
+      }
+      _ => None,
+    })
+    .collect();
+  g.drain();
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("2").into_bytes() });
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res).unwrap().index, Index(3));
+  g.nodes[1].input.extend(dups);
+  g.drain();
+
+  // The stale request doesn't remove the entry after it, which was already
+  // acknowledged.
+  assert_eq!(g.nodes[1].raft.status().log_last, (Term(1), Index(3)));
+  assert_eq!(g.nodes[1].log.last_index(), Some(Index(3)));
+}
+
+#[test]
+fn stale_vote() {
+  testutil::log_init();
+
+  let mut g = DeterministicGroup::new(3, Config::default());
+  let (n0, n1) = (g.nodes[0].raft.id(), g.nodes[1].raft.id());
+
+  // n0 calls two elections, but none of its vote requests get through.
+  g.nodes[0].start_election();
+  g.nodes[0].start_election();
+  g.nodes[0].drop_messages();
+  g.drain();
+  assert_eq!(g.nodes[0].raft.status().current_term, Term(2));
+
+  // A vote granted by n1 in the first election arrives late. It doesn't count
+  // toward the second one.
+  let res = PayloadShared::RequestVoteRes(RequestVoteResShared::new(Term(1), 1));
+  let msg = MessageShared::new(n1, n0, res, g.cfg().cluster_id, PROTOCOL_VERSION);
+  g.nodes[0].step(Input::Message(msg.capnp_as_ref()));
+  assert_eq!(g.nodes[0].raft.debug(), "candidate");
+}
+
+#[test]
+fn regression_write_completed_before_commit() {
+  testutil::log_init();
+
+  // Regression test for a bug where every write at or after the commit index
+  // was completed when it advanced, even ones that weren't committed yet.
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
+  g.drain();
+
+  let mut res1 = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  let sent = g.nodes[0].output.len();
+  let mut res2 = g.nodes[0].write(WriteReq { payload: String::from("2").into_bytes() });
+  // The second write's AppendEntries are lost.
+  let unsent: Vec<_> = g.nodes[0]
+    .output
+    .drain(sent..)
+    .filter(|output| !matches!(output, Output::Message(_)))
+    .collect();
+  g.nodes[0].output.extend(unsent);
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res1).unwrap().index, Index(2));
+  noopfuture::assert_pending(&mut res2);
+}
+
+#[test]
+fn regression_vote_for_stale_log() {
+  testutil::log_init();
+
+  // Regression test for a bug where a node would vote for a candidate whose
+  // log was missing entries it had, which let it win and overwrite committed
+  // entries.
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
+  g.drain();
+
+  // A write is committed by n0 and n1 while n2 misses it.
+  let mut res = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
+  let n2 = g.nodes[2].raft.id();
+  g.nodes[0].output.retain(|output| match output {
+    Output::Message(msg) => msg.capnp_as_ref().dest() != n2,
+    _ => true,
+  });
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res).unwrap().index, Index(2));
+
+  // n2 calls an election, but neither of the others votes for it.
+  g.nodes[2].start_election();
+  g.drain();
+  assert_eq!(g.nodes[2].raft.debug(), "candidate");
+
+  // n1 has the committed write, so it can win and the write is still there.
+  g.nodes[1].start_election();
+  g.drain();
+  assert_eq!(g.nodes[1].raft.debug(), "leader");
+  let mut res =
+    g.nodes[1].read(ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] });
+  g.drain();
+  assert_eq!(noopfuture::assert_ready(&mut res).unwrap().payload, String::from("1").into_bytes());
+}
+
 #[test]
 fn regression_request_starts_election() {
   testutil::log_init();
@@ -194,55 +1124,61 @@ fn regression_request_starts_election() {
   // Regression test for a bug where a write request didn't start an election
   // (only a tick would).
   {
-    let mut g = DeterministicGroup3::new();
+    let mut g = DeterministicGroup::new(3, Config::default());
     // Request fails with NotLeaderError, but kicks off an election.
-    let mut res1 = g.n0.write(WriteReq { payload: String::from("1").into_bytes() });
+    let mut res1 = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
     assert_eq!(
       noopfuture::assert_ready(&mut res1),
-      Err(ClientError::NotLeaderError(NotLeaderError::new(Some(g.n0.raft.id()))))
+      Err(ClientError::NotLeaderError(NotLeaderError::new(Some(g.nodes[0].raft.id()))))
     );
     g.drain();
-    assert_eq!(g.n0.raft.debug(), "leader");
+    assert_eq!(g.nodes[0].raft.debug(), "leader");
   }
 
   // Same thing but for a 1 node cluster.
   {
-    let mut g = DeterministicGroup1::new();
+    let mut g = DeterministicGroup::new(1, Config::default());
     // Request fails with NotLeaderError, but kicks off an election.
-    let mut res1 = g.n.write(WriteReq { payload: String::from("1").into_bytes() });
+    let mut res1 = g.nodes[0].write(WriteReq { payload: String::from("1").into_bytes() });
     noopfuture::assert_pending(&mut res1);
     g.drain();
-    assert_eq!(g.n.raft.debug(), "leader");
+    assert_eq!(g.nodes[0].raft.debug(), "leader");
     assert_eq!(
       noopfuture::assert_ready(&mut res1),
-      Ok(WriteRes { term: Term(1), index: Index(1) }),
+      Ok(WriteRes { term: Term(1), index: Index(2) }),
     );
   }
 
   // Same thing but for a read request.
   {
-    let mut g = DeterministicGroup3::new();
+    let mut g = DeterministicGroup::new(3, Config::default());
     // Request fails with NotLeaderError, but kicks off an election.
-    let mut res1 = g.n0.read(ReadReq { payload: String::from("1").into_bytes() });
+    let mut res1 = g.nodes[0].read(ReadReq {
+      consistency: ReadConsistency::Linearizable,
+      payload: String::from("1").into_bytes(),
+    });
     assert_eq!(
       noopfuture::assert_ready(&mut res1),
-      Err(ClientError::NotLeaderError(NotLeaderError::new(Some(g.n0.raft.id()))))
+      Err(ClientError::NotLeaderError(NotLeaderError::new(Some(g.nodes[0].raft.id()))))
     );
     g.drain();
-    assert_eq!(g.n0.raft.debug(), "leader");
+    assert_eq!(g.nodes[0].raft.debug(), "leader");
   }
 
   // Same thing but for a 1 node cluster.
   {
-    let mut g = DeterministicGroup1::new();
+    let mut g = DeterministicGroup::new(1, Config::default());
     // Request fails with NotLeaderError, but kicks off an election.
-    let mut res1 = g.n.read(ReadReq { payload: String::from("1").into_bytes() });
+    let mut res1 = g.nodes[0].read(ReadReq {
+      consistency: ReadConsistency::Linearizable,
+      payload: String::from("1").into_bytes(),
+    });
     noopfuture::assert_pending(&mut res1);
     g.drain();
-    assert_eq!(g.n.raft.debug(), "leader");
+    assert_eq!(g.nodes[0].raft.debug(), "leader");
     assert_eq!(
       noopfuture::assert_ready(&mut res1),
-      Ok(ReadRes { term: Term(1), index: Index(0), payload: vec![] })
+      Ok(ReadRes { term: Term(1), index: Index(1), payload: vec![] })
     );
   }
 }
@@ -252,11 +1188,34 @@ fn regression_request_starts_election() {
 fn regression_follower_write() {
   testutil::log_init();
 
-  let mut g = DeterministicGroup3::new();
-  g.n0.start_election();
+  let mut g = DeterministicGroup::new(3, Config::default());
+  g.nodes[0].start_election();
   g.drain();
 
   // A write is sent to a follower. This used to panic.
-  assert_eq!(g.n1.raft.debug(), "follower");
-  g.n1.write(WriteReq { payload: String::from("1").into_bytes() });
+  assert_eq!(g.nodes[1].raft.debug(), "follower");
+  g.nodes[1].write(WriteReq { payload: String::from("1").into_bytes() });
+}
+
+/// Regression test for a bug where a node in an even-sized group could be
+/// elected with the votes of only half the group.
+#[test]
+fn regression_even_majority() {
+  testutil::log_init();
+
+  for n in [2, 4] {
+    let mut g = DeterministicGroup::new(n, Config::default());
+    // Only half the group can vote for node 0.
+    for id in n / 2..n {
+      g.isolate(NodeID(id));
+    }
+    g.nodes[0].start_election();
+    g.drain();
+    assert_eq!(g.nodes[0].raft.debug(), "candidate", "n={}", n);
+
+    g.heal(NodeID(n / 2));
+    g.nodes[0].start_election();
+    g.drain();
+    assert_eq!(g.nodes[0].raft.debug(), "leader", "n={}", n);
+  }
 }
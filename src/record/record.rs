@@ -0,0 +1,530 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, Read, Write};
+use std::time::{Duration, Instant};
+
+use capnp_runtime::segment_framing_alternate;
+
+use crate::prelude::*;
+
+/// A wrapper around [`Raft`] that records every input it's handed, along with
+/// the resulting outputs.
+///
+/// Because Raft is deterministic, a recording can be handed to [`replay`] to
+/// reproduce the node's exact behavior after the fact.
+///
+/// The recording is a sequence of steps, each of which is an input followed by
+/// the outputs it resulted in. Messages (including the entries in a
+/// [`ReadLogRes`]) are written in the rast.capnp encoding and everything else
+/// as little-endian u64s and length-prefixed bytes. Ticks are written relative
+/// to the first tick recorded. Outputs are written as their `Debug` rendering,
+/// which is all that's needed to compare them.
+///
+/// Calls to [`start_election`](Recorder::start_election) and
+/// [`update_config`](Recorder::update_config) are recorded as steps too. A node
+/// that's restarted with [`Raft::restart`] needs a new recording.
+pub struct Recorder<W: Write> {
+  raft: Raft,
+  w: W,
+  start: Option<Instant>,
+}
+
+impl<W: Write> Recorder<W> {
+  /// Returns a `Recorder` that steps the given node and writes what happens to
+  /// `w`.
+  ///
+  /// To be replayed, a recording must start with the node in the state it was
+  /// constructed (or restarted) in.
+  pub fn new(raft: Raft, w: W) -> Recorder<W> {
+    Recorder { raft, w, start: None }
+  }
+
+  /// Returns the node being recorded.
+  pub fn raft(&self) -> &Raft {
+    &self.raft
+  }
+
+  /// Returns the node being recorded and the recording.
+  pub fn into_inner(self) -> (Raft, W) {
+    (self.raft, self.w)
+  }
+
+  /// See [`Raft::step`], whose result is returned inside the result of writing
+  /// the recording.
+  ///
+  /// The input is handed to Raft even if writing the recording fails, in which
+  /// case the recording is incomplete and the error is returned. A rejected
+  /// message is recorded like any other input.
+  pub fn step(
+    &mut self,
+    output: &mut impl Extend<Output>,
+    input: Input<'_>,
+  ) -> io::Result<Result<(), ProtocolError>> {
+    let mut buf = vec![];
+    let encoded = self.encode_input(&mut buf, &input);
+    let mut step_output = vec![];
+    let res = self.raft.step(&mut step_output, input);
+    self.write_step(output, buf, encoded, step_output)?;
+    Ok(res)
+  }
+
+  /// See [`Raft::start_election`].
+  ///
+  /// The node is instructed even if writing the recording fails, in which case
+  /// the recording is incomplete and the error is returned.
+  pub fn start_election(
+    &mut self,
+    output: &mut impl Extend<Output>,
+    new_leader: NodeID,
+  ) -> io::Result<()> {
+    let mut buf = vec![TAG_START_ELECTION];
+    let encoded = encode_u64(&mut buf, new_leader.0);
+    let mut step_output = vec![];
+    self.raft.start_election(&mut step_output, new_leader);
+    self.write_step(output, buf, encoded, step_output)
+  }
+
+  /// See [`Raft::update_config`], whose result is returned inside the result
+  /// of writing the recording.
+  ///
+  /// The config is handed to Raft even if writing the recording fails, in which
+  /// case the recording is incomplete and the error is returned.
+  pub fn update_config(&mut self, cfg: Config) -> io::Result<Result<(), ConfigError>> {
+    let mut buf = vec![TAG_UPDATE_CONFIG];
+    let encoded = encode_config(&mut buf, &cfg);
+    let res = self.raft.update_config(cfg);
+    self.write_step(&mut vec![], buf, encoded, vec![])?;
+    Ok(res)
+  }
+
+  // Writes a step, whose input was encoded into `buf`, and the outputs it
+  // resulted in, which are first handed to `output`.
+  fn write_step(
+    &mut self,
+    output: &mut impl Extend<Output>,
+    mut buf: Vec<u8>,
+    encoded: io::Result<()>,
+    step_output: Vec<Output>,
+  ) -> io::Result<()> {
+    let step_output_debug: Vec<String> = step_output.iter().map(debug).collect();
+    output.extend(step_output);
+    encoded?;
+    encode_u64(&mut buf, step_output_debug.len() as u64)?;
+    for output in step_output_debug.iter() {
+      encode_bytes(&mut buf, output.as_bytes())?;
+    }
+    self.w.write_all(&buf)
+  }
+
+  fn encode_input(&mut self, buf: &mut Vec<u8>, input: &Input<'_>) -> io::Result<()> {
+    match input {
+      Input::Write(req, _) => {
+        buf.write_all(&[TAG_WRITE])?;
+        encode_bytes(buf, &req.payload)
+      }
+      Input::Read(req, _) => {
+        buf.write_all(&[TAG_READ])?;
+        encode_bytes(buf, &req.payload)?;
+        match req.consistency {
+          ReadConsistency::Linearizable => buf.write_all(&[0]),
+          ReadConsistency::AtLeastIndex(index) => {
+            buf.write_all(&[1])?;
+            encode_u64(buf, index.0)
+          }
+          ReadConsistency::MaxStaleness(staleness) => {
+            buf.write_all(&[2])?;
+            encode_u64(buf, staleness.as_nanos() as u64)
+          }
+        }
+      }
+      Input::Tick(now) => {
+        let start = *self.start.get_or_insert(*now);
+        buf.write_all(&[TAG_TICK])?;
+        encode_u64(buf, now.saturating_duration_since(start).as_nanos() as u64)
+      }
+      Input::Message(msg) => {
+        buf.write_all(&[TAG_MESSAGE])?;
+        segment_framing_alternate::encode(buf, msg)
+      }
+      Input::PersistRes(res) => {
+        buf.write_all(&[TAG_PERSIST_RES])?;
+        encode_u64(buf, res.leader_id.0)?;
+        encode_u64(buf, res.read_id.0)?;
+        encode_u64(buf, res.log_index.0)?;
+        encode_u64(buf, res.generation)?;
+        match &res.error {
+          None => buf.write_all(&[0]),
+          Some(err) => {
+            buf.write_all(&[1])?;
+            encode_bytes(buf, err.message.as_bytes())
+          }
+        }
+      }
+      Input::ReadStateMachineRes(res) => {
+        buf.write_all(&[TAG_READ_STATE_MACHINE_RES])?;
+        encode_u64(buf, res.index.0)?;
+        encode_u64(buf, res.reads.len() as u64)?;
+        for (read_id, payload) in res.reads.iter() {
+          encode_u64(buf, read_id.0)?;
+          encode_bytes(buf, payload)?;
+        }
+        Ok(())
+      }
+      Input::ReadLogRes(res) => {
+        buf.write_all(&[TAG_READ_LOG_RES])?;
+        encode_u64(buf, res.peer.0)?;
+        encode_u64(buf, res.term.0)?;
+        encode_u64(buf, res.entries.len() as u64)?;
+        for entry in res.entries.iter() {
+          segment_framing_alternate::encode(buf, &entry.capnp_as_ref())?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+/// An error returned by [`replay`].
+#[derive(Debug)]
+pub enum ReplayError {
+  /// The recording couldn't be read.
+  Io(io::Error),
+  /// The recording was malformed.
+  Decode(String),
+  /// The replayed node's outputs differed from the recorded ones.
+  Diverged {
+    /// The index of the first step whose outputs differed.
+    step: usize,
+    /// The outputs in the recording, as rendered by `Debug`.
+    recorded: Vec<String>,
+    /// The outputs of the replayed node, as rendered by `Debug`.
+    replayed: Vec<String>,
+  },
+}
+
+impl Display for ReplayError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      ReplayError::Io(err) => write!(f, "reading recording: {}", err),
+      ReplayError::Decode(msg) => write!(f, "decoding recording: {}", msg),
+      ReplayError::Diverged { step, recorded, replayed } => {
+        writeln!(f, "diverged at step {}", step)?;
+        writeln!(f, "recorded:")?;
+        recorded.iter().try_for_each(|output| writeln!(f, "  {}", output))?;
+        writeln!(f, "replayed:")?;
+        replayed.iter().try_for_each(|output| writeln!(f, "  {}", output))
+      }
+    }
+  }
+}
+
+impl Error for ReplayError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ReplayError::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for ReplayError {
+  fn from(err: io::Error) -> Self {
+    ReplayError::Io(err)
+  }
+}
+
+/// Feeds a recording made by [`Recorder`] into the given node, returning the
+/// number of steps replayed or an error at the first one whose outputs differ
+/// from the recording.
+///
+/// The node must be in the same state that the recorded one was when the
+/// recording started, which usually means constructing it with the same
+/// arguments.
+pub fn replay<R: BufRead>(mut raft: Raft, r: &mut R) -> Result<usize, ReplayError> {
+  let start = Instant::now();
+  let mut step = 0;
+  while !r.fill_buf()?.is_empty() {
+    let mut output = vec![];
+    match decode_step(r, start)? {
+      // NB: A message rejected when it was recorded is rejected again, which is
+      // checked by comparing the (lack of) outputs.
+      Step::Input(input) => {
+        let _ = raft.step(&mut output, input.as_ref());
+      }
+      Step::StartElection(new_leader) => raft.start_election(&mut output, new_leader),
+      // NB: Likewise, a rejected config is rejected again. Either way, any
+      // difference shows up in the outputs of later steps.
+      Step::UpdateConfig(cfg) => {
+        let _ = raft.update_config(cfg);
+      }
+    }
+    let replayed: Vec<String> = output.iter().map(debug).collect();
+
+    let len = decode_len(r)?;
+    let recorded = (0..len)
+      .map(|_| {
+        String::from_utf8(decode_bytes(r)?).map_err(|err| ReplayError::Decode(err.to_string()))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    if recorded != replayed {
+      return Err(ReplayError::Diverged { step, recorded, replayed });
+    }
+    step += 1;
+  }
+  Ok(step)
+}
+
+const TAG_WRITE: u8 = 0;
+const TAG_READ: u8 = 1;
+const TAG_TICK: u8 = 2;
+const TAG_MESSAGE: u8 = 3;
+const TAG_PERSIST_RES: u8 = 4;
+const TAG_READ_STATE_MACHINE_RES: u8 = 5;
+const TAG_READ_LOG_RES: u8 = 6;
+const TAG_START_ELECTION: u8 = 7;
+const TAG_UPDATE_CONFIG: u8 = 8;
+
+// The most items decoded for any one list in a recording, see decode_len.
+const MAX_LIST_LEN: u64 = 1 << 20;
+
+// Something recorded that's replayed against the node, see Recorder.
+enum Step {
+  Input(OwnedInput),
+  StartElection(NodeID),
+  UpdateConfig(Config),
+}
+
+fn debug(output: &Output) -> String {
+  format!("{:?}", output)
+}
+
+fn encode_u64(buf: &mut Vec<u8>, x: u64) -> io::Result<()> {
+  buf.write_all(&x.to_le_bytes())
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, x: &[u8]) -> io::Result<()> {
+  encode_u64(buf, x.len() as u64)?;
+  buf.write_all(x)
+}
+
+fn encode_config(buf: &mut Vec<u8>, cfg: &Config) -> io::Result<()> {
+  encode_u64(buf, cfg.cluster_id.0)?;
+  encode_u64(buf, cfg.protocol_version as u64)?;
+  encode_u64(buf, cfg.election_timeout.as_nanos() as u64)?;
+  encode_u64(buf, cfg.heartbeat_interval.as_nanos() as u64)
+}
+
+fn decode_step<R: BufRead>(r: &mut R, start: Instant) -> Result<Step, ReplayError> {
+  let mut tag = [0u8; 1];
+  r.read_exact(&mut tag)?;
+  let input = match tag[0] {
+    TAG_START_ELECTION => return Ok(Step::StartElection(NodeID(decode_u64(r)?))),
+    TAG_UPDATE_CONFIG => return Ok(Step::UpdateConfig(decode_config(r)?)),
+    TAG_WRITE => OwnedInput::Write(WriteReq { payload: decode_bytes(r)? }, WriteFuture::new()),
+    TAG_READ => {
+      let payload = decode_bytes(r)?;
+      let req = ReadReq { consistency: decode_read_consistency(r)?, payload };
+      OwnedInput::Read(req, ReadFuture::new())
+    }
+    TAG_TICK => OwnedInput::Tick(start + Duration::from_nanos(decode_u64(r)?)),
+    TAG_MESSAGE => OwnedInput::Message(decode_capnp(r)?),
+    TAG_PERSIST_RES => OwnedInput::PersistRes(PersistRes {
+      leader_id: NodeID(decode_u64(r)?),
+      read_id: ReadID(decode_u64(r)?),
+      log_index: Index(decode_u64(r)?),
+      generation: decode_u64(r)?,
+      error: decode_persist_error(r)?,
+    }),
+    TAG_READ_STATE_MACHINE_RES => {
+      let index = Index(decode_u64(r)?);
+      let len = decode_len(r)?;
+      let reads = (0..len)
+        .map(|_| Ok((ReadID(decode_u64(r)?), decode_bytes(r)?)))
+        .collect::<Result<Vec<_>, ReplayError>>()?;
+      OwnedInput::ReadStateMachineRes(ReadStateMachineRes { index, reads })
+    }
+    TAG_READ_LOG_RES => {
+      let (peer, term) = (NodeID(decode_u64(r)?), Term(decode_u64(r)?));
+      let len = decode_len(r)?;
+      let entries = (0..len).map(|_| decode_capnp(r)).collect::<Result<Vec<_>, _>>()?;
+      OwnedInput::ReadLogRes(ReadLogRes { peer, term, entries })
+    }
+    tag => return Err(ReplayError::Decode(format!("unknown input tag {}", tag))),
+  };
+  Ok(Step::Input(input))
+}
+
+fn decode_config<R: BufRead>(r: &mut R) -> Result<Config, ReplayError> {
+  let cluster_id = ClusterID(decode_u64(r)?);
+  let protocol_version = decode_u64(r)?;
+  let protocol_version = u32::try_from(protocol_version)
+    .map_err(|_| ReplayError::Decode(format!("protocol version {}", protocol_version)))?;
+  Ok(Config {
+    cluster_id,
+    protocol_version,
+    election_timeout: Duration::from_nanos(decode_u64(r)?),
+    heartbeat_interval: Duration::from_nanos(decode_u64(r)?),
+  })
+}
+
+fn decode_capnp<T, R>(r: &mut R) -> Result<T, ReplayError>
+where
+  T: capnp_runtime::prelude::TypedStructShared,
+  R: BufRead,
+{
+  segment_framing_alternate::decode(r).map_err(|err| ReplayError::Decode(err.to_string()))
+}
+
+fn decode_u64<R: BufRead>(r: &mut R) -> Result<u64, ReplayError> {
+  let mut buf = [0u8; 8];
+  r.read_exact(&mut buf)?;
+  Ok(u64::from_le_bytes(buf))
+}
+
+// Decodes the number of items in a list. No one step comes anywhere near
+// MAX_LIST_LEN of anything, so a longer list means the recording is corrupt.
+fn decode_len<R: BufRead>(r: &mut R) -> Result<u64, ReplayError> {
+  let len = decode_u64(r)?;
+  if len > MAX_LIST_LEN {
+    return Err(ReplayError::Decode(format!("list of {} items", len)));
+  }
+  Ok(len)
+}
+
+fn decode_bytes<R: BufRead>(r: &mut R) -> Result<Vec<u8>, ReplayError> {
+  let len = decode_u64(r)?;
+  // NB: The length isn't trusted with an allocation up front, the bytes are
+  // only buffered as they're read.
+  let mut buf = vec![];
+  r.take(len).read_to_end(&mut buf)?;
+  if (buf.len() as u64) < len {
+    return Err(ReplayError::Decode(format!("expected {} bytes got {}", len, buf.len())));
+  }
+  Ok(buf)
+}
+
+fn decode_persist_error<R: BufRead>(r: &mut R) -> Result<Option<PersistError>, ReplayError> {
+  let mut tag = [0u8; 1];
+  r.read_exact(&mut tag)?;
+  match tag[0] {
+    0 => Ok(None),
+    1 => {
+      let message = String::from_utf8(decode_bytes(r)?)
+        .map_err(|err| ReplayError::Decode(format!("persist error: {}", err)))?;
+      Ok(Some(PersistError::new(message)))
+    }
+    tag => Err(ReplayError::Decode(format!("unknown persist error tag {}", tag))),
+  }
+}
+
+fn decode_read_consistency<R: BufRead>(r: &mut R) -> Result<ReadConsistency, ReplayError> {
+  let mut tag = [0u8; 1];
+  r.read_exact(&mut tag)?;
+  match tag[0] {
+    0 => Ok(ReadConsistency::Linearizable),
+    1 => Ok(ReadConsistency::AtLeastIndex(Index(decode_u64(r)?))),
+    2 => Ok(ReadConsistency::MaxStaleness(Duration::from_nanos(decode_u64(r)?))),
+    tag => Err(ReplayError::Decode(format!("unknown read consistency tag {}", tag))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::wildcard_imports)]
+  use super::*;
+
+  use crate::serde::{PayloadShared, RequestVoteReqShared, RequestVoteResShared};
+
+  // Steps a node of a three node group through winning an election and
+  // committing a write, writing the recording to `w`.
+  fn record(w: &mut Vec<u8>) {
+    let nodes = vec![NodeID(0), NodeID(1), NodeID(2)];
+    let mut r = Recorder::new(Raft::new(NodeID(0), nodes, Config::default()), w);
+    let mut output = vec![];
+    let now = Instant::now();
+    r.step(&mut output, Input::Tick(now)).unwrap().unwrap();
+    let vote = RequestVoteResShared::new(Term(1), 1);
+    let vote = PayloadShared::RequestVoteRes(vote);
+    let vote = MessageShared::new(NodeID(1), NodeID(0), vote, ClusterID(0), PROTOCOL_VERSION);
+    r.step(&mut output, Input::Message(vote.capnp_as_ref())).unwrap().unwrap();
+    assert_eq!(r.raft().status().role, Role::Leader);
+    r.step(&mut output, Input::Write(WriteReq { payload: b"1".to_vec() }, WriteFuture::new()))
+      .unwrap()
+      .unwrap();
+    r.step(&mut output, Input::Tick(now + Config::default().heartbeat_interval)).unwrap().unwrap();
+    let res = PersistRes {
+      leader_id: NodeID(0),
+      read_id: ReadID(0),
+      log_index: Index(2),
+      generation: 0,
+      error: None,
+    };
+    r.step(&mut output, Input::PersistRes(res)).unwrap().unwrap();
+    let req = RequestVoteReqShared::new(Term(1), NodeID(2), Index(0), Term(0));
+    let req = PayloadShared::RequestVoteReq(req);
+    let req = MessageShared::new(NodeID(2), NodeID(0), req, ClusterID(0), PROTOCOL_VERSION);
+    r.step(&mut output, Input::Message(req.capnp_as_ref())).unwrap().unwrap();
+    assert!(!output.is_empty());
+    let cfg = Config { heartbeat_interval: Duration::from_millis(1), ..Config::default() };
+    r.update_config(cfg).unwrap().unwrap();
+    output.clear();
+    r.start_election(&mut output, NodeID(1)).unwrap();
+    assert_eq!(output.len(), 1);
+  }
+
+  #[test]
+  fn record_replay() {
+    let mut recording = vec![];
+    record(&mut recording);
+    let nodes = vec![NodeID(0), NodeID(1), NodeID(2)];
+    let raft = Raft::new(NodeID(0), nodes, Config::default());
+    assert_eq!(replay(raft, &mut recording.as_slice()).unwrap(), 8);
+  }
+
+  #[test]
+  fn record_replay_diverged() {
+    let mut recording = vec![];
+    record(&mut recording);
+    // A node in a five node group asks more peers for votes, so it diverges on
+    // the first step.
+    let nodes = (0..5).map(NodeID).collect();
+    let raft = Raft::new(NodeID(0), nodes, Config::default());
+    match replay(raft, &mut recording.as_slice()) {
+      Err(ReplayError::Diverged { step, .. }) => assert_eq!(step, 0),
+      res => panic!("expected divergence got {:?}", res),
+    }
+
+    // A truncated recording can't be decoded.
+    let nodes = vec![NodeID(0), NodeID(1), NodeID(2)];
+    let raft = Raft::new(NodeID(0), nodes, Config::default());
+    match replay(raft, &mut &recording[..recording.len() - 1]) {
+      Err(ReplayError::Decode(_)) => {}
+      res => panic!("expected decode error got {:?}", res),
+    }
+  }
+
+  #[test]
+  fn replay_corrupt() {
+    let nodes = vec![NodeID(0), NodeID(1), NodeID(2)];
+    let replay_corrupt = |recording: Vec<u8>| match replay(
+      Raft::new(NodeID(0), nodes.clone(), Config::default()),
+      &mut &recording[..],
+    ) {
+      Err(ReplayError::Decode(_)) => {}
+      res => panic!("expected decode error got {:?}", res),
+    };
+
+    // A write with a huge length prefix and no payload.
+    let mut recording = vec![TAG_WRITE];
+    recording.extend(&u64::MAX.to_le_bytes());
+    replay_corrupt(recording);
+
+    // A ReadLogRes with a huge number of entries.
+    let mut recording = vec![TAG_READ_LOG_RES];
+    [1, 1, u64::MAX].iter().for_each(|x| recording.extend(&u64::to_le_bytes(*x)));
+    replay_corrupt(recording);
+  }
+}
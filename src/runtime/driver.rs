@@ -0,0 +1,447 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+
+use crate::prelude::*;
+use crate::runtime::{finish_disk, Connection, Inbound, LogStore, Transport};
+
+// The number of inputs a Driver steps before yielding back to the executor, so
+// that a busy node doesn't starve the others sharing its thread.
+const STEP_BUDGET: usize = 64;
+
+/// A boxed future returned by the IO hooks of a [`Driver`].
+pub type IOFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// The disk and network IO of a [`Driver`].
+///
+/// Disk IO is asynchronous. The driver awaits each returned future before
+/// starting the next, so disk IO finishes in the order Raft requested it (see
+/// [`Output`]). Sends don't return a future because messages are allowed to be
+/// lost anyway.
+pub trait DriverIO {
+  /// Durably persists the hard state of the node.
+  ///
+  /// Messages output by the same step as a hard state change are held until
+  /// this finishes.
+  fn set_hard_state(&mut self, hard_state: HardState) -> IOFuture<()>;
+
+  /// Performs the given disk output (anything but `Output::Message`) and
+  /// resolves to the resulting input for Raft, if any.
+  fn disk(&mut self, req: Output) -> IOFuture<Option<OwnedInput>>;
+
+  /// Sends the given message without blocking.
+  fn send(&mut self, msg: MessageShared);
+}
+
+/// A [`DriverIO`] that synchronously does disk IO against a [`LogStore`] and
+/// sends messages with a [`Transport`].
+///
+/// The disk IO runs on whatever thread polls the [`Driver`], so this is only
+/// suitable for logs that don't block, like [`MemLog`](crate::runtime::MemLog).
+pub struct BlockingIO<L: LogStore, T: Transport> {
+  log: L,
+  rpc: T,
+  conns: HashMap<NodeID, T::Connection>,
+  state: Vec<u8>,
+  failed: Option<u64>,
+}
+
+impl<L: LogStore, T: Transport> BlockingIO<L, T> {
+  /// Returns a `BlockingIO` with the given log and rpc system.
+  pub fn new(log: L, rpc: T) -> BlockingIO<L, T> {
+    BlockingIO { log, rpc, conns: HashMap::new(), state: vec![], failed: None }
+  }
+
+  /// Returns the log, so it can be handed to a restarted node.
+  pub fn into_log(self) -> L {
+    self.log
+  }
+}
+
+impl<L: LogStore, T: Transport> DriverIO for BlockingIO<L, T> {
+  fn set_hard_state(&mut self, hard_state: HardState) -> IOFuture<()> {
+    // TODO: Handle errors from the log.
+    self.log.set_hard_state(hard_state).expect("WIP");
+    Box::pin(std::future::ready(()))
+  }
+
+  fn disk(&mut self, req: Output) -> IOFuture<Option<OwnedInput>> {
+    let input = finish_disk(req, &mut self.log, &mut self.state, &mut self.failed);
+    Box::pin(std::future::ready(input))
+  }
+
+  fn send(&mut self, msg: MessageShared) {
+    let dest = msg.capnp_as_ref().dest();
+    let rpc = &self.rpc;
+    let conn = self.conns.entry(dest).or_insert_with(|| rpc.dial(dest));
+    if conn.send(msg.capnp_as_ref()).is_err() {
+      // Messages are allowed to be lost (see Output), so this one is dropped.
+      // Dial again next time in case the peer comes back.
+      self.conns.remove(&dest);
+    }
+  }
+}
+
+#[derive(Debug)]
+struct Queue {
+  inputs: VecDeque<OwnedInput>,
+  // The waker of the last poll of the driver that found the queue empty.
+  waker: Option<Waker>,
+  stopped: bool,
+}
+
+/// A thread-safe handle for sending inputs to a [`Driver`].
+#[derive(Debug, Clone)]
+pub struct DriverHandle {
+  id: NodeID,
+  queue: Arc<Mutex<Queue>>,
+}
+
+impl DriverHandle {
+  /// Submits a read request to the Raft node.
+  pub fn read(&self, req: ReadReq) -> ReadFuture {
+    let mut res = ReadFuture::new();
+    self.send(Input::Read(req, res.clone()).into()).err().iter().for_each(|_| {
+      // An error here means the driver has stopped. Dunno who the leader is but
+      // it's not us.
+      res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))));
+    });
+    res
+  }
+
+  /// Submits a write request to the Raft node.
+  pub fn write(&self, req: WriteReq) -> WriteFuture {
+    let mut res = WriteFuture::new();
+    self.send(Input::Write(req, res.clone()).into()).err().iter().for_each(|_| {
+      // An error here means the driver has stopped. Dunno who the leader is but
+      // it's not us.
+      res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))));
+    });
+    res
+  }
+
+  /// Queues the given input, such as a tick or a message from a peer, for the
+  /// Raft node. It's handed back if the driver has stopped.
+  pub fn send(&self, input: OwnedInput) -> Result<(), OwnedInput> {
+    let waker = {
+      let mut queue = self.queue.lock().unwrap();
+      if queue.stopped {
+        return Err(input);
+      }
+      queue.inputs.push_back(input);
+      queue.waker.take()
+    };
+    waker.iter().for_each(Waker::wake_by_ref);
+    Ok(())
+  }
+
+  /// Returns an [`Inbound`] that hands messages to the Raft node, for use with
+  /// [`Transport::listen`].
+  pub fn inbound(&self) -> Inbound {
+    Inbound::driver(self.id, self.clone())
+  }
+
+  /// Stops the driver, which then finishes the next time it's polled.
+  ///
+  /// Disk IO that's still in progress is abandoned, as if the node crashed.
+  /// Reads and writes that weren't yet handed to Raft fail with a
+  /// [`NotLeaderError`].
+  pub fn stop(&self) {
+    let waker = {
+      let mut queue = self.queue.lock().unwrap();
+      queue.stopped = true;
+      queue.waker.take()
+    };
+    waker.iter().for_each(Waker::wake_by_ref);
+  }
+}
+
+/// An executor-agnostic [`Future`] that drives a [`Raft`] node.
+///
+/// This is the async counterpart of [`Runtime`](crate::runtime::Runtime).
+/// Instead of blocking a thread per node, it pulls inputs from a queue filled
+/// through its [`DriverHandle`] and hands disk and network IO to a
+/// [`DriverIO`]. This lets any number of nodes share the threads of whatever
+/// executor it's spawned on.
+///
+/// A driver doesn't have a clock. Ticks are inputs like any other and should be
+/// sent through the handle by a timer of the executor in use. The future
+/// resolves to the node and its IO once [`stop`](DriverHandle::stop) is
+/// called.
+pub struct Driver<IO: DriverIO> {
+  raft: Option<Raft>,
+  io: Option<IO>,
+  handle: DriverHandle,
+  metrics: Arc<dyn Metrics>,
+  // The last hard state handed to io.
+  hard_state: HardState,
+  // Outputs of Raft that haven't been started yet, in order.
+  pending: VecDeque<Pending>,
+  // The number of hard states that are pending or in flight. While this is
+  // nonzero, messages wait their turn in pending instead of being sent right
+  // away.
+  hard_states: usize,
+  in_flight: Option<InFlight>,
+  output: Vec<Output>,
+}
+
+enum Pending {
+  HardState(HardState),
+  Disk(Output),
+  Message(MessageShared),
+}
+
+enum InFlight {
+  HardState(IOFuture<()>),
+  // The start time is only set for a PersistReq, for Metrics::persist_latency.
+  Disk(IOFuture<Option<OwnedInput>>, Option<Instant>),
+}
+
+impl<IO: DriverIO> Driver<IO> {
+  /// Returns a driver for the given node, which must already have its hard
+  /// state persisted in `io`.
+  ///
+  /// Nothing happens until the driver is spawned on an executor. Messages for
+  /// this node should be handed to it by giving
+  /// [`handle().inbound()`](DriverHandle::inbound) to the rpc system. The
+  /// driver records to the same [`Metrics`] as the given `raft`.
+  pub fn new(raft: Raft, io: IO) -> Driver<IO> {
+    let queue = Queue { inputs: VecDeque::new(), waker: None, stopped: false };
+    let handle = DriverHandle { id: raft.id(), queue: Arc::new(Mutex::new(queue)) };
+    Driver {
+      metrics: raft.metrics(),
+      hard_state: raft.hard_state(),
+      raft: Some(raft),
+      io: Some(io),
+      handle,
+      pending: VecDeque::new(),
+      hard_states: 0,
+      in_flight: None,
+      output: vec![],
+    }
+  }
+
+  /// Returns a new thread-safe handle for sending inputs to this driver.
+  pub fn handle(&self) -> DriverHandle {
+    self.handle.clone()
+  }
+
+  fn step(&mut self, input: OwnedInput) {
+    let raft = self.raft.as_mut().expect("driver polled after completion");
+    let io = self.io.as_mut().expect("driver polled after completion");
+    // A message that no correct peer would have sent is dropped, which is
+    // surfaced as an Event::MessageRejected.
+    let _ = raft.step(&mut self.output, input.as_ref());
+    // Raft doesn't output its hard state yet, so persist it before any of the
+    // outputs are processed. See Raft::hard_state.
+    let hard_state = raft.hard_state();
+    if hard_state != self.hard_state {
+      self.hard_state = hard_state;
+      self.hard_states += 1;
+      self.pending.push_back(Pending::HardState(hard_state));
+    }
+    #[cfg(feature = "log")]
+    self.output.iter().for_each(|o| {
+      debug!("  out: {:?}", o);
+    });
+    for output in self.output.drain(..) {
+      match output {
+        Output::Message(msg) if self.hard_states == 0 => io.send(msg),
+        Output::Message(msg) => self.pending.push_back(Pending::Message(msg)),
+        output => self.pending.push_back(Pending::Disk(output)),
+      }
+    }
+  }
+
+  // Makes as much progress as possible on the IO requested by Raft, stopping
+  // early if some of it resulted in an input being stepped. Returns whether
+  // that happened.
+  fn poll_io(&mut self, cx: &mut Context<'_>) -> bool {
+    loop {
+      match self.in_flight.as_mut() {
+        Some(InFlight::HardState(fut)) => match fut.as_mut().poll(cx) {
+          Poll::Pending => return false,
+          Poll::Ready(()) => {
+            self.in_flight = None;
+            self.hard_states -= 1;
+          }
+        },
+        Some(InFlight::Disk(fut, start)) => match fut.as_mut().poll(cx) {
+          Poll::Pending => return false,
+          Poll::Ready(input) => {
+            if let Some(start) = start {
+              self.metrics.persist_latency(start.elapsed());
+            }
+            self.in_flight = None;
+            if let Some(input) = input {
+              self.step(input);
+              return true;
+            }
+          }
+        },
+        None => {}
+      }
+      let io = self.io.as_mut().expect("driver polled after completion");
+      match self.pending.pop_front() {
+        None => return false,
+        Some(Pending::Message(msg)) => io.send(msg),
+        Some(Pending::HardState(hard_state)) => {
+          self.in_flight = Some(InFlight::HardState(io.set_hard_state(hard_state)));
+        }
+        Some(Pending::Disk(req)) => {
+          let start = match req {
+            Output::PersistReq(_) => Some(Instant::now()),
+            _ => None,
+          };
+          self.in_flight = Some(InFlight::Disk(io.disk(req), start));
+        }
+      }
+    }
+  }
+
+  fn finish(&mut self) -> (Raft, IO) {
+    let inputs = std::mem::take(&mut self.handle.queue.lock().unwrap().inputs);
+    inputs.into_iter().for_each(|input| match input {
+      OwnedInput::Write(_, mut res) => {
+        res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))))
+      }
+      OwnedInput::Read(_, mut res) => {
+        res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))))
+      }
+      _ => {}
+    });
+    let raft = self.raft.take().expect("driver polled after completion");
+    let io = self.io.take().expect("driver polled after completion");
+    (raft, io)
+  }
+}
+
+impl<IO: DriverIO + Unpin> Future for Driver<IO> {
+  type Output = (Raft, IO);
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let driver = self.get_mut();
+    let mut budget = STEP_BUDGET;
+    loop {
+      if !driver.poll_io(cx) {
+        let input = {
+          let mut queue = driver.handle.queue.lock().unwrap();
+          if queue.stopped {
+            drop(queue);
+            return Poll::Ready(driver.finish());
+          }
+          match queue.inputs.pop_front() {
+            Some(input) => input,
+            None => {
+              queue.waker = Some(cx.waker().clone());
+              return Poll::Pending;
+            }
+          }
+        };
+        driver.step(input);
+      }
+      budget -= 1;
+      if budget == 0 {
+        cx.waker().wake_by_ref();
+        return Poll::Pending;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::wildcard_imports)]
+  use super::*;
+
+  use crate::runtime::{MemLog, MemRPC};
+
+  // Polls every driver whenever it's polled and resolves to the output of work
+  // once that's done.
+  struct Group<F: Future> {
+    drivers: Vec<Driver<BlockingIO<MemLog, MemRPC>>>,
+    work: Pin<Box<F>>,
+  }
+
+  impl<F: Future> Future for Group<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+      let group = self.get_mut();
+      for driver in group.drivers.iter_mut() {
+        if Pin::new(driver).poll(cx).is_ready() {
+          panic!("driver finished before it was stopped");
+        }
+      }
+      group.work.as_mut().poll(cx)
+    }
+  }
+
+  // Returns Pending once, so that everything else gets polled in the meantime.
+  struct YieldNow(bool);
+
+  impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+      if self.0 {
+        return Poll::Ready(());
+      }
+      self.0 = true;
+      cx.waker().wake_by_ref();
+      Poll::Pending
+    }
+  }
+
+  async fn write_then_read(client: DriverHandle) -> Vec<u8> {
+    loop {
+      match client.write(WriteReq { payload: b"1".to_vec() }).await {
+        Ok(_) => break,
+        // The election isn't over yet.
+        Err(_) => YieldNow(false).await,
+      }
+    }
+    client
+      .read(ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] })
+      .await
+      .expect("read failed")
+      .payload
+  }
+
+  #[test]
+  fn driver_group() {
+    let ids: Vec<NodeID> = (0..3).map(NodeID).collect();
+    let rpc = MemRPC::new();
+    let drivers: Vec<_> = ids
+      .iter()
+      .map(|id| {
+        let raft = Raft::new(*id, ids.clone(), Config::default());
+        let driver = Driver::new(raft, BlockingIO::new(MemLog::new(), rpc.clone()));
+        rpc.clone().listen(*id, driver.handle().inbound());
+        driver
+      })
+      .collect();
+    let handles: Vec<_> = drivers.iter().map(Driver::handle).collect();
+
+    // Only the first node is ticked, so it wins the election it calls.
+    handles[0].send(Input::Tick(Instant::now()).into()).unwrap();
+    let mut group = Group { drivers, work: Box::pin(write_then_read(handles[0].clone())) };
+    assert_eq!(extreme::run(&mut group), b"1");
+
+    // A stopped driver hands back its node and log, which has the write.
+    handles.iter().for_each(DriverHandle::stop);
+    let leader = group.drivers.remove(0);
+    let (raft, io) = extreme::run(leader);
+    let log = io.into_log();
+    assert_eq!(raft.status().role, Role::Leader);
+    assert_eq!(log.hard_state(), raft.hard_state());
+    assert_eq!(log.user_payloads(Index(0), Index(100)).unwrap(), vec![b"1".to_vec()]);
+    // Stopped drivers fail new requests instead of hanging.
+    assert!(extreme::run(handles[1].write(WriteReq { payload: vec![] })).is_err());
+  }
+}
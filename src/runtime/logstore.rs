@@ -0,0 +1,74 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::io;
+
+use crate::prelude::*;
+
+/// Durable storage for the Raft log and hard state of one node.
+///
+/// This is what handles the disk outputs of [`Raft::step`] in a [`Runtime`].
+/// Implementations may block (for example, to fsync) and may fail, which lets
+/// tests run against storage that misbehaves on purpose. [`MemLog`] is the
+/// in-memory implementation.
+///
+/// [`Runtime`]: crate::runtime::Runtime
+/// [`MemLog`]: crate::runtime::MemLog
+pub trait LogStore {
+  /// Durably appends the given consecutive entries, first truncating any
+  /// existing entries that conflict with them (same index but a different
+  /// term).
+  ///
+  /// Appending an entry that's already in the log (same index and term) is a
+  /// no-op. Appending no entries must still fail if the storage is failing.
+  fn append(&mut self, entries: &[EntryShared]) -> io::Result<()>;
+
+  /// Returns, in log order, every entry with an index between `start` and `end`
+  /// (both inclusive).
+  fn read(&self, start: Index, end: Index) -> io::Result<Vec<EntryShared>>;
+
+  /// Returns the term of the entry at the given index or None if that index
+  /// doesn't exist.
+  fn term(&self, index: Index) -> io::Result<Option<Term>>;
+
+  /// Returns the index of the first entry in the log or None if it's empty.
+  fn first_index(&self) -> Option<Index>;
+
+  /// Returns the index of the last entry in the log or None if it's empty.
+  ///
+  /// This index is not monotonic, but it will never regress lower than
+  /// `stable`.
+  fn last_index(&self) -> Option<Index>;
+
+  /// Returns the highest index marked stable, if any.
+  fn stable(&self) -> Option<Index>;
+
+  /// Marks the given index as stable, promising that it will never be truncated
+  /// by a later append.
+  fn mark_stable(&mut self, index: Index) -> io::Result<()>;
+
+  /// Discards every entry with an index at or below the given one, which must
+  /// be stable.
+  fn compact(&mut self, index: Index) -> io::Result<()>;
+
+  /// Returns the last hard state persisted by the Raft node that owns this log.
+  fn hard_state(&self) -> HardState;
+
+  /// Durably persists the hard state of the Raft node that owns this log.
+  fn set_hard_state(&mut self, hard_state: HardState) -> io::Result<()>;
+
+  /// Returns, in log order, the payloads of the user entries with an index
+  /// between `start` and `end` (both inclusive).
+  ///
+  /// Entries written by Raft itself, such as the no-op a new leader appends,
+  /// are skipped because they're never applied to the state machine.
+  fn user_payloads(&self, start: Index, end: Index) -> io::Result<Vec<Vec<u8>>> {
+    let entries = self.read(start, end)?;
+    let payloads = entries
+      .iter()
+      .map(|entry| entry.capnp_as_ref())
+      .filter(|entry| matches!(entry.kind(), Ok(EntryKind::User)))
+      .map(|entry| entry.payload().expect("WIP").to_vec())
+      .collect();
+    Ok(payloads)
+  }
+}
@@ -1,35 +1,36 @@
 // Copyright 2020 Daniel Harrison. All Rights Reserved.
 
 use std::collections::BTreeMap;
+use std::io;
 
 use crate::prelude::*;
+use crate::runtime::LogStore;
 
 /// An unpersisted Raft log implementation suitable for unit tests and
 /// benchmarks.
 pub struct MemLog {
-  /// The Raft log entries.
-  pub entries: BTreeMap<Index, (Term, Vec<u8>)>,
-  /// A guarantee that any entry with a lesser term will never change.
-  pub stable: Option<Index>,
+  // The Raft log entries.
+  entries: BTreeMap<Index, (Term, EntryKind, Vec<u8>)>,
+  // A guarantee that any entry with a lesser term will never change.
+  stable: Option<Index>,
+  // The last hard state persisted by the Raft node that owns this log.
+  hard_state: HardState,
 }
 
 impl MemLog {
   /// Constructs a new, empty `MemLog`.
   pub fn new() -> MemLog {
-    MemLog { entries: BTreeMap::new(), stable: None }
+    MemLog {
+      entries: BTreeMap::new(),
+      stable: None,
+      hard_state: HardState { current_term: Term(0), voted_for: None },
+    }
   }
 
-  /// Returns the largest index added to this log.
-  ///
-  /// This index is not monotonic, but it will never regress lower than
-  /// `stable`.
-  pub fn highest_index(&self) -> Index {
-    self.entries.keys().next_back().map_or(Index(0), |index| *index)
-  }
-
-  /// Appends a new entry to the log, truncating existing entries that conflict,
-  /// if necessary.
-  pub fn add<'a>(&mut self, entry: EntryRef<'a>) {
+  fn add<'a>(&mut self, entry: EntryRef<'a>) {
+    if self.entries.get(&entry.index()).map_or(false, |(term, _, _)| *term == entry.term()) {
+      return;
+    }
     // Invariant: All entries <= the stable one will not change.
     debug_assert!(self.stable.map_or(true, |stable| entry.index() > stable));
     // Invariant: Indexes are consecutive.
@@ -37,22 +38,110 @@ impl MemLog {
       let mut preceding = self.entries.range(..entry.index());
       preceding.next_back().map_or(true, |prev| *prev.0 + 1 == entry.index())
     });
-    // Remove all entries >= the index of the new one. This is an awkward way to
-    // do it but we're limited by the BTreeMap interface.
+    // Remove all entries >= the index of the new one, which conflict with it.
+    // This is an awkward way to do it but we're limited by the BTreeMap
+    // interface.
     let _ = self.entries.split_off(&entry.index());
-    self.entries.insert(entry.index(), (entry.term(), entry.payload().expect("WIP").to_vec()));
+    self.entries.insert(
+      entry.index(),
+      (entry.term(), entry.kind().expect("WIP"), entry.payload().expect("WIP").to_vec()),
+    );
+  }
+}
+
+impl LogStore for MemLog {
+  fn append(&mut self, entries: &[EntryShared]) -> io::Result<()> {
+    entries.iter().for_each(|entry| self.add(entry.capnp_as_ref()));
+    Ok(())
+  }
+
+  fn read(&self, start: Index, end: Index) -> io::Result<Vec<EntryShared>> {
+    if start > end {
+      return Ok(vec![]);
+    }
+    let entries = self
+      .entries
+      .range(start..=end)
+      .map(|(index, (term, kind, payload))| EntryShared::new(*term, *index, payload, *kind))
+      .collect();
+    Ok(entries)
+  }
+
+  fn term(&self, index: Index) -> io::Result<Option<Term>> {
+    Ok(self.entries.get(&index).map(|(term, _, _)| *term))
+  }
+
+  fn first_index(&self) -> Option<Index> {
+    self.entries.keys().next().copied()
+  }
+
+  fn last_index(&self) -> Option<Index> {
+    self.entries.keys().next_back().copied()
   }
 
-  /// Returns the payload of the entry at the given index or None if that index
-  /// doesn't exist.
-  pub fn get(&self, index: Index) -> Option<&Vec<u8>> {
-    self.entries.get(&index).map(|value| &value.1)
+  fn stable(&self) -> Option<Index> {
+    self.stable
   }
 
-  /// Marks the given index as stable, promising that it will never be truncated
-  /// by a later addition.
-  pub fn mark_stable(&mut self, index: Index) {
+  fn mark_stable(&mut self, index: Index) -> io::Result<()> {
     // TODO: only forward stable
     self.stable = Some(index);
+    Ok(())
+  }
+
+  fn compact(&mut self, index: Index) -> io::Result<()> {
+    debug_assert!(self.stable.map_or(false, |stable| index <= stable));
+    self.entries = self.entries.split_off(&(index + 1));
+    Ok(())
+  }
+
+  fn hard_state(&self) -> HardState {
+    self.hard_state
+  }
+
+  fn set_hard_state(&mut self, hard_state: HardState) -> io::Result<()> {
+    self.hard_state = hard_state;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::wildcard_imports)]
+  use super::*;
+
+  fn entry(term: u64, index: u64, payload: &str) -> EntryShared {
+    EntryShared::new(Term(term), Index(index), payload.as_bytes(), EntryKind::User)
+  }
+
+  fn payloads(log: &MemLog) -> Vec<Vec<u8>> {
+    log.user_payloads(Index(0), Index(100)).unwrap()
+  }
+
+  #[test]
+  fn memlog() {
+    let mut log = MemLog::new();
+    assert_eq!((log.first_index(), log.last_index()), (None, None));
+
+    log.append(&[entry(1, 1, "a"), entry(1, 2, "b"), entry(1, 3, "c")]).unwrap();
+    assert_eq!((log.first_index(), log.last_index()), (Some(Index(1)), Some(Index(3))));
+    assert_eq!(log.term(Index(2)).unwrap(), Some(Term(1)));
+    assert_eq!(log.term(Index(4)).unwrap(), None);
+    assert_eq!(log.read(Index(2), Index(3)).unwrap().len(), 2);
+    assert_eq!(log.read(Index(3), Index(2)).unwrap().len(), 0);
+
+    // Entries that are already in the log are skipped.
+    log.append(&[entry(1, 2, "b")]).unwrap();
+    assert_eq!(payloads(&log), vec![b"a", b"b", b"c"]);
+
+    // A conflicting entry truncates everything after it.
+    log.append(&[entry(2, 2, "d")]).unwrap();
+    assert_eq!(payloads(&log), vec![b"a", b"d"]);
+    assert_eq!(log.term(Index(2)).unwrap(), Some(Term(2)));
+
+    log.mark_stable(Index(2)).unwrap();
+    log.compact(Index(1)).unwrap();
+    assert_eq!((log.first_index(), log.last_index()), (Some(Index(2)), Some(Index(2))));
+    assert_eq!(payloads(&log), vec![b"d"]);
   }
 }
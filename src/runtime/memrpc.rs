@@ -5,12 +5,16 @@ use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 
 use crate::prelude::*;
+use crate::runtime::{Connection, Inbound, Transport, TransportError};
 
 /// An channel-based, in-process rpc implementation. Suitable for unit tests and
 /// benchmarks.
+///
+/// Clones share the same set of registered nodes. Messages to a node that isn't
+/// registered or whose channel is closed are dropped.
 #[derive(Debug, Clone)]
 pub struct MemRPC {
-  conns: Arc<Mutex<HashMap<NodeID, Sender<OwnedInput>>>>,
+  conns: Arc<Mutex<HashMap<NodeID, Inbound>>>,
 }
 impl MemRPC {
   /// Constructs a new `MemRPC` with no connections.
@@ -20,27 +24,72 @@ impl MemRPC {
 
   /// Registers a channel Sender and its destination.
   pub fn register(&mut self, dest: NodeID, sender: Sender<OwnedInput>) {
-    // TODO: handle error
-    self.conns.lock().unwrap().insert(dest, sender);
+    self.listen(dest, Inbound::new(dest, sender));
+  }
+}
+
+impl Transport for MemRPC {
+  type Connection = MemConn;
+
+  fn listen(&mut self, id: NodeID, inbound: Inbound) {
+    self.conns.lock().unwrap().insert(id, inbound);
   }
 
-  /// Returns a connection for sending to the specified node.
-  pub fn dial(&self, node: NodeID) -> MemConn {
-    // TODO: handle error
-    let sender = self.conns.lock().unwrap().get(&node).unwrap().clone();
-    MemConn { sender: sender }
+  fn dial(&self, dest: NodeID) -> MemConn {
+    MemConn { dest, conns: self.conns.clone() }
   }
 }
 
 /// A channel-based, in-process rpc connection to a peer node. Suitable for unit
 /// tests and benchmarks.
 pub struct MemConn {
-  sender: Sender<OwnedInput>,
+  dest: NodeID,
+  conns: Arc<Mutex<HashMap<NodeID, Inbound>>>,
+}
+
+impl Connection for MemConn {
+  fn send(&mut self, m: MessageRef<'_>) -> Result<(), TransportError> {
+    // NB: This is looked up each time so that a peer that's registered after
+    // this was dialed (or registered again) is found.
+    let inbound = self.conns.lock().unwrap().get(&self.dest).cloned();
+    match inbound {
+      Some(inbound) => inbound.deliver(m.capnp_to_owned()),
+      None => Err(TransportError::UnknownPeer(self.dest)),
+    }
+  }
 }
-impl MemConn {
-  /// Sends the given message.
-  pub fn send<'a>(&self, m: MessageRef<'a>) {
-    // TODO: handle error
-    self.sender.send(OwnedInput::Message(m.capnp_to_owned())).unwrap();
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::wildcard_imports)]
+  use super::*;
+
+  use std::sync::mpsc;
+
+  use crate::serde::{PayloadShared, StartElectionReqShared};
+
+  #[test]
+  fn memrpc_lossy() {
+    let msg = StartElectionReqShared::new(Term(1));
+    let msg = PayloadShared::StartElectionReq(msg);
+    let msg = MessageShared::new(NodeID(0), NodeID(1), msg, ClusterID(0), PROTOCOL_VERSION);
+    let mut rpc = MemRPC::new();
+
+    // Messages to an unknown peer are dropped instead of panicking.
+    let mut conn = rpc.dial(NodeID(1));
+    assert_eq!(conn.send(msg.capnp_as_ref()), Err(TransportError::UnknownPeer(NodeID(1))));
+
+    // A connection dialed before the peer was registered still reaches it.
+    let (sender, receiver) = mpsc::channel();
+    rpc.register(NodeID(1), sender);
+    assert_eq!(conn.send(msg.capnp_as_ref()), Ok(()));
+    match receiver.try_recv() {
+      Ok(OwnedInput::Message(got)) => assert_eq!(got.capnp_as_ref(), msg.capnp_as_ref()),
+      res => panic!("expected message got {:?}", res),
+    }
+
+    // As are messages to a peer that went away.
+    drop(receiver);
+    assert_eq!(conn.send(msg.capnp_as_ref()), Err(TransportError::Disconnected(NodeID(1))));
   }
 }
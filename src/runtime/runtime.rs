@@ -1,19 +1,32 @@
 // Copyright 2020 Daniel Harrison. All Rights Reserved.
 
-use std::collections::{HashMap, VecDeque};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Instant;
 
 use crate::prelude::*;
-use crate::runtime::{MemConn, MemLog, MemRPC};
+use crate::raft::PersistReq;
+use crate::runtime::watch::Watchers;
+use crate::runtime::{Connection, Inbound, LogStore, MemLog, MemRPC, Transport, Watch};
+
+// The generation of the PersistRes sent to the runtime's own input channel to
+// shut it down. Raft never gets near it, see PersistReq::generation.
+const SHUTDOWN_GENERATION: u64 = u64::MAX;
 
 /// A thread-safe client for interacting with the local [Raft](crate::Raft)
 /// node.
 #[derive(Clone)]
 pub struct RastClient {
   sender: Sender<OwnedInput>,
+  watchers: Arc<Mutex<Watchers>>,
 }
 
 impl RastClient {
@@ -38,48 +51,136 @@ impl RastClient {
     });
     res
   }
+
+  /// Returns a stream of the user entries committed by the Raft group, starting
+  /// from the one at the given index. `Index(0)`, which is before the first
+  /// entry, starts from the beginning of the log.
+  ///
+  /// The stream fails with a [`WatchError`](crate::runtime::WatchError) if that
+  /// index has been compacted out of the log.
+  pub fn watch(&self, start: Index) -> Watch {
+    self.watchers.lock().unwrap().add(start)
+  }
 }
 
+// What the thread running a node hands back when it's shut down: the queue of
+// inputs that weren't handled yet and the log.
+type Exit<L> = Result<(Receiver<OwnedInput>, L), mpsc::RecvError>;
+
 /// An in-process end-to-end implementation of Raft, including log and rpc.
 ///
+/// The log can be any [`LogStore`] and the rpc system any [`Transport`].
 /// Currently only suitable for unit tests and benchmarks.
-pub struct Runtime {
+pub struct Runtime<L: LogStore + Send + 'static = MemLog, T: Transport = MemRPC> {
   /// The unique id of the local Raft node.
   pub id: NodeID,
-  handle: Option<JoinHandle<Result<(), mpsc::RecvError>>>,
+  name: String,
+  rpc: T,
+  handle: Option<JoinHandle<Exit<L>>>,
+  // While crashed, inputs keep being queued here so the clients and peers of
+  // this node don't need to reconnect when it's restarted.
+  crashed: Option<Receiver<OwnedInput>>,
   client: RastClient,
 }
 
-impl Runtime {
+impl<L: LogStore + Send + 'static, T: Transport> Runtime<L, T> {
   /// Starts a Raft runtime, driving network/disk IO and clock ticks as
-  /// necessary. This runtime is spawned in a new thread and stops when
-  /// [`stop`](Runtime::stop) is called or when the returned handle is dropped.
-  pub fn new(name: String, raft: Raft, rpc: MemRPC, log: MemLog) -> Runtime {
+  /// necessary. This runtime is spawned in a new thread (plus one that writes
+  /// the log) and stops when [`stop`](Runtime::stop) is called or when the
+  /// returned handle is dropped.
+  ///
+  /// Messages sent to this node over `rpc` are handed to it. The runtime
+  /// records to the same [`Metrics`] as the given `raft`.
+  pub fn new(name: String, raft: Raft, mut rpc: T, log: L) -> Runtime<L, T> {
     let id = raft.id();
     let (sender, receiver) = mpsc::channel();
-    let client = RastClient { sender: sender };
-    let handle = thread::Builder::new()
-      .name(name)
-      .spawn(move || Runtime::run(raft, receiver, rpc, log))
-      .expect("WIP");
-    // TODO start up a ticker thread too
-    Runtime { id: id, handle: Some(handle), client: client }
+    rpc.listen(id, Inbound::new(id, sender.clone()));
+    let client = RastClient { sender, watchers: Arc::new(Mutex::new(Watchers::new())) };
+    let mut runtime = Runtime { id, name, rpc, handle: None, crashed: None, client };
+    runtime.spawn(raft, receiver, log).expect("WIP");
+    runtime
   }
 
   /// Stops the Raft runtime represented by this handle.
   pub fn stop(&mut self) {
+    self.crashed = None;
+    let handle = match self.handle.take() {
+      Some(handle) => handle,
+      None => return,
+    };
     // Send the shutdown sentinel.
-    let msg = PersistRes { leader_id: NodeID(0), read_id: ReadID(0), log_index: Index(0) };
+    let msg = PersistRes {
+      leader_id: NodeID(0),
+      read_id: ReadID(0),
+      log_index: Index(0),
+      generation: SHUTDOWN_GENERATION,
+      error: None,
+    };
     match self.client.sender.send(Input::PersistRes(msg).into()).err() {
       Some(_) => {
         debug!("runtime crashed before stop");
       }
       None => {
         debug!("runtime stopping");
-        self.handle.take().unwrap().join().unwrap().unwrap();
+        handle.join().unwrap().unwrap();
         debug!("runtime stopped");
       }
     }
+    self.client.watchers.lock().unwrap().close();
+  }
+
+  /// Simulates a crash of the process running this Raft runtime.
+  ///
+  /// The in-memory Raft node is discarded, along with any disk IO it requested
+  /// that wasn't yet acknowledged back to it. Returns the node's log, which
+  /// holds everything that was acknowledged, so that it can be handed to
+  /// [`restart`](Runtime::restart).
+  ///
+  /// Panics if the runtime isn't running, which includes if its thread
+  /// panicked.
+  pub fn crash(&mut self) -> L {
+    debug!("runtime crashing");
+    let handle = self.handle.take().expect("runtime is not running");
+    // Send the shutdown sentinel. NB: An error here means the thread running the
+    // node has already exited, which joining it reports.
+    let msg = PersistRes {
+      leader_id: NodeID(0),
+      read_id: ReadID(0),
+      log_index: Index(0),
+      generation: SHUTDOWN_GENERATION,
+      error: None,
+    };
+    let _ = self.client.sender.send(Input::PersistRes(msg).into());
+    let (receiver, log) = match handle.join() {
+      Ok(exit) => exit.expect("unreachable"),
+      Err(panic) => panic::resume_unwind(panic),
+    };
+    self.crashed = Some(receiver);
+    debug!("runtime crashed");
+    log
+  }
+
+  /// Starts a crashed Raft runtime again with the given node and log.
+  ///
+  /// Any inputs sent to the runtime while it was crashed are dropped, as if
+  /// they never arrived. Reads and writes among them fail with a
+  /// [`NotLeaderError`].
+  ///
+  /// Returns an error if the runtime's threads couldn't be started, in which
+  /// case it's left stopped. Panics if the runtime isn't crashed.
+  pub fn restart(&mut self, raft: Raft, log: L) -> io::Result<()> {
+    debug!("runtime restarting");
+    let receiver = self.crashed.take().expect("runtime is not crashed");
+    receiver.try_iter().for_each(|input| match input {
+      OwnedInput::Write(_, mut res) => {
+        res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))))
+      }
+      OwnedInput::Read(_, mut res) => {
+        res.fill(Err(ClientError::NotLeaderError(NotLeaderError::new(None))))
+      }
+      _ => {}
+    });
+    self.spawn(raft, receiver, log)
   }
 
   /// Returns a new thread-safe client for interacting with this Raft node.
@@ -92,71 +193,388 @@ impl Runtime {
     self.client.sender.clone()
   }
 
-  fn run(
-    mut raft: Raft,
-    reqs: Receiver<OwnedInput>,
-    rpc: MemRPC,
-    _log: MemLog,
-  ) -> Result<(), mpsc::RecvError> {
-    let mut conns: HashMap<NodeID, MemConn> = HashMap::new();
-    let mut cmds = VecDeque::new();
+  fn spawn(&mut self, raft: Raft, receiver: Receiver<OwnedInput>, log: L) -> io::Result<()> {
+    let rpc = self.rpc.clone();
+    let writer = LogWriter::spawn(
+      &self.name,
+      log,
+      self.client.sender.clone(),
+      self.client.watchers.clone(),
+      raft.metrics(),
+    )?;
+    let handle = thread::Builder::new()
+      .name(self.name.clone())
+      .spawn(move || Runtime::run(raft, receiver, rpc, writer))?;
+    self.handle = Some(handle);
+    Ok(())
+  }
+
+  fn run(mut raft: Raft, reqs: Receiver<OwnedInput>, rpc: T, writer: LogWriter<L>) -> Exit<L> {
+    // TODO: Make this configurable.
+    let tick_interval = raft.config().heartbeat_interval;
+    let mut conns: HashMap<NodeID, T::Connection> = HashMap::new();
+    let mut hard_state = raft.hard_state();
     let mut output = vec![];
-    let mut state: Vec<u8> = vec![];
+    // NB: The first tick is delayed so that a node that's sent a request right
+    // away can win an election before the others call one.
+    let mut next_tick = Instant::now() + tick_interval;
     loop {
-      let cmd = match cmds.pop_front() {
-        Some(cmd) => cmd,
-        None => reqs.recv()?,
+      let now = Instant::now();
+      let cmd = if now >= next_tick {
+        next_tick = now + tick_interval;
+        Input::Tick(now).into()
+      } else {
+        match reqs.recv_timeout(next_tick - now) {
+          Ok(cmd) => cmd,
+          Err(RecvTimeoutError::Timeout) => continue,
+          Err(RecvTimeoutError::Disconnected) => {
+            writer.crash();
+            return Err(mpsc::RecvError);
+          }
+        }
       };
       // If we got the shutdown sentinel, exit.
-      match &cmd {
-        OwnedInput::PersistRes(res) => {
-          if res.log_index == Index(0) {
-            return Ok(());
-          }
+      if let OwnedInput::PersistRes(res) = &cmd {
+        if res.generation == SHUTDOWN_GENERATION {
+          return Ok((reqs, writer.crash()));
         }
-        _ => {}
       }
-      raft.step(&mut output, cmd.as_ref());
+      // A message that no correct peer would have sent is dropped, which is
+      // surfaced as an Event::MessageRejected.
+      let _ = raft.step(&mut output, cmd.as_ref());
+      // Raft doesn't output its hard state yet, so persist it before any of the
+      // outputs are processed. See Raft::hard_state.
+      if raft.hard_state() != hard_state {
+        hard_state = raft.hard_state();
+        writer.set_hard_state(hard_state);
+      }
       #[cfg(feature = "log")]
       output.iter().for_each(|o| {
         debug!("  out: {:?}", o);
       });
       output.drain(..).for_each(|output| match output {
-        Output::ApplyReq(_) => {
-          // TODO: implement
-        }
-        Output::PersistReq(req) => {
-          // TODO: implement
-          req
-            .entries
-            .iter()
-            .for_each(|entry| state.extend(entry.capnp_as_ref().payload().expect("WIP").iter()));
-          let msg = PersistRes {
-            leader_id: req.leader_id,
-            read_id: req.read_id,
-            log_index: req.entries.last().unwrap().capnp_as_ref().index(),
-          };
-          cmds.push_back(Input::PersistRes(msg).into());
-        }
-        Output::ReadStateMachineReq(req) => {
-          // TODO: implement
-          let payload = state.clone();
-          let msg =
-            ReadStateMachineRes { index: req.index, read_id: req.read_id, payload: payload };
-          cmds.push_back(Input::ReadStateMachineRes(msg).into());
-        }
         Output::Message(message) => {
           let dest = message.capnp_as_ref().dest();
           let conn = conns.entry(dest).or_insert_with(|| rpc.dial(dest));
-          conn.send(message.capnp_as_ref());
+          if conn.send(message.capnp_as_ref()).is_err() {
+            // Messages are allowed to be lost (see Output), so this one is
+            // dropped. Dial again next time in case the peer comes back.
+            conns.remove(&dest);
+          }
         }
+        output => writer.push(output),
       });
     }
   }
 }
 
-impl Drop for Runtime {
+impl<L: LogStore + Send + 'static, T: Transport> Drop for Runtime<L, T> {
   fn drop(&mut self) {
     self.stop();
   }
 }
+
+// The log writer stage of a Runtime.
+//
+// Disk IO requested by Raft is handled on a dedicated thread, so the raft loop
+// keeps handling inputs while the log syncs. Every PersistReq that queues up
+// while an append is in flight is made durable by the next append, one for all
+// of them (group commit), after which each gets its PersistRes in order. Disk IO
+// is otherwise still handled one at a time and in the order it was requested,
+// see Output.
+struct LogWriter<L: LogStore + Send + 'static> {
+  log: Arc<Mutex<L>>,
+  watchers: Arc<Mutex<Watchers>>,
+  reqs: Sender<Output>,
+  crashed: Arc<AtomicBool>,
+  handle: JoinHandle<()>,
+}
+
+impl<L: LogStore + Send + 'static> LogWriter<L> {
+  // Starts a log writer, which hands the inputs resulting from disk IO to
+  // `inputs` and serves `watchers` from the log.
+  fn spawn(
+    name: &str,
+    log: L,
+    inputs: Sender<OwnedInput>,
+    watchers: Arc<Mutex<Watchers>>,
+    metrics: Arc<dyn Metrics>,
+  ) -> io::Result<LogWriter<L>> {
+    let log = Arc::new(Mutex::new(log));
+    watchers.lock().unwrap().attach(log.clone());
+    let (sender, reqs) = mpsc::channel();
+    let crashed = Arc::new(AtomicBool::new(false));
+    let handle = {
+      let (log, watchers, crashed) = (log.clone(), watchers.clone(), crashed.clone());
+      thread::Builder::new()
+        .name(format!("{}-log", name))
+        .spawn(move || LogWriter::run(log, reqs, inputs, watchers, crashed, metrics))?
+    };
+    Ok(LogWriter { log, watchers, reqs: sender, crashed, handle })
+  }
+
+  // Queues the given disk IO.
+  fn push(&self, req: Output) {
+    // The writer only exits when crashed, which consumes self.
+    self.reqs.send(req).expect("unreachable");
+  }
+
+  // Durably persists the given hard state, waiting for any in-flight append to
+  // finish first.
+  fn set_hard_state(&self, hard_state: HardState) {
+    // TODO: Handle errors from the log.
+    self.log.lock().unwrap().set_hard_state(hard_state).expect("WIP");
+  }
+
+  // Stops the writer and returns the log. Queued disk IO that wasn't started
+  // yet is lost, as if the process crashed.
+  fn crash(self) -> L {
+    self.crashed.store(true, Ordering::SeqCst);
+    drop(self.reqs);
+    self.handle.join().unwrap();
+    self.watchers.lock().unwrap().detach();
+    let log = Arc::try_unwrap(self.log).ok().expect("unreachable");
+    log.into_inner().unwrap()
+  }
+
+  fn run(
+    log: Arc<Mutex<L>>,
+    reqs: Receiver<Output>,
+    inputs: Sender<OwnedInput>,
+    watchers: Arc<Mutex<Watchers>>,
+    crashed: Arc<AtomicBool>,
+    metrics: Arc<dyn Metrics>,
+  ) {
+    let mut queue: VecDeque<Output> = VecDeque::new();
+    let mut state: Vec<u8> = vec![];
+    // The generation of the last failed write, see PersistReq::generation.
+    let mut failed: Option<u64> = None;
+    loop {
+      if queue.is_empty() {
+        match reqs.recv() {
+          Ok(req) => queue.push_back(req),
+          Err(_) => return,
+        }
+      }
+      queue.extend(reqs.try_iter());
+      if crashed.load(Ordering::SeqCst) {
+        return;
+      }
+      let mut batch = vec![];
+      while let Some(Output::PersistReq(_)) = queue.front() {
+        match queue.pop_front() {
+          Some(Output::PersistReq(req)) => batch.push(req),
+          _ => unreachable!(),
+        }
+      }
+      // NB: Errors sending to inputs mean the runtime has been dropped, in which
+      // case nobody is waiting for the result.
+      if batch.is_empty() {
+        let req = queue.pop_front().expect("unreachable");
+        let applied = matches!(req, Output::ApplyReq(_));
+        let input = finish_disk(req, &mut *log.lock().unwrap(), &mut state, &mut failed);
+        if let Some(input) = input {
+          let _ = inputs.send(input);
+        }
+        if applied {
+          watchers.lock().unwrap().notify();
+        }
+        continue;
+      }
+      // Generations only go up, so any writes that must fail are at the front.
+      let stale = batch.iter().take_while(|req| failed_before(req, failed)).count();
+      batch.drain(..stale).for_each(|req| {
+        let res = persist_res(&req, Some(PersistError::new("queued behind a failed write")));
+        let _ = inputs.send(Input::PersistRes(res).into());
+      });
+      if batch.is_empty() {
+        continue;
+      }
+      let start = Instant::now();
+      let generation = batch.last().expect("unreachable").generation;
+      let (entries, res) = group_commit(batch);
+      let error = log.lock().unwrap().append(&entries).err().map(PersistError::from);
+      metrics.persist_latency(start.elapsed());
+      if error.is_some() {
+        failed = Some(generation);
+      }
+      res.into_iter().for_each(|mut res| {
+        // Every write in the group fails together.
+        res.error = error.clone();
+        let _ = inputs.send(Input::PersistRes(res).into());
+      });
+    }
+  }
+}
+
+// Combines the given PersistReqs into the entries for a single append and the
+// PersistRes for each, in order.
+//
+// The entries are merged the same way the log appends them (see
+// LogStore::append). An entry already in the batch (same index and term) is
+// skipped, and one that conflicts with an earlier request's (same index but a
+// different term) replaces it and everything after it, just as the log would
+// have truncated them.
+fn group_commit(batch: Vec<PersistReq>) -> (Vec<EntryShared>, Vec<PersistRes>) {
+  let mut entries: BTreeMap<Index, EntryShared> = BTreeMap::new();
+  let mut res = Vec::with_capacity(batch.len());
+  for req in batch {
+    res.push(persist_res(&req, None));
+    for entry in req.entries {
+      let (term, index) = (entry.capnp_as_ref().term(), entry.capnp_as_ref().index());
+      match entries.get(&index) {
+        Some(existing) if existing.capnp_as_ref().term() == term => continue,
+        Some(_) => {
+          let _ = entries.split_off(&index);
+        }
+        None => {}
+      }
+      entries.insert(index, entry);
+    }
+  }
+  (entries.into_values().collect(), res)
+}
+
+// Returns whether the given write must fail without being attempted, because an
+// earlier one failed. See PersistReq::generation.
+fn failed_before(req: &PersistReq, failed: Option<u64>) -> bool {
+  failed.map_or(false, |failed| req.generation <= failed)
+}
+
+fn persist_res(req: &PersistReq, error: Option<PersistError>) -> PersistRes {
+  PersistRes {
+    leader_id: req.leader_id,
+    read_id: req.read_id,
+    log_index: req.entries.last().map_or(Index(0), |entry| entry.capnp_as_ref().index()),
+    generation: req.generation,
+    error,
+  }
+}
+
+// Performs the given disk IO against `log` and returns the resulting input for
+// Raft, if any. `state` is the state machine, which is rebuilt from the log on
+// each apply, and `failed` is the generation of the last failed write.
+pub(crate) fn finish_disk<L: LogStore>(
+  req: Output,
+  log: &mut L,
+  state: &mut Vec<u8>,
+  failed: &mut Option<u64>,
+) -> Option<OwnedInput> {
+  match req {
+    Output::PersistReq(req) => {
+      let error = if failed_before(&req, *failed) {
+        Some(PersistError::new("queued behind a failed write"))
+      } else {
+        log.append(&req.entries).err().map(PersistError::from)
+      };
+      if error.is_some() {
+        *failed = cmp::max(*failed, Some(req.generation));
+      }
+      Some(Input::PersistRes(persist_res(&req, error)).into())
+    }
+    Output::ApplyReq(index) => {
+      log.mark_stable(index).expect("WIP");
+      *state = log.user_payloads(Index(0), index).expect("WIP").concat();
+      None
+    }
+    Output::ReadStateMachineReq(req) => {
+      let reads = req.reads.into_iter().map(|(read_id, _)| (read_id, state.clone())).collect();
+      let msg = ReadStateMachineRes { index: req.index, reads };
+      Some(Input::ReadStateMachineRes(msg).into())
+    }
+    Output::ReadLogReq(req) => {
+      let entries = log.read(req.start, req.end).expect("WIP");
+      let msg = ReadLogRes { peer: req.peer, term: req.term, entries };
+      Some(Input::ReadLogRes(msg).into())
+    }
+    Output::Message(_) => unreachable!(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::wildcard_imports)]
+  use super::*;
+
+  fn persist(leader_id: u64, entries: &[(u64, u64, &str)]) -> Output {
+    let entries = entries
+      .iter()
+      .map(|(term, index, payload)| {
+        EntryShared::new(Term(*term), Index(*index), payload.as_bytes(), EntryKind::User)
+      })
+      .collect();
+    Output::PersistReq(PersistReq {
+      leader_id: NodeID(leader_id),
+      read_id: ReadID(0),
+      entries,
+      generation: 0,
+    })
+  }
+
+  #[test]
+  fn log_writer_group_commit() {
+    let log = Arc::new(Mutex::new(MemLog::new()));
+    let (sender, reqs) = mpsc::channel();
+    let (inputs, receiver) = mpsc::channel();
+    let metrics = Arc::new(MemMetrics::new());
+
+    // Everything is queued before the writer starts, like PersistReqs that
+    // arrive during an fsync. The last one conflicts with the second.
+    sender.send(persist(0, &[(1, 1, "a"), (1, 2, "b")])).unwrap();
+    sender.send(persist(0, &[(1, 3, "c")])).unwrap();
+    sender.send(persist(1, &[(2, 3, "d"), (2, 4, "e")])).unwrap();
+    sender.send(Output::ApplyReq(Index(2))).unwrap();
+    sender.send(persist(1, &[(2, 5, "f")])).unwrap();
+    drop(sender);
+    let crashed = Arc::new(AtomicBool::new(false));
+    let watchers = Arc::new(Mutex::new(Watchers::new()));
+    LogWriter::run(log.clone(), reqs, inputs, watchers, crashed, metrics.clone());
+
+    // The first three are one append, the apply is a barrier, and the last is a
+    // second append.
+    assert_eq!(metrics.snapshot().persist_latencies.len(), 2);
+    let res: Vec<_> = receiver
+      .try_iter()
+      .map(|input| match input {
+        OwnedInput::PersistRes(res) => (res.leader_id.0, res.log_index.0),
+        input => panic!("expected PersistRes got {:?}", input),
+      })
+      .collect();
+    assert_eq!(res, vec![(0, 2), (0, 3), (1, 4), (1, 5)]);
+    let log = log.lock().unwrap();
+    assert_eq!(log.stable(), Some(Index(2)));
+    let payloads = log.user_payloads(Index(0), Index(100)).unwrap();
+    assert_eq!(payloads, vec![b"a", b"b", b"d", b"e", b"f"]);
+  }
+
+  #[test]
+  fn log_writer_group_commit_duplicate() {
+    let log = Arc::new(Mutex::new(MemLog::new()));
+    let (sender, reqs) = mpsc::channel();
+    let (inputs, receiver) = mpsc::channel();
+    let metrics = Arc::new(MemMetrics::new());
+
+    // A duplicate of an earlier AppendEntries is persisted again after a later
+    // one, in the same batch. It doesn't conflict, so nothing is truncated.
+    sender.send(persist(0, &[(1, 1, "a"), (1, 2, "b"), (1, 3, "c")])).unwrap();
+    sender.send(persist(0, &[(1, 1, "a")])).unwrap();
+    drop(sender);
+    let crashed = Arc::new(AtomicBool::new(false));
+    let watchers = Arc::new(Mutex::new(Watchers::new()));
+    LogWriter::run(log.clone(), reqs, inputs, watchers, crashed, metrics.clone());
+
+    assert_eq!(metrics.snapshot().persist_latencies.len(), 1);
+    let res: Vec<_> = receiver
+      .try_iter()
+      .map(|input| match input {
+        OwnedInput::PersistRes(res) => res.log_index.0,
+        input => panic!("expected PersistRes got {:?}", input),
+      })
+      .collect();
+    assert_eq!(res, vec![3, 1]);
+    let log = log.lock().unwrap();
+    assert_eq!(log.last_index(), Some(Index(3)));
+    let payloads = log.user_payloads(Index(0), Index(100)).unwrap();
+    assert_eq!(payloads, vec![b"a", b"b", b"c"]);
+  }
+}
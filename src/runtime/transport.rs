@@ -0,0 +1,98 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::sync::mpsc::Sender;
+
+use crate::prelude::*;
+use crate::runtime::DriverHandle;
+
+/// An rpc system for sending messages between the nodes of a Raft group.
+///
+/// This is what a [`Runtime`] sends `Output::Message`s with. Messages are
+/// allowed to be lost (see [`Output`]), so sends never block and a message that
+/// can't be sent is dropped.
+///
+/// [`Runtime`]: crate::runtime::Runtime
+pub trait Transport: Clone + Send + 'static {
+  /// A connection for sending to one peer, see [`dial`](Transport::dial).
+  type Connection: Connection;
+
+  /// Arranges for messages sent to the given node to be handed to `inbound`.
+  fn listen(&mut self, id: NodeID, inbound: Inbound);
+
+  /// Returns a connection for sending to the given node.
+  ///
+  /// This doesn't fail, even if the node is unknown or down. Any problem is
+  /// instead returned by [`Connection::send`].
+  fn dial(&self, dest: NodeID) -> Self::Connection;
+}
+
+/// A connection to a peer node, see [`Transport`].
+pub trait Connection: Send {
+  /// Sends the given message without blocking.
+  ///
+  /// An error means the message was dropped. The connection may work again
+  /// later, but it's fine to discard it and dial again.
+  fn send(&mut self, msg: MessageRef<'_>) -> Result<(), TransportError>;
+}
+
+/// The receiving end of a [`Transport`] for one node, which hands inbound
+/// messages to its [`Runtime`](crate::runtime::Runtime) or
+/// [`Driver`](crate::runtime::Driver).
+#[derive(Debug, Clone)]
+pub struct Inbound {
+  id: NodeID,
+  queue: InboundQueue,
+}
+
+#[derive(Debug, Clone)]
+enum InboundQueue {
+  Channel(Sender<OwnedInput>),
+  Driver(DriverHandle),
+}
+
+impl Inbound {
+  /// Returns an `Inbound` that hands messages to the given channel.
+  pub fn new(id: NodeID, sender: Sender<OwnedInput>) -> Inbound {
+    Inbound { id, queue: InboundQueue::Channel(sender) }
+  }
+
+  pub(crate) fn driver(id: NodeID, handle: DriverHandle) -> Inbound {
+    Inbound { id, queue: InboundQueue::Driver(handle) }
+  }
+
+  /// Hands the given message to the node.
+  pub fn deliver(&self, msg: MessageShared) -> Result<(), TransportError> {
+    let res = match &self.queue {
+      InboundQueue::Channel(sender) => sender.send(OwnedInput::Message(msg)).map_err(|_| ()),
+      InboundQueue::Driver(handle) => handle.send(OwnedInput::Message(msg)).map_err(|_| ()),
+    };
+    res.map_err(|_| TransportError::Disconnected(self.id))
+  }
+}
+
+/// An error returned when a message couldn't be sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+  /// Nothing is listening for messages to the given node.
+  UnknownPeer(NodeID),
+  /// The given node stopped listening for messages.
+  Disconnected(NodeID),
+}
+
+impl Display for TransportError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      TransportError::UnknownPeer(id) => write!(f, "unknown peer: {:?}", id),
+      TransportError::Disconnected(id) => write!(f, "peer disconnected: {:?}", id),
+    }
+  }
+}
+
+impl Error for TransportError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
@@ -0,0 +1,246 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::prelude::*;
+use crate::runtime::LogStore;
+
+/// A user entry committed by the Raft group, see [`Watch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommittedEntry {
+  /// The term of the leader that wrote the entry.
+  pub term: Term,
+  /// The position of the entry in the Raft log.
+  pub index: Index,
+  /// The payload of the [`WriteReq`] that wrote the entry.
+  pub payload: Vec<u8>,
+}
+
+/// An error returned by a [`Watch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchError {
+  /// The requested index was compacted out of the log, so the entries from it
+  /// on can't be served.
+  Compacted {
+    /// The index the watch was started from.
+    index: Index,
+    /// The first index still in the log.
+    first: Index,
+  },
+}
+
+impl Display for WatchError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      WatchError::Compacted { index, first } => {
+        write!(f, "index {:?} was compacted, first is {:?}", index, first)
+      }
+    }
+  }
+}
+
+impl Error for WatchError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Debug)]
+struct WatchState {
+  // The index of the next entry to hand out.
+  next: Index,
+  entries: VecDeque<Result<CommittedEntry, WatchError>>,
+  // The waker of the last poll that found entries empty.
+  waker: Option<Waker>,
+  closed: bool,
+}
+
+/// An async stream of the user entries committed by a Raft group, in log
+/// order, as applied by the local node.
+///
+/// Returned by [`RastClient::watch`](crate::runtime::RastClient::watch). Entries
+/// are read from the log as they're applied, so a watch that starts behind is
+/// caught up from the log first. Entries written by Raft itself, such as the
+/// no-op a new leader appends, are skipped. Entries that haven't been taken
+/// with [`next_entry`](Watch::next_entry) are buffered without bound.
+#[derive(Debug)]
+pub struct Watch {
+  state: Arc<Mutex<WatchState>>,
+}
+
+impl Watch {
+  /// Returns a future resolved with the next committed entry.
+  ///
+  /// This is `None` once the runtime is stopped or after an error.
+  pub fn next_entry(&mut self) -> WatchNext<'_> {
+    WatchNext { watch: self }
+  }
+}
+
+/// The [`Future`] returned by [`Watch::next_entry`].
+#[derive(Debug)]
+pub struct WatchNext<'a> {
+  watch: &'a mut Watch,
+}
+
+impl<'a> Future for WatchNext<'a> {
+  type Output = Option<Result<CommittedEntry, WatchError>>;
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+    let mut state = self.watch.state.lock().unwrap();
+    if let Some(entry) = state.entries.pop_front() {
+      return Poll::Ready(Some(entry));
+    }
+    if state.closed {
+      return Poll::Ready(None);
+    }
+    state.waker = Some(cx.waker().clone());
+    Poll::Pending
+  }
+}
+
+// The watches of a runtime and the log they're served from.
+//
+// This outlives any one log writer, so that watches keep going across a crash
+// and restart. The log is detached while the runtime is crashed.
+pub(crate) struct Watchers {
+  log: Option<Arc<Mutex<dyn LogStore + Send>>>,
+  watches: Vec<Arc<Mutex<WatchState>>>,
+}
+
+impl Watchers {
+  pub(crate) fn new() -> Watchers {
+    Watchers { log: None, watches: vec![] }
+  }
+
+  // Starts serving watches from the given log.
+  pub(crate) fn attach(&mut self, log: Arc<Mutex<dyn LogStore + Send>>) {
+    self.log = Some(log);
+    self.notify();
+  }
+
+  // Stops serving watches until the next attach.
+  pub(crate) fn detach(&mut self) {
+    self.log = None;
+  }
+
+  // Ends every watch.
+  pub(crate) fn close(&mut self) {
+    self.detach();
+    self.watches.drain(..).for_each(|watch| {
+      let mut watch = watch.lock().unwrap();
+      watch.closed = true;
+      watch.waker.take().iter().for_each(Waker::wake_by_ref);
+    });
+  }
+
+  // Returns a new watch of the entries from `start` on.
+  pub(crate) fn add(&mut self, start: Index) -> Watch {
+    // NB: Index(0) is before the first entry, it means from the beginning.
+    let start = cmp::max(start, Index(1));
+    let state = WatchState { next: start, entries: VecDeque::new(), waker: None, closed: false };
+    let state = Arc::new(Mutex::new(state));
+    self.watches.push(state.clone());
+    self.notify();
+    Watch { state }
+  }
+
+  // Hands every watch whatever has been applied since it was last notified.
+  pub(crate) fn notify(&mut self) {
+    let log = match &self.log {
+      Some(log) => log.lock().unwrap(),
+      None => return,
+    };
+    // NB: A watch that was dropped is only referenced here.
+    self.watches.retain(|watch| Arc::strong_count(watch) > 1);
+    self.watches.retain(|watch| catch_up(&*log, &mut watch.lock().unwrap()));
+  }
+}
+
+// Reads everything applied from the next index of `watch` on out of the log
+// and hands it to the watch. Returns false if the watch is over.
+fn catch_up(log: &dyn LogStore, watch: &mut WatchState) -> bool {
+  // NB: The log may have been compacted all the way through the stable index.
+  let first = log.first_index().or_else(|| log.stable().map(|stable| stable + 1));
+  if let Some(first) = first.filter(|first| watch.next < *first) {
+    watch.entries.push_back(Err(WatchError::Compacted { index: watch.next, first }));
+    watch.closed = true;
+  } else if let Some(stable) = log.stable().filter(|stable| watch.next <= *stable) {
+    for entry in log.read(watch.next, stable).expect("WIP").iter() {
+      let entry = entry.capnp_as_ref();
+      if !matches!(entry.kind(), Ok(EntryKind::User)) {
+        continue;
+      }
+      let payload = entry.payload().expect("WIP").to_vec();
+      let entry = CommittedEntry { term: entry.term(), index: entry.index(), payload };
+      watch.entries.push_back(Ok(entry));
+    }
+    watch.next = stable + 1;
+  } else {
+    return true;
+  }
+  watch.waker.take().iter().for_each(Waker::wake_by_ref);
+  !watch.closed
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::wildcard_imports)]
+  use super::*;
+
+  use crate::runtime::{MemLog, MemRPC, Runtime};
+
+  #[test]
+  fn watch() {
+    let raft = Raft::new(NodeID(0), vec![NodeID(0)], Config::default());
+    let runtime = Runtime::new("watch".to_string(), raft, MemRPC::new(), MemLog::new());
+    let client = runtime.client();
+    extreme::run(client.write(WriteReq { payload: b"a".to_vec() })).unwrap();
+
+    // A watch that starts behind is caught up from the log, skipping the no-op,
+    // and then sees new entries as they're applied.
+    let mut watch = client.watch(Index(1));
+    let entry = extreme::run(watch.next_entry()).unwrap().unwrap();
+    assert_eq!(entry, CommittedEntry { term: Term(1), index: Index(2), payload: b"a".to_vec() });
+    extreme::run(client.write(WriteReq { payload: b"b".to_vec() })).unwrap();
+    let entry = extreme::run(watch.next_entry()).unwrap().unwrap();
+    assert_eq!(entry, CommittedEntry { term: Term(1), index: Index(3), payload: b"b".to_vec() });
+
+    // Index 0 is before the first entry, so a watch from there starts at the
+    // beginning of the log. Nothing has been compacted.
+    let mut from_zero = client.watch(Index(0));
+    let entry = extreme::run(from_zero.next_entry()).unwrap().unwrap();
+    assert_eq!(entry.index, Index(2));
+
+    // The watch ends when the runtime is stopped.
+    drop(runtime);
+    assert_eq!(extreme::run(watch.next_entry()), None);
+  }
+
+  #[test]
+  fn watch_compacted() {
+    let mut log = MemLog::new();
+    let entries: Vec<_> =
+      (1..=3).map(|index| EntryShared::new(Term(1), Index(index), b"x", EntryKind::User)).collect();
+    log.append(&entries).unwrap();
+    log.mark_stable(Index(3)).unwrap();
+    log.compact(Index(1)).unwrap();
+    let mut watchers = Watchers::new();
+    watchers.attach(Arc::new(Mutex::new(log)));
+
+    let mut watch = watchers.add(Index(1));
+    let err = WatchError::Compacted { index: Index(1), first: Index(2) };
+    assert_eq!(extreme::run(watch.next_entry()), Some(Err(err)));
+    assert_eq!(extreme::run(watch.next_entry()), None);
+
+    let mut watch = watchers.add(Index(2));
+    assert_eq!(extreme::run(watch.next_entry()).unwrap().unwrap().index, Index(2));
+  }
+}
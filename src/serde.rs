@@ -2,6 +2,7 @@
 
 use std::fmt;
 use std::ops::Add;
+use std::time::Duration;
 
 /// A Raft term.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -15,9 +16,16 @@ pub struct Index(pub u64);
 ///
 /// Nodes must restart with the same ID, unless they lose data, in which case
 /// they need to be started from scratch with a new ID.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct NodeID(pub u64);
 
+/// A unique identifier for a raft group.
+///
+/// Every node in a group must be configured with the same one, see
+/// [`Config::cluster_id`](crate::Config::cluster_id).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ClusterID(pub u64);
+
 /// An internal identifier for tracking the allowability of a read request.
 ///
 /// TODO: Make this more general.
@@ -63,9 +71,41 @@ pub struct WriteRes {
   pub index: Index,
 }
 
+/// How up to date the state a [`ReadReq`] is served from must be.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ReadConsistency {
+  /// The read reflects every write that completed before it was started.
+  ///
+  /// Only the leader can serve these, once it has confirmed with a majority of
+  /// the group that it's still the leader.
+  #[default]
+  Linearizable,
+  /// The read reflects at least every entry up to and including the given
+  /// index.
+  ///
+  /// This is served by whichever node it's sent to, once that node has applied
+  /// the index. For example: Passing the [`WriteRes::index`] of an earlier
+  /// write gives read-your-writes. It fails with
+  /// [`ClientError::UnknownIndex`](crate::ClientError::UnknownIndex) if that
+  /// node hasn't heard of the index yet, or if the entry it had at the index
+  /// is truncated before it's committed.
+  AtLeastIndex(Index),
+  /// The read reflects at least everything that was committed the given
+  /// duration before it was started.
+  ///
+  /// This is served by whichever node it's sent to if it heard from the leader
+  /// recently enough (or, for the leader, from a majority of the group) and
+  /// otherwise falls back to [`ReadConsistency::Linearizable`]. Unlike the
+  /// other levels, this depends on the clocks of the nodes running at about the
+  /// same rate.
+  MaxStaleness(Duration),
+}
+
 /// See [`Input::Read`](crate::Input::Read).
 #[derive(Clone)]
 pub struct ReadReq {
+  /// How up to date the state this is served from must be.
+  pub consistency: ReadConsistency,
   /// The read payload to be handed to the replicated state machine.
   ///
   /// For example: This could be a key when the replicated state machine is a
@@ -75,15 +115,19 @@ pub struct ReadReq {
 
 impl From<String> for ReadReq {
   fn from(payload: String) -> Self {
-    ReadReq { payload: payload.into_bytes() }
+    ReadReq { consistency: ReadConsistency::Linearizable, payload: payload.into_bytes() }
   }
 }
 
 impl fmt::Debug for ReadReq {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match std::str::from_utf8(&self.payload) {
-      Ok(payload) => write!(f, "r{:?}", payload),
-      Err(_) => write!(f, "r{:?}", self.payload),
+      Ok(payload) => write!(f, "r{:?}", payload)?,
+      Err(_) => write!(f, "r{:?}", self.payload)?,
+    }
+    match self.consistency {
+      ReadConsistency::Linearizable => Ok(()),
+      consistency => write!(f, "@{:?}", consistency),
     }
   }
 }
@@ -94,6 +138,9 @@ pub struct ReadRes {
   /// The term at which the read happened.
   pub term: Term,
   /// The index at which the read happened.
+  ///
+  /// The state the read was served from reflects every entry up to and
+  /// including this one and none after it.
   pub index: Index,
   /// The result of reading the state machine with the request's payload.
   ///
@@ -106,12 +153,15 @@ pub struct ReadRes {
 mod generated {
   use std::fmt;
 
-  use super::{Index, NodeID, ReadID, Term};
+  use super::{ClusterID, Index, NodeID, ReadID, Term};
 
   include!("../capnp/runtime/src/samples/rast_capnp.rs");
 
   impl<'a> fmt::Display for EntryRef<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      if let Ok(EntryKind::Noop) = self.kind() {
+        return write!(f, "({:}.{:} noop)", self.term().0, self.index().0);
+      }
       match std::str::from_utf8(&self.payload().expect("WIP")) {
         Ok(payload) => write!(f, "({:}.{:} {:?})", self.term().0, self.index().0, payload),
         Err(_) => write!(f, "({:}.{:} {:?})", self.term().0, self.index().0, self.payload()),
@@ -119,6 +169,15 @@ mod generated {
     }
   }
 
+  impl fmt::Debug for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+        EntryKind::User => write!(f, "user"),
+        EntryKind::Noop => write!(f, "noop"),
+      }
+    }
+  }
+
   impl fmt::Debug for EntryShared {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
       self.capnp_as_ref().fmt(f)
@@ -0,0 +1,709 @@
+// Copyright 2020 Daniel Harrison. All Rights Reserved.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+use crate::prelude::*;
+use crate::runtime::{LogStore, MemLog};
+
+/// Tunables for a [`Sim`].
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+  /// The seed for every random decision made by the simulation.
+  ///
+  /// Two simulations with the same config that are driven by the same sequence
+  /// of calls behave identically.
+  pub seed: u64,
+  /// The number of nodes in the simulated Raft group.
+  pub nodes: u64,
+  /// The Raft tunables used by every node.
+  pub raft: Config,
+  /// The range (min inclusive, max exclusive) from which the latency of each
+  /// message between two nodes is picked.
+  pub message_latency: (Duration, Duration),
+  /// The range (min inclusive, max exclusive) from which the latency of each
+  /// `PersistReq`, `ApplyReq`, and `ReadStateMachineReq` is picked.
+  pub disk_latency: (Duration, Duration),
+  /// The range (min inclusive, max exclusive) from which the time between two
+  /// ticks of a node is picked.
+  pub tick_interval: (Duration, Duration),
+  /// Network faults to inject, each at the given virtual time.
+  ///
+  /// More can be scheduled after the simulation starts with
+  /// [`Sim::schedule_fault`].
+  pub faults: Vec<(Duration, Fault)>,
+}
+
+impl Default for SimConfig {
+  fn default() -> SimConfig {
+    SimConfig {
+      seed: 0,
+      nodes: 3,
+      raft: Config::default(),
+      message_latency: (Duration::from_micros(100), Duration::from_millis(2)),
+      disk_latency: (Duration::from_micros(10), Duration::from_millis(1)),
+      tick_interval: (Duration::from_millis(1), Duration::from_millis(5)),
+      faults: vec![],
+    }
+  }
+}
+
+/// A network fault injected into a [`Sim`].
+///
+/// Faults accumulate until they're removed by [`Fault::Heal`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+  /// Every message between a node on one side and a node on the other, in
+  /// either direction, is dropped.
+  Partition(Vec<NodeID>, Vec<NodeID>),
+  /// Every message from a node on the first side to a node on the second is
+  /// dropped. Messages in the other direction are still delivered.
+  OneWayPartition(Vec<NodeID>, Vec<NodeID>),
+  /// The link from `src` to `dest` misbehaves as described by `faults`.
+  Link {
+    /// The sender of the affected messages.
+    src: NodeID,
+    /// The receiver of the affected messages.
+    dest: NodeID,
+    /// How the link misbehaves.
+    faults: LinkFaults,
+  },
+  /// Every link without its own [`Fault::Link`] misbehaves as described.
+  AllLinks(LinkFaults),
+  /// Every partition and link fault is removed.
+  Heal,
+}
+
+/// How a network link between two simulated nodes misbehaves.
+///
+/// The default is a well-behaved link.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkFaults {
+  /// The probability (between 0 and 1) that a message is dropped.
+  pub drop: f64,
+  /// The probability (between 0 and 1) that a message is delivered twice.
+  pub duplicate: f64,
+  /// The window within which messages are reordered.
+  ///
+  /// Each message is delayed by an extra random duration less than this and
+  /// is delivered without regard for the order it was sent in. Zero keeps
+  /// delivery in order.
+  pub reorder: Duration,
+}
+
+/// A record of one input stepped into a simulated node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+  /// The virtual time at which the input was stepped.
+  pub time: Duration,
+  /// The node the input was stepped into.
+  pub node: NodeID,
+  /// A human readable description of the input.
+  pub input: String,
+}
+
+#[derive(Debug)]
+enum SimEvent {
+  Tick(NodeID),
+  Message(MessageShared),
+  Disk(NodeID, Output),
+  Fault(Fault),
+}
+
+struct SimNode {
+  raft: Raft,
+  log: MemLog,
+  state: Vec<u8>,
+  // Disk operations are processed one at a time and in the order they were
+  // output. This is when the last one that was scheduled finishes.
+  disk_idle: Duration,
+}
+
+/// A seeded, discrete-event simulation of a Raft group.
+///
+/// Every node, the network between them, and their disks are driven by a
+/// single queue of events on a virtual clock. Message latencies, disk
+/// latencies, and the interval between ticks are all picked by a random number
+/// generator seeded from [`SimConfig::seed`], so a failing run can be replayed
+/// exactly by rerunning it with the same seed.
+///
+/// Messages between any two nodes are delivered in the order they were sent
+/// and the disk outputs of each node are processed in the order they were
+/// emitted, as required by [`Output`]. The network can be made to partition,
+/// drop, duplicate, and reorder messages by injecting a [`Fault`]. These
+/// decisions are made by the same seeded random number generator, so they are
+/// replayed exactly too.
+pub struct Sim {
+  cfg: SimConfig,
+  rng: SmallRng,
+  epoch: Instant,
+  now: Duration,
+  next_seq: u64,
+  events: BTreeMap<(Duration, u64), SimEvent>,
+  nodes: BTreeMap<NodeID, SimNode>,
+  // The delivery time of the last message sent on each (src, dest) link.
+  links: HashMap<(NodeID, NodeID), Duration>,
+  // The (src, dest) links that currently drop every message.
+  partitioned: HashSet<(NodeID, NodeID)>,
+  link_faults: HashMap<(NodeID, NodeID), LinkFaults>,
+  all_link_faults: LinkFaults,
+  trace: Vec<TraceEntry>,
+}
+
+impl Sim {
+  /// Returns a new simulation of a group of `cfg.nodes` Raft nodes with empty
+  /// logs.
+  pub fn new(cfg: SimConfig) -> Sim {
+    let ids: Vec<_> = (0..cfg.nodes).map(NodeID).collect();
+    let nodes = ids
+      .iter()
+      .map(|id| {
+        let node = SimNode {
+          raft: Raft::new(*id, ids.clone(), cfg.raft.clone()),
+          log: MemLog::new(),
+          state: vec![],
+          disk_idle: Duration::from_nanos(0),
+        };
+        (*id, node)
+      })
+      .collect();
+    let mut sim = Sim {
+      rng: SmallRng::seed_from_u64(cfg.seed),
+      cfg,
+      epoch: Instant::now(),
+      now: Duration::from_nanos(0),
+      next_seq: 0,
+      events: BTreeMap::new(),
+      nodes,
+      links: HashMap::new(),
+      partitioned: HashSet::new(),
+      link_faults: HashMap::new(),
+      all_link_faults: LinkFaults::default(),
+      trace: vec![],
+    };
+    // Raft doesn't start its election timer until the first tick, so give every
+    // node one at the very beginning.
+    for id in ids {
+      sim.schedule(Duration::from_nanos(0), SimEvent::Tick(id));
+    }
+    for (time, fault) in sim.cfg.faults.clone() {
+      sim.schedule_fault(time, fault);
+    }
+    sim
+  }
+
+  /// Returns the config this simulation was created with.
+  pub fn cfg(&self) -> &SimConfig {
+    &self.cfg
+  }
+
+  /// Returns the virtual time elapsed since the simulation started.
+  pub fn now(&self) -> Duration {
+    self.now
+  }
+
+  /// Returns the ids of every simulated node.
+  pub fn nodes(&self) -> Vec<NodeID> {
+    self.nodes.keys().copied().collect()
+  }
+
+  /// Returns the Raft node with the given id.
+  ///
+  /// Panics if there is no such node.
+  pub fn raft(&self, id: NodeID) -> &Raft {
+    &self.node(id).raft
+  }
+
+  /// Returns a mutable reference to the Raft node with the given id.
+  ///
+  /// This is intended for installing metrics and event hooks. Stepping the node
+  /// directly bypasses the simulation and is not recorded in the trace.
+  ///
+  /// Panics if there is no such node.
+  pub fn raft_mut(&mut self, id: NodeID) -> &mut Raft {
+    &mut self.node_mut(id).raft
+  }
+
+  /// Returns the persisted log of the node with the given id.
+  ///
+  /// Panics if there is no such node.
+  pub fn log(&self, id: NodeID) -> &MemLog {
+    &self.node(id).log
+  }
+
+  /// Returns the state machine of the node with the given id. The simulated
+  /// state machine is the concatenation of every applied user payload.
+  ///
+  /// Panics if there is no such node.
+  pub fn state(&self, id: NodeID) -> &[u8] {
+    &self.node(id).state
+  }
+
+  /// Returns the node that most recently became leader, if any node currently
+  /// believes it is leader.
+  pub fn leader(&self) -> Option<NodeID> {
+    self
+      .nodes
+      .values()
+      .map(|node| node.raft.status())
+      .filter(|status| status.role == Role::Leader)
+      .max_by_key(|status| status.current_term)
+      .map(|status| status.id)
+  }
+
+  /// Returns every input stepped into every node so far, in order.
+  pub fn trace(&self) -> &[TraceEntry] {
+    &self.trace
+  }
+
+  /// Submits a user write to the given node at the current virtual time.
+  pub fn write(&mut self, id: NodeID, req: WriteReq) -> WriteFuture {
+    let res = WriteFuture::new();
+    self.step_node(id, Input::Write(req, res.clone()));
+    res
+  }
+
+  /// Submits a user read to the given node at the current virtual time.
+  pub fn read(&mut self, id: NodeID, req: ReadReq) -> ReadFuture {
+    let res = ReadFuture::new();
+    self.step_node(id, Input::Read(req, res.clone()));
+    res
+  }
+
+  /// Injects the given network fault at the current virtual time.
+  pub fn fault(&mut self, fault: Fault) {
+    debug!("fault {:?}", fault);
+    match fault {
+      Fault::Partition(left, right) => {
+        for l in left.iter() {
+          for r in right.iter() {
+            self.partitioned.insert((*l, *r));
+            self.partitioned.insert((*r, *l));
+          }
+        }
+      }
+      Fault::OneWayPartition(from, to) => {
+        for f in from.iter() {
+          for t in to.iter() {
+            self.partitioned.insert((*f, *t));
+          }
+        }
+      }
+      Fault::Link { src, dest, faults } => {
+        self.link_faults.insert((src, dest), faults);
+      }
+      Fault::AllLinks(faults) => self.all_link_faults = faults,
+      Fault::Heal => {
+        self.partitioned.clear();
+        self.link_faults.clear();
+        self.all_link_faults = LinkFaults::default();
+      }
+    }
+  }
+
+  /// Schedules the given network fault to be injected at the given virtual
+  /// time. A time in the past injects it as soon as the next event is
+  /// processed.
+  pub fn schedule_fault(&mut self, time: Duration, fault: Fault) {
+    let time = std::cmp::max(time, self.now);
+    self.schedule(time, SimEvent::Fault(fault));
+  }
+
+  /// Processes the next event, advancing virtual time to when it happens.
+  pub fn step(&mut self) {
+    // There is always at least one tick scheduled for every node, so the queue
+    // is never empty.
+    let key = *self.events.keys().next().expect("unreachable");
+    let event = self.events.remove(&key).expect("unreachable");
+    self.now = key.0;
+    match event {
+      SimEvent::Tick(id) => {
+        let now = self.epoch + self.now;
+        self.step_node(id, Input::Tick(now));
+        self.schedule_tick(id);
+      }
+      SimEvent::Message(msg) => {
+        let (src, dest) = (msg.capnp_as_ref().src(), msg.capnp_as_ref().dest());
+        // NB: A partition also drops any messages that were in flight when it
+        // started.
+        if self.partitioned.contains(&(src, dest)) {
+          return;
+        }
+        self.step_node(dest, Input::Message(msg.capnp_as_ref()));
+      }
+      SimEvent::Disk(id, output) => self.finish_disk(id, output),
+      SimEvent::Fault(fault) => self.fault(fault),
+    }
+  }
+
+  /// Processes every event scheduled in the next `duration` of virtual time.
+  pub fn run_for(&mut self, duration: Duration) {
+    let end = self.now + duration;
+    while self.events.keys().next().map_or(false, |(time, _)| *time <= end) {
+      self.step();
+    }
+    self.now = end;
+  }
+
+  /// Processes events until `cond` returns true or `timeout` of virtual time
+  /// has passed, whichever comes first. Returns whether `cond` returned true.
+  pub fn run_until(&mut self, timeout: Duration, mut cond: impl FnMut(&Sim) -> bool) -> bool {
+    let end = self.now + timeout;
+    loop {
+      if cond(self) {
+        return true;
+      }
+      if self.events.keys().next().map_or(true, |(time, _)| *time > end) {
+        self.now = end;
+        return false;
+      }
+      self.step();
+    }
+  }
+
+  /// Processes events until the given future resolves or `timeout` of virtual
+  /// time has passed, whichever comes first. Returns the result of the future
+  /// or None if it timed out.
+  pub fn run_until_ready<F: Future + Unpin>(
+    &mut self,
+    timeout: Duration,
+    future: &mut F,
+  ) -> Option<F::Output> {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut context = Context::from_waker(&waker);
+    let mut result = None;
+    self.run_until(timeout, |_| match Pin::new(&mut *future).poll(&mut context) {
+      Poll::Ready(res) => {
+        result = Some(res);
+        true
+      }
+      Poll::Pending => false,
+    });
+    result
+  }
+
+  fn node(&self, id: NodeID) -> &SimNode {
+    self.nodes.get(&id).unwrap_or_else(|| panic!("unknown node: {:?}", id))
+  }
+
+  fn node_mut(&mut self, id: NodeID) -> &mut SimNode {
+    self.nodes.get_mut(&id).unwrap_or_else(|| panic!("unknown node: {:?}", id))
+  }
+
+  fn schedule(&mut self, time: Duration, event: SimEvent) {
+    let seq = self.next_seq;
+    self.next_seq += 1;
+    self.events.insert((time, seq), event);
+  }
+
+  fn schedule_tick(&mut self, id: NodeID) {
+    let interval = random_duration(&mut self.rng, self.cfg.tick_interval);
+    self.schedule(self.now + interval, SimEvent::Tick(id));
+  }
+
+  fn step_node(&mut self, id: NodeID, input: Input) {
+    self.trace.push(TraceEntry { time: self.now, node: id, input: describe(&input) });
+    let mut output = vec![];
+    self.node_mut(id).raft.step(&mut output, input).expect("message from a correct peer");
+    output.into_iter().for_each(|output| self.start_output(id, output));
+  }
+
+  fn start_output(&mut self, id: NodeID, output: Output) {
+    match output {
+      Output::Message(msg) => self.send(id, msg),
+      output => {
+        let latency = random_duration(&mut self.rng, self.cfg.disk_latency);
+        let now = self.now;
+        let node = self.node_mut(id);
+        let time = std::cmp::max(now, node.disk_idle) + latency;
+        node.disk_idle = time;
+        self.schedule(time, SimEvent::Disk(id, output));
+      }
+    }
+  }
+
+  fn send(&mut self, src: NodeID, msg: MessageShared) {
+    let dest = msg.capnp_as_ref().dest();
+    if self.partitioned.contains(&(src, dest)) {
+      return;
+    }
+    let faults = self.link_faults.get(&(src, dest)).unwrap_or(&self.all_link_faults).clone();
+    // NB: The random number generator is only consulted for the faults that
+    // are enabled, so injecting none doesn't change the rest of the run.
+    if faults.drop > 0.0 && self.rng.gen_bool(faults.drop) {
+      return;
+    }
+    let copies = if faults.duplicate > 0.0 && self.rng.gen_bool(faults.duplicate) { 2 } else { 1 };
+    for _ in 0..copies {
+      let latency = random_duration(&mut self.rng, self.cfg.message_latency);
+      let time = if faults.reorder > Duration::from_nanos(0) {
+        let jitter = random_duration(&mut self.rng, (Duration::from_nanos(0), faults.reorder));
+        self.now + latency + jitter
+      } else {
+        // Keep delivery on each link in order by never delivering a message
+        // before one that was sent earlier.
+        let last = self.links.get(&(src, dest)).copied().unwrap_or_default();
+        let time = std::cmp::max(self.now + latency, last);
+        self.links.insert((src, dest), time);
+        time
+      };
+      self.schedule(time, SimEvent::Message(msg.clone()));
+    }
+  }
+
+  fn finish_disk(&mut self, id: NodeID, output: Output) {
+    let node = self.node_mut(id);
+    match output {
+      Output::PersistReq(req) => {
+        node.log.append(&req.entries).expect("WIP");
+        let res = PersistRes {
+          leader_id: req.leader_id,
+          read_id: req.read_id,
+          log_index: req.entries.last().map_or(Index(0), |entry| entry.capnp_as_ref().index()),
+          generation: req.generation,
+          error: None,
+        };
+        self.step_node(id, Input::PersistRes(res));
+      }
+      Output::ApplyReq(index) => {
+        node.log.mark_stable(index).expect("WIP");
+        node.state = node.log.user_payloads(Index(0), index).expect("WIP").concat();
+      }
+      Output::ReadStateMachineReq(req) => {
+        let reads = req.reads.into_iter().map(|(read_id, _)| (read_id, node.state.clone()));
+        let res = ReadStateMachineRes { index: req.index, reads: reads.collect() };
+        self.step_node(id, Input::ReadStateMachineRes(res));
+      }
+      Output::ReadLogReq(req) => {
+        let entries = node.log.read(req.start, req.end).expect("WIP");
+        let res = ReadLogRes { peer: req.peer, term: req.term, entries };
+        self.step_node(id, Input::ReadLogRes(res));
+      }
+      Output::Message(_) => unreachable!(),
+    }
+  }
+}
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+  fn wake(self: Arc<Self>) {}
+}
+
+fn random_duration(rng: &mut impl Rng, (min, max): (Duration, Duration)) -> Duration {
+  if max <= min {
+    return min;
+  }
+  Duration::from_nanos(rng.gen_range(min.as_nanos() as u64, max.as_nanos() as u64))
+}
+
+fn describe(input: &Input) -> String {
+  // NB: Tick's Instant is different in every run, so it's left out.
+  match input {
+    Input::Write(req, _) => format!("write {:?}", req),
+    Input::Read(req, _) => format!("read {:?}", req),
+    Input::Tick(_) => String::from("tick"),
+    Input::Message(msg) => format!("{}", msg),
+    Input::PersistRes(res) => format!("{:?}", res),
+    Input::ReadStateMachineRes(res) => format!("{:?}", res),
+    Input::ReadLogRes(res) => format!("{:?}", res),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #![allow(clippy::wildcard_imports)]
+  use super::*;
+
+  use crate::testutil;
+
+  fn write_then_read(seed: u64) -> (Sim, Vec<u8>) {
+    let mut sim = Sim::new(SimConfig { seed, ..Default::default() });
+    let timeout = Duration::from_secs(10);
+    assert!(sim.run_until(timeout, |sim| sim.leader().is_some()));
+    let leader = sim.leader().unwrap();
+
+    for payload in ["1", "2", "3"].iter() {
+      let mut res = sim.write(leader, WriteReq { payload: payload.as_bytes().to_vec() });
+      let res = sim.run_until_ready(timeout, &mut res).expect("write timed out");
+      assert!(res.is_ok(), "{:?}", res);
+    }
+    let mut res =
+      sim.read(leader, ReadReq { consistency: ReadConsistency::Linearizable, payload: vec![] });
+    let res = sim.run_until_ready(timeout, &mut res).expect("read timed out");
+    (sim, res.expect("read failed").payload)
+  }
+
+  #[test]
+  fn sim() {
+    testutil::log_init();
+
+    let (mut sim, payload) = write_then_read(0);
+    assert_eq!(payload, b"123".to_vec());
+
+    // Every node eventually applies every write.
+    sim.run_for(Duration::from_secs(1));
+    for id in sim.nodes() {
+      assert_eq!(sim.state(id), b"123", "node {:?}", id);
+    }
+  }
+
+  #[test]
+  fn sim_deterministic() {
+    testutil::log_init();
+
+    for seed in 0..10 {
+      let (sim1, payload1) = write_then_read(seed);
+      let (sim2, payload2) = write_then_read(seed);
+      assert_eq!(payload1, payload2);
+      assert_eq!(sim1.trace(), sim2.trace(), "seed {}", seed);
+    }
+
+    let (sim1, _) = write_then_read(1);
+    let (sim2, _) = write_then_read(2);
+    assert_ne!(sim1.trace(), sim2.trace());
+  }
+
+  fn role(sim: &Sim, id: NodeID) -> Role {
+    sim.raft(id).status().role
+  }
+
+  // Writes the given payloads one at a time, retrying each against whichever
+  // node is leader until it succeeds.
+  fn write_all(sim: &mut Sim, payloads: &[&str]) {
+    let timeout = Duration::from_secs(10);
+    for payload in payloads.iter() {
+      let success = sim.run_until(timeout, |sim| sim.leader().is_some())
+        && (0..100).any(|_| {
+          let leader = match sim.leader() {
+            Some(leader) => leader,
+            None => return false,
+          };
+          let mut res = sim.write(leader, WriteReq { payload: payload.as_bytes().to_vec() });
+          let res = sim.run_until_ready(Duration::from_secs(1), &mut res);
+          res.map_or(false, |res| res.is_ok())
+        });
+      assert!(success, "write {:?} never succeeded", payload);
+    }
+  }
+
+  // Runs until every node has applied everything committed so far and returns
+  // the resulting state.
+  fn converge(sim: &mut Sim) -> Vec<u8> {
+    let committed = sim.nodes().iter().map(|id| sim.raft(*id).status().commit_index).max();
+    let committed = committed.expect("unreachable");
+    let applied = |sim: &Sim, id: NodeID| -> Option<Vec<u8>> {
+      let log = sim.log(id);
+      if log.stable().map_or(true, |stable| stable < committed) {
+        return None;
+      }
+      Some(log.user_payloads(Index(0), committed).expect("WIP").concat())
+    };
+    let converged = sim.run_until(Duration::from_secs(10), |sim| {
+      sim.nodes().iter().all(|id| applied(sim, *id).is_some())
+    });
+    assert!(converged, "states never converged");
+    let expected = applied(sim, NodeID(0)).expect("unreachable");
+    for id in sim.nodes() {
+      assert_eq!(applied(sim, id), Some(expected.clone()), "node {:?}", id);
+    }
+    expected
+  }
+
+  #[test]
+  fn sim_partition() {
+    testutil::log_init();
+
+    let mut sim = Sim::new(SimConfig::default());
+    let timeout = Duration::from_secs(10);
+    write_all(&mut sim, &["1"]);
+    let old_leader = sim.leader().unwrap();
+    let old_term = sim.raft(old_leader).status().current_term;
+
+    // Isolate the leader, the rest of the group elects a new one.
+    let rest: Vec<_> = sim.nodes().into_iter().filter(|id| *id != old_leader).collect();
+    sim.fault(Fault::Partition(vec![old_leader], rest));
+    assert!(sim.run_until(timeout, |sim| sim.leader() != Some(old_leader)));
+    assert!(sim.raft(sim.leader().unwrap()).status().current_term > old_term);
+    // The isolated leader doesn't know it's been replaced and can't commit.
+    assert_eq!(role(&sim, old_leader), Role::Leader);
+    let mut res = sim.write(old_leader, WriteReq { payload: b"x".to_vec() });
+    write_all(&mut sim, &["2"]);
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(sim.run_until_ready(Duration::from_nanos(0), &mut res), None);
+
+    // Once healed, the old leader steps down and catches up. Its uncommitted
+    // write is discarded.
+    sim.fault(Fault::Heal);
+    assert!(sim.run_until(timeout, |sim| role(sim, old_leader) == Role::Follower));
+    assert!(sim.run_until_ready(Duration::from_nanos(0), &mut res).unwrap().is_err());
+    write_all(&mut sim, &["3"]);
+    assert_eq!(converge(&mut sim), b"123".to_vec());
+  }
+
+  #[test]
+  fn sim_one_way_partition() {
+    testutil::log_init();
+
+    let mut sim = Sim::new(SimConfig::default());
+    let timeout = Duration::from_secs(10);
+    write_all(&mut sim, &["1"]);
+    let old_leader = sim.leader().unwrap();
+
+    // The leader can hear the rest of the group but not reach it, so the rest
+    // of the group elects a new leader and the old one steps down when it hears
+    // about the new term.
+    let rest: Vec<_> = sim.nodes().into_iter().filter(|id| *id != old_leader).collect();
+    sim.fault(Fault::OneWayPartition(vec![old_leader], rest));
+    assert!(sim.run_until(timeout, |sim| role(sim, old_leader) != Role::Leader));
+    assert!(sim.run_until(timeout, |sim| sim.leader().is_some()));
+    assert_ne!(sim.leader(), Some(old_leader));
+    write_all(&mut sim, &["2"]);
+
+    sim.fault(Fault::Heal);
+    write_all(&mut sim, &["3"]);
+    assert_eq!(converge(&mut sim), b"123".to_vec());
+  }
+
+  fn lossy(seed: u64) -> Sim {
+    let faults = LinkFaults { drop: 0.1, duplicate: 0.1, reorder: Duration::from_millis(5) };
+    let cfg = SimConfig {
+      seed,
+      faults: vec![(Duration::from_nanos(0), Fault::AllLinks(faults))],
+      ..Default::default()
+    };
+    let mut sim = Sim::new(cfg);
+    write_all(&mut sim, &["1", "2", "3", "4", "5"]);
+    sim
+  }
+
+  #[test]
+  fn sim_lossy() {
+    testutil::log_init();
+
+    for seed in 0..10 {
+      let mut sim = lossy(seed);
+      // A write that failed may still have been committed and retried, so only
+      // check that every write was applied, in order, on every node.
+      let state = converge(&mut sim);
+      let mut expected = b"12345".iter().peekable();
+      state.iter().for_each(|b| {
+        if expected.peek() == Some(&b) {
+          expected.next();
+        }
+      });
+      assert_eq!(expected.next(), None, "seed {} state {:?}", seed, state);
+    }
+
+    // Faults are picked by the seeded random number generator, so they replay
+    // exactly too.
+    assert_eq!(lossy(7).trace(), lossy(7).trace());
+  }
+}
@@ -3,26 +3,50 @@
 use std::collections::HashMap;
 
 use crate::prelude::*;
-use crate::runtime::{MemLog, MemRPC, RastClient, Runtime};
+use crate::runtime::{LogStore, MemLog, MemRPC, RastClient, Runtime};
 
 pub struct ConcurrentNode {
   runtime: Runtime,
-  rpc: MemRPC,
+  nodes: Vec<NodeID>,
+  cfg: Config,
+  crashed: Option<MemLog>,
 }
 
 impl ConcurrentNode {
-  fn new(id: NodeID, nodes: Vec<NodeID>) -> ConcurrentNode {
+  fn new(id: NodeID, nodes: Vec<NodeID>, rpc: MemRPC) -> ConcurrentNode {
     let name = format!("runtime-{:?}", id);
     let cfg = Config::default();
-    let raft = Raft::new(id, nodes, cfg);
-    let rpc = MemRPC::new();
-    let runtime = Runtime::new(name, raft, rpc.clone(), MemLog::new());
-    ConcurrentNode { runtime: runtime, rpc: rpc }
+    let raft = Raft::new(id, nodes.clone(), cfg.clone());
+    let runtime = Runtime::new(name, raft, rpc, MemLog::new());
+    ConcurrentNode { runtime, nodes, cfg, crashed: None }
   }
 
   pub fn client(&self) -> RastClient {
     self.runtime.client()
   }
+
+  // Crashes this node, losing everything that wasn't acknowledged as durable.
+  pub fn crash(&mut self) {
+    self.crashed = Some(self.runtime.crash());
+  }
+
+  // Restarts a crashed node from what it had durably persisted.
+  pub fn restart(&mut self) {
+    let log = self.crashed.take().expect("node is not crashed");
+    let entries = match (log.first_index(), log.last_index()) {
+      (Some(first), Some(last)) => log.read(first, last).expect("WIP"),
+      _ => vec![],
+    };
+    let entries: Vec<_> = entries.iter().map(|entry| entry.capnp_as_ref()).collect();
+    let raft = Raft::restart(
+      self.runtime.id,
+      self.nodes.clone(),
+      self.cfg.clone(),
+      log.hard_state(),
+      &entries,
+    );
+    self.runtime.restart(raft, log).expect("WIP");
+  }
 }
 
 pub struct ConcurrentGroup {
@@ -33,17 +57,13 @@ pub struct ConcurrentGroup {
 impl ConcurrentGroup {
   pub fn new(nodes: u64) -> ConcurrentGroup {
     let node_ids: Vec<_> = (0..nodes).map(|node| NodeID(node)).collect();
-    let mut nodes: HashMap<_, _> = node_ids
+    // Every node listens on the same rpc system, so they can all reach each
+    // other.
+    let rpc = MemRPC::new();
+    let nodes: HashMap<_, _> = node_ids
       .iter()
-      .map(|node_id| (*node_id, ConcurrentNode::new(*node_id, node_ids.clone())))
+      .map(|node_id| (*node_id, ConcurrentNode::new(*node_id, node_ids.clone(), rpc.clone())))
       .collect();
-    let senders: Vec<_> =
-      nodes.iter().map(|(node_id, node)| (*node_id, node.runtime.sender())).collect();
-    for (_, node) in nodes.iter_mut() {
-      for (dest_id, dest_sender) in senders.iter() {
-        node.rpc.register(*dest_id, dest_sender.clone());
-      }
-    }
     ConcurrentGroup { nodes: nodes }
   }
 }
@@ -1,28 +1,35 @@
 // Copyright 2020 Daniel Harrison. All Rights Reserved.
 
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::time::{Duration, Instant};
 
+use crate::fuzz::{dump, Invariants, NodeState};
 use crate::prelude::*;
-use crate::runtime::MemLog;
+use crate::runtime::{LogStore, MemLog};
 
-pub struct DeterministicNode {
+pub struct DeterministicNode<L: LogStore + 'static = MemLog> {
   pub raft: Raft,
   pub now: Instant,
   pub input: Vec<OwnedInput>,
   pub output: Vec<Output>,
-  pub log: MemLog,
+  pub log: L,
   pub state: Vec<u8>,
 }
 
-impl DeterministicNode {
-  fn new(id: NodeID, nodes: Vec<NodeID>, cfg: Config, now: Instant) -> DeterministicNode {
+impl<L: LogStore + 'static> DeterministicNode<L> {
+  fn new(
+    id: NodeID,
+    nodes: Vec<NodeID>,
+    cfg: Config,
+    now: Instant,
+    log: L,
+  ) -> DeterministicNode<L> {
     DeterministicNode {
       raft: Raft::new(id, nodes, cfg),
       now: now,
       input: vec![],
       output: vec![],
-      log: MemLog::new(),
+      log,
       state: vec![],
     }
   }
@@ -44,6 +51,34 @@ impl DeterministicNode {
     self.output.extend(output);
   }
 
+  pub fn state(&self) -> NodeState<'_> {
+    NodeState { status: self.raft.status(), log: Some(&self.log) }
+  }
+
+  // Drops every message output by this node that hasn't been delivered yet, as
+  // if the network lost them. Disk outputs are kept.
+  pub fn drop_messages(&mut self) {
+    self.output.retain(|output| !matches!(output, Output::Message(_)));
+  }
+
+  // Fails the oldest PersistReq output by this node that hasn't been handled
+  // yet with `error`, instead of writing it to the log.
+  pub fn fail_persist(&mut self, error: PersistError) {
+    let idx = self.output.iter().position(|output| matches!(output, Output::PersistReq(_)));
+    let req = match self.output.remove(idx.expect("no PersistReq to fail")) {
+      Output::PersistReq(req) => req,
+      _ => unreachable!(),
+    };
+    let msg = PersistRes {
+      leader_id: req.leader_id,
+      read_id: req.read_id,
+      log_index: req.entries.last().map_or(Index(0), |entry| entry.capnp_as_ref().index()),
+      generation: req.generation,
+      error: Some(error),
+    };
+    self.step(Input::PersistRes(msg));
+  }
+
   pub fn tick(&mut self, inc: Duration) {
     self.now += inc;
     self.step(Input::Tick(self.now));
@@ -55,7 +90,7 @@ impl DeterministicNode {
 
     #[cfg(feature = "log")]
     debug!("w   {:?}: {:?}", self.raft.id().0, req);
-    self.raft.step(&mut output, Input::Write(req, res.clone()));
+    self.raft.step(&mut output, Input::Write(req, res.clone())).expect("unreachable");
     #[cfg(feature = "log")]
     {
       output.iter().for_each(|output| {
@@ -74,7 +109,7 @@ impl DeterministicNode {
 
     #[cfg(feature = "log")]
     debug!("r   {:?}: {:?}", self.raft.id().0, req);
-    self.raft.step(&mut output, Input::Read(req, res.clone()));
+    self.raft.step(&mut output, Input::Read(req, res.clone())).expect("unreachable");
     #[cfg(feature = "log")]
     {
       output.iter().for_each(|output| {
@@ -92,7 +127,7 @@ impl DeterministicNode {
 
     #[cfg(feature = "log")]
     debug!("in  {:?}: {:?}", self.raft.id().0, input);
-    self.raft.step(&mut output, input);
+    self.raft.step(&mut output, input).expect("message from a correct peer");
     #[cfg(feature = "log")]
     {
       output.iter().for_each(|output| {
@@ -114,7 +149,7 @@ impl DeterministicNode {
       let mut output = vec![];
       #[cfg(feature = "log")]
       debug!("in  {:?}: {:?}", id.0, input);
-      self.raft.step(&mut output, input.as_ref());
+      self.raft.step(&mut output, input.as_ref()).expect("message from a correct peer");
       #[cfg(feature = "log")]
       {
         output.iter().for_each(|output| {
@@ -128,109 +163,153 @@ impl DeterministicNode {
   }
 }
 
-pub struct DeterministicGroup1 {
+pub struct DeterministicGroup<L: LogStore + 'static = MemLog> {
   cfg: Config,
-  pub n: DeterministicNode,
+  invariants: Invariants,
+  // How fast each node's clock runs relative to the group's, see set_skew.
+  rates: Vec<f64>,
+  isolated: HashSet<NodeID>,
+  // Node i has NodeID(i).
+  pub nodes: Vec<DeterministicNode<L>>,
 }
 
-impl DeterministicGroup1 {
-  pub fn new() -> DeterministicGroup1 {
+impl DeterministicGroup<MemLog> {
+  pub fn new(n: u64, cfg: Config) -> DeterministicGroup<MemLog> {
+    DeterministicGroup::with_logs(n, cfg, |_| MemLog::new())
+  }
+}
+
+impl<L: LogStore + 'static> DeterministicGroup<L> {
+  // Like new, but each node's log is whatever `log` returns for its id.
+  pub fn with_logs<F>(n: u64, cfg: Config, mut log: F) -> DeterministicGroup<L>
+  where
+    F: FnMut(NodeID) -> L,
+  {
     let now = Instant::now();
-    let cfg: Config = Default::default();
-    DeterministicGroup1 {
-      n: DeterministicNode::new(NodeID(0), vec![NodeID(0)], cfg.clone(), now),
-      cfg: cfg,
+    let ids: Vec<NodeID> = (0..n).map(NodeID).collect();
+    let nodes =
+      ids.iter().map(|id| DeterministicNode::new(*id, ids.clone(), cfg.clone(), now, log(*id)));
+    DeterministicGroup {
+      nodes: nodes.collect(),
+      rates: vec![1.0; n as usize],
+      isolated: HashSet::new(),
+      invariants: Invariants::new(),
+      cfg,
     }
   }
-}
 
-impl DeterministicGroup for DeterministicGroup1 {
-  fn cfg(&self) -> &Config {
+  pub fn cfg(&self) -> &Config {
     &self.cfg
   }
-  fn nodes(&self) -> Vec<&DeterministicNode> {
-    vec![&self.n]
+
+  // Makes the given node's clock run at `rate` times the speed of the rest of
+  // the group's when ticked by tick or tick_node. A rate of 1.0 is in sync.
+  pub fn set_skew(&mut self, id: NodeID, rate: f64) {
+    self.rates[id.0 as usize] = rate;
   }
-  fn nodes_mut(&mut self) -> Vec<&mut DeterministicNode> {
-    vec![&mut self.n]
+
+  // Drops every message to or from the given node until it's healed.
+  pub fn isolate(&mut self, id: NodeID) {
+    self.isolated.insert(id);
   }
-}
 
-pub struct DeterministicGroup3 {
-  cfg: Config,
-  pub n0: DeterministicNode,
-  pub n1: DeterministicNode,
-  pub n2: DeterministicNode,
-}
+  pub fn heal(&mut self, id: NodeID) {
+    self.isolated.remove(&id);
+  }
 
-impl DeterministicGroup3 {
-  pub fn new() -> DeterministicGroup3 {
-    let now = Instant::now();
-    let cfg: Config = Default::default();
-    let nodes = vec![NodeID(0), NodeID(1), NodeID(2)];
-    DeterministicGroup3 {
-      n0: DeterministicNode::new(NodeID(0), nodes.clone(), cfg.clone(), now),
-      n1: DeterministicNode::new(NodeID(1), nodes.clone(), cfg.clone(), now),
-      n2: DeterministicNode::new(NodeID(2), nodes, cfg.clone(), now),
-      cfg: cfg,
+  pub fn tick(&mut self, inc: Duration) {
+    for (node, rate) in self.nodes.iter_mut().zip(self.rates.iter()) {
+      node.tick(inc.mul_f64(*rate));
     }
   }
-}
 
-impl DeterministicGroup for DeterministicGroup3 {
-  fn cfg(&self) -> &Config {
-    &self.cfg
+  pub fn tick_node(&mut self, id: NodeID, inc: Duration) {
+    let rate = self.rates[id.0 as usize];
+    self.nodes[id.0 as usize].tick(inc.mul_f64(rate));
   }
-  fn nodes(&self) -> Vec<&DeterministicNode> {
-    vec![&self.n0, &self.n1, &self.n2]
+
+  // Handles the given node's queued inputs and then its outputs. Any messages
+  // it sends are queued on the destination but not handled. Returns whether
+  // there was anything to do.
+  pub fn step(&mut self, id: NodeID) -> bool {
+    let did_work = self.nodes[id.0 as usize].drain_inputs();
+    let did_work = drain_outputs(&mut self.nodes, &self.isolated, Some(id)) || did_work;
+    self.check_invariants();
+    did_work
   }
-  fn nodes_mut(&mut self) -> Vec<&mut DeterministicNode> {
-    vec![&mut self.n0, &mut self.n1, &mut self.n2]
+
+  // Delivers messages and handles disk outputs until every node is idle. The
+  // safety invariants are checked after each round of delivery.
+  pub fn drain(&mut self) {
+    loop {
+      drain_outputs(&mut self.nodes, &self.isolated, None);
+      self.check_invariants();
+      let mut did_work = false;
+      for node in self.nodes.iter_mut() {
+        did_work = node.drain_inputs() || did_work;
+      }
+      if !did_work {
+        return;
+      }
+    }
   }
-}
 
-fn drain_inputs(nodes: &mut HashMap<NodeID, &mut DeterministicNode>) -> bool {
-  let mut did_work = false;
-  for (_, node) in nodes {
-    did_work = did_work || node.drain_inputs();
+  // The state of every node, for use with Invariants.
+  pub fn states(&self) -> Vec<NodeState<'_>> {
+    self.nodes.iter().map(DeterministicNode::state).collect()
+  }
+
+  fn check_invariants(&mut self) {
+    let states: Vec<_> = self.nodes.iter().map(DeterministicNode::state).collect();
+    if let Err(err) = self.invariants.check(&states) {
+      panic!("{}\n{}", err, dump(&states));
+    }
   }
-  did_work
 }
 
-fn drain_outputs(nodes: &mut HashMap<NodeID, &mut DeterministicNode>) {
+// Handles the outputs of the given node or, if None, every node. Returns
+// whether there were any.
+fn drain_outputs<L: LogStore + 'static>(
+  nodes: &mut [DeterministicNode<L>],
+  isolated: &HashSet<NodeID>,
+  only: Option<NodeID>,
+) -> bool {
+  let mut did_work = false;
   // TODO: do this without the intermediate vector
   let mut rpcs = vec![];
-  for (_, node) in nodes.iter_mut() {
+  for node in nodes.iter_mut() {
+    if only.map_or(false, |only| only != node.raft.id()) {
+      continue;
+    }
     for output in node.output.drain(..) {
+      did_work = true;
       match output {
         Output::PersistReq(req) => {
           // TODO: test this being delayed
-          for entry in req.entries {
+          for entry in req.entries.iter() {
             debug!("APPEND {:?} {:?}", node.raft.id(), &entry.capnp_as_ref());
-            node.log.add(entry.capnp_as_ref());
           }
+          node.log.append(&req.entries).expect("WIP");
           debug!(
-            "STATE {:?} last={:?} stable={:?} {:?}",
+            "STATE {:?} last={:?} stable={:?}",
             node.raft.id(),
-            node.log.highest_index(),
-            node.log.stable,
-            node.log.entries
+            node.log.last_index(),
+            node.log.stable()
           );
           debug!("");
           let msg = PersistRes {
             leader_id: req.leader_id,
             read_id: req.read_id,
-            log_index: node.log.highest_index(),
+            log_index: req.entries.last().map_or(Index(0), |entry| entry.capnp_as_ref().index()),
+            generation: req.generation,
+            error: None,
           };
           node.input.push(Input::PersistRes(msg).into());
         }
         Output::ApplyReq(index) => {
           // TODO: test this being delayed
-          node.log.mark_stable(index);
-          let mut state: Vec<u8> = vec![];
-          for (_, (_, payload)) in node.log.entries.range(..=index) {
-            state.extend(payload.iter());
-          }
+          node.log.mark_stable(index).expect("WIP");
+          let state = node.log.user_payloads(Index(0), index).expect("WIP").concat();
           debug!("APPLY  {:?} {:?}", node.raft.id(), state);
           debug!("");
         }
@@ -238,45 +317,34 @@ fn drain_outputs(nodes: &mut HashMap<NodeID, &mut DeterministicNode>) {
           // TODO: test this being delayed
           debug!("READ   {:?} {:?}", node.raft.id(), &node.state);
           debug!("");
-          let mut state = vec![];
-          if let Some(stable_index) = node.log.stable {
-            for (_, (_, payload)) in node.log.entries.range(..=stable_index) {
-              state.extend(payload.iter());
-            }
-          }
-          let msg = ReadStateMachineRes { index: req.index, read_id: req.read_id, payload: state };
+          let state = match node.log.stable() {
+            Some(stable) => node.log.user_payloads(Index(0), stable).expect("WIP").concat(),
+            None => vec![],
+          };
+          let reads = req.reads.into_iter().map(|(read_id, _)| (read_id, state.clone()));
+          let msg = ReadStateMachineRes { index: req.index, reads: reads.collect() };
           node.input.push(Input::ReadStateMachineRes(msg).into());
         }
+        Output::ReadLogReq(req) => {
+          // TODO: test this being delayed
+          let entries = node.log.read(req.start, req.end).expect("WIP");
+          let msg = ReadLogRes { peer: req.peer, term: req.term, entries };
+          node.input.push(Input::ReadLogRes(msg).into());
+        }
         Output::Message(msg) => rpcs.push(msg),
       }
     }
   }
   for msg in rpcs.drain(..) {
-    let dest = msg.capnp_as_ref().dest();
+    let (src, dest) = (msg.capnp_as_ref().src(), msg.capnp_as_ref().dest());
+    if isolated.contains(&src) || isolated.contains(&dest) {
+      continue;
+    }
     nodes
-      .get_mut(&dest)
+      .get_mut(dest.0 as usize)
       .iter_mut()
       // TODO: get rid of this clone
       .for_each(|dest| dest.input.push(OwnedInput::Message(msg.clone())));
   }
-}
-
-pub trait DeterministicGroup {
-  fn cfg(&self) -> &Config;
-  fn nodes(&self) -> Vec<&DeterministicNode>;
-  fn nodes_mut(&mut self) -> Vec<&mut DeterministicNode>;
-
-  fn tick(&mut self, inc: Duration) {
-    // TODO: ensure this ticks them all to the same time
-    self.nodes_mut().iter_mut().for_each(|node| node.tick(inc));
-  }
-
-  fn drain(&mut self) {
-    let mut nodes: HashMap<NodeID, &mut DeterministicNode> =
-      self.nodes_mut().drain(..).map(|node| (node.raft.id(), node)).collect();
-    drain_outputs(&mut nodes);
-    while drain_inputs(&mut nodes) {
-      drain_outputs(&mut nodes);
-    }
-  }
+  did_work
 }